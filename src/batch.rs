@@ -0,0 +1,323 @@
+use crate::file;
+use crate::import;
+use crate::query::querier;
+use crate::table;
+use crate::table::Purveyor;
+use crate::util::sql;
+
+use querier::QuerierTrait;
+use std::collections::HashMap;
+use std::{fs, path};
+
+// Options for a non-interactive csvql run
+pub struct Options {
+  pub imports: Vec<(String, Option<String>)>,
+  pub queries: Vec<String>,
+  pub script: Option<String>, // sql piped in through STDIN, executed after all query files
+  pub exports: Vec<String>,
+  pub use_json: bool,
+  pub export_options: file::ExportOptions,
+  pub dialect: file::Dialect, // Csv dialect of all imported files
+  pub infer_types: bool,      // Infer the types of unannotated columns of imported files
+  pub reject_rows: bool,      // Set aside rows of imported files that do not fit their table
+  pub null_marker: String,    // What NULL values are displayed as in styled tables
+  pub keep: bool,             // Leave the tables created in the session in the database
+}
+
+// Where the resulting tables of a script are written to
+enum Output<'a> {
+  Styled,        // Decorated tables on STDOUT, as in the repl
+  Plain,         // csv, or json, on STDOUT
+  File(&'a str), // csv or json export file
+}
+
+// Imports all tables, executes every statement of every query file (and of the piped in
+// script) and exports each resulting table. Returns the process exit code, which is non-zero
+// if anything failed.
+pub async fn run(options: Options, db_querier: &dyn QuerierTrait) -> i32 {
+  let mut directives = import::Directives::new(
+    options.dialect.clone(),
+    options.infer_types,
+    options.reject_rows,
+  );
+  let mut result = Ok(());
+  for (import_path, optional_name) in options.imports.iter() {
+    let import_path = path::Path::new(import_path);
+    let optional_name = optional_name.clone();
+    let imported = import::import(
+      db_querier,
+      import_path,
+      optional_name,
+      &options.dialect,
+      options.infer_types,
+      options.reject_rows,
+    )
+    .await;
+    match imported {
+      Ok(_) => (),
+      Err(e) => {
+        result = Err(format!("Failure. {}", e));
+        break;
+      }
+    }
+  }
+
+  if result.is_ok() {
+    let mut context = Context {
+      db_querier,
+      directives: &mut directives,
+      use_json: options.use_json,
+      export_options: &options.export_options,
+      null_marker: &options.null_marker,
+      export_count: 0,
+      exported_headers: HashMap::new(),
+    };
+    for (query_file_index, query_path) in options.queries.iter().enumerate() {
+      let output = match export_path(&options.exports, query_file_index) {
+        Some(export_path) => Output::File(export_path),
+        None => Output::Styled,
+      };
+      result = match fs::read_to_string(query_path) {
+        Ok(script) => {
+          let base_directory = path::Path::new(query_path).parent();
+          execute_script(&mut context, query_path, &script, base_directory, output).await
+        }
+        Err(_) => Err(format!(
+          "Failure. Could not read query file. Path: {}",
+          query_path
+        )),
+      };
+      if result.is_err() {
+        break;
+      }
+    }
+
+    if let (Ok(_), Some(script)) = (&result, &options.script) {
+      let output = match export_path(&options.exports, options.queries.len()) {
+        Some(export_path) => Output::File(export_path),
+        None => Output::Plain,
+      };
+      result = execute_script(&mut context, "STDIN", script, None, output).await;
+    }
+  }
+
+  // Only the tables and views created in the session are dropped
+  if options.keep {
+    eprintln!(
+      "Kept the tables of the session in {}.",
+      db_querier.location()
+    );
+  } else {
+    for name in db_querier.clean().await {
+      eprintln!("Failure. Could not drop {}.", name);
+    }
+  }
+
+  match result {
+    Ok(_) => 0,
+    Err(message) => {
+      eprintln!("{}", message);
+      1
+    }
+  }
+}
+
+// Scripts are matched with export paths by position. A single export path collects the
+// results of every script.
+fn export_path(exports: &[String], script_index: usize) -> Option<&String> {
+  if exports.len() == 1 {
+    exports.first()
+  } else {
+    exports.get(script_index)
+  }
+}
+
+// State shared by every executed script
+struct Context<'a> {
+  db_querier: &'a dyn QuerierTrait,
+  directives: &'a mut import::Directives,
+  use_json: bool,
+  export_options: &'a file::ExportOptions,
+  null_marker: &'a str,
+  export_count: usize,
+  exported_headers: HashMap<String, table::Header>, // Export files written to during this run
+}
+
+// Executes every statement of a sql script, in order, stopping at the first failure.
+// Tables referenced by #[import(...)] directives are imported relative to base_directory.
+async fn execute_script(
+  context: &mut Context<'_>,
+  origin: &str,
+  script: &str,
+  base_directory: Option<&path::Path>,
+  output: Output<'_>,
+) -> Result<(), String> {
+  let db_querier = context.db_querier;
+  let script = match context
+    .directives
+    .resolve(db_querier, script, base_directory)
+    .await
+  {
+    Ok((script, _)) => script,
+    Err(e) => return Err(format!("Failure. {}", e)),
+  };
+
+  for statement in sql::split_statements(script.as_str()).iter() {
+    let mut table = match db_querier.query(statement.as_str()).await {
+      Ok(Some(table)) => table,
+      Ok(None) => continue,
+      Err(e) => {
+        return Err(format!(
+          "Failure. Query in {} could not be executed.\n{}\nError: {}",
+          origin, statement, e
+        ))
+      }
+    };
+    context.export_count += 1;
+    match output {
+      Output::Styled => {
+        table.set_null_marker(context.null_marker);
+        println!("{}", table)
+      }
+      Output::Plain if context.use_json => {
+        println!("{}", file::to_json(&table, context.export_options.pretty))
+      }
+      Output::Plain => print!("{}", file::to_csv(&table, context.export_options)),
+      Output::File(export_path) => {
+        let use_json = if context.use_json { Some(true) } else { None };
+        // Results after the first one are appended, so that one export file collects them all.
+        // Only results of the same columns can share a file, which could not be read otherwise.
+        let mut export_options = context.export_options.clone();
+        match context.exported_headers.get(export_path) {
+          Some(header) if *header != table.header => {
+            return Err(format!(
+              "Failure. Query result has other columns than the results already exported to the same file.\n{}\nPath: {}",
+              statement, export_path
+            ))
+          }
+          Some(_) => export_options.mode = file::WriteMode::Append,
+          None => {
+            context
+              .exported_headers
+              .insert(export_path.to_string(), table.header.clone());
+          }
+        }
+        if table
+          .export(
+            path::Path::new(export_path),
+            use_json,
+            Some(context.export_count),
+            &export_options,
+          )
+          .is_err()
+        {
+          return Err(format!(
+            "Failure. Could not export query result. Path: {}",
+            export_path
+          ));
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::query::memory;
+
+  // A directory of its own for the files of each test
+  fn test_directory(name: &str) -> path::PathBuf {
+    let directory =
+      std::env::temp_dir().join(format!("csvql_batch_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    directory
+  }
+
+  fn options(queries: Vec<String>, exports: Vec<String>) -> Options {
+    Options {
+      imports: Vec::new(),
+      queries,
+      script: None,
+      exports,
+      use_json: false,
+      export_options: file::ExportOptions::default(),
+      dialect: file::Dialect::default(),
+      infer_types: false,
+      reject_rows: false,
+      null_marker: "NULL".to_string(),
+      keep: false,
+    }
+  }
+
+  #[tokio::test]
+  async fn imports_queries_and_exports_in_memory() {
+    let directory = test_directory("export");
+    fs::write(
+      directory.join("fruit.csv"),
+      "id(INT),name,price(NUMERIC)\n1,apple,0.50\n2,pear,\n3,fig,1.25\n",
+    )
+    .unwrap();
+    let query_path = directory.join("query.sql");
+    fs::write(
+      &query_path,
+      "SELECT name, price FROM #[import(fruit.csv)] WHERE id > 1 ORDER BY id;\n\
+       SELECT name, price FROM fruit WHERE id = 1;\n",
+    )
+    .unwrap();
+    let export_path = directory.join("out.csv");
+    let options = options(
+      vec![query_path.to_str().unwrap().to_string()],
+      vec![export_path.to_str().unwrap().to_string()],
+    );
+
+    let db_querier = memory::Querier::new();
+    assert_eq!(run(options, &db_querier).await, 0);
+    assert_eq!(
+      fs::read_to_string(&export_path).unwrap(),
+      "name(TEXT),price(NUMERIC)\npear,\nfig,1.25\napple,0.50\n"
+    );
+    // Tables of the session are dropped once it is done
+    assert!(db_querier.load("fruit", None).await.is_err());
+    fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[tokio::test]
+  async fn exports_piped_script_as_json() {
+    let directory = test_directory("json");
+    let table_path = directory.join("fruit.csv");
+    fs::write(&table_path, "id(INT),name\n1,apple\n2,\"\"\n").unwrap();
+    let export_path = directory.join("out.json");
+    let mut options = options(Vec::new(), vec![export_path.to_str().unwrap().to_string()]);
+    options.imports = vec![(table_path.to_str().unwrap().to_string(), None)];
+    options.script = Some("SELECT * FROM fruit ORDER BY id".to_string());
+    options.use_json = true;
+
+    assert_eq!(run(options, &memory::Querier::new()).await, 0);
+    assert_eq!(
+      fs::read_to_string(&export_path).unwrap(),
+      "[{\"id\":1,\"name\":\"apple\"},{\"id\":2,\"name\":\"\"}]\n"
+    );
+    fs::remove_dir_all(&directory).unwrap();
+  }
+
+  #[tokio::test]
+  async fn fails_on_results_of_other_columns_for_one_file() {
+    let directory = test_directory("columns");
+    let table_path = directory.join("fruit.csv");
+    fs::write(&table_path, "id,name\n1,apple\n").unwrap();
+    let export_path = directory.join("out.csv");
+    let mut options = options(Vec::new(), vec![export_path.to_str().unwrap().to_string()]);
+    options.imports = vec![(table_path.to_str().unwrap().to_string(), None)];
+    options.script = Some("SELECT * FROM fruit; SELECT name FROM fruit;".to_string());
+
+    assert_eq!(run(options, &memory::Querier::new()).await, 1);
+    assert_eq!(
+      fs::read_to_string(&export_path).unwrap(),
+      "id(TEXT),name(TEXT)\n1,apple\n"
+    );
+    fs::remove_dir_all(&directory).unwrap();
+  }
+}
@@ -1,10 +1,16 @@
 use crate::table;
 use crate::types;
+use crate::types::annotation;
+use crate::types::constraint::Constraint;
 use io::BufRead;
 use regex;
+use serde::{Deserialize, Serialize};
 // use serde::ser;
 // use serde::{Serialize, Serializer};
-use std::io::Write;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{fs, io, path};
 
 #[derive(Debug)]
@@ -42,75 +48,807 @@ impl std::error::Error for Error {}
 //   }
 // }
 
-pub fn import_csv(path: &path::Path) -> Result<table::Table, Error> {
-  let line = read_first_line(path)?;
-  if line.len() == 0 {
-    Err(Error::new(
+// Csv dialect of an imported file
+#[derive(Debug, Clone)]
+pub struct Dialect {
+  pub delimiter: char,
+  pub quote: char,
+  pub escape: char, // Precedes a quote within a quoted field, same as quote by default
+  pub null: String, // Unquoted string that represents NULL
+  pub encoding: Option<String>, // Defaults to the client encoding, i.e. UTF8
+  pub header: bool, // Whether the first record holds the column names
+  fixed: Vec<&'static str>, // Options given explicitly, which are never sniffed
+}
+
+impl Default for Dialect {
+  fn default() -> Self {
+    Self {
+      delimiter: ',',
+      quote: '"',
+      escape: '"',
+      null: String::new(),
+      encoding: None,
+      header: true,
+      fixed: Vec::new(),
+    }
+  }
+}
+
+impl Dialect {
+  pub const OPTIONS: [&'static str; 6] =
+    ["delimiter", "quote", "escape", "null", "encoding", "header"];
+
+  // Sets a dialect option given its name and value, e.g. ("delimiter", "tab")
+  pub fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+    match option {
+      "delimiter" => self.delimiter = parse_char(value)?,
+      "quote" => self.quote = parse_char(value)?,
+      "escape" => self.escape = parse_char(value)?,
+      "null" => self.null = value.to_string(),
+      "encoding" => self.encoding = Some(value.to_uppercase()),
+      "header" => self.header = parse_bool(value)?,
+      _ => return Err(format!("Unknown csv option {}.", option)),
+    }
+    if let Some(option) = Self::OPTIONS.iter().find(|&&known| known == option) {
+      self.fixed.push(option);
+    }
+    Ok(())
+  }
+
+  fn is_fixed(&self, option: &str) -> bool {
+    self.fixed.contains(&option)
+  }
+
+  pub fn is_latin1(&self) -> bool {
+    match &self.encoding {
+      Some(encoding) => {
+        ["LATIN1", "ISO-8859-1", "ISO_8859_1", "ISO88591"].contains(&encoding.as_str())
+      }
+      None => false,
+    }
+  }
+}
+
+impl std::fmt::Display for Dialect {
+  fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      formatter,
+      "delimiter={:?} quote={:?} header={}",
+      self.delimiter, self.quote, self.header
+    )
+  }
+}
+
+// Parses a single character, or one of the names of common delimiters
+fn parse_char(value: &str) -> Result<char, String> {
+  match value {
+    "\\t" | "tab" => Ok('\t'),
+    "comma" => Ok(','),
+    "semicolon" => Ok(';'),
+    "pipe" => Ok('|'),
+    "space" => Ok(' '),
+    "quote" | "double_quote" => Ok('"'),
+    "single_quote" => Ok('\''),
+    "backslash" => Ok('\\'),
+    _ => {
+      let mut chars = value.chars();
+      match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("Expected a single character but got {}.", value)),
+      }
+    }
+  }
+}
+
+// Options for writing exported tables
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+  pub pretty: bool,    // Indent json output, rather than writing it on a single line
+  pub delimiter: char, // Field delimiter of csv output
+  pub terminator: String, // Line terminator of csv output, LF or CRLF
+  pub null: String,    // Unquoted string that represents NULL in csv output
+  pub mode: WriteMode, // What to do with an export file that already exists
+  pub header: HeaderStyle, // How column types are recorded in csv output
+}
+
+// How the column types of an exported csv file are recorded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderStyle {
+  Annotated, // Header fields are annotated with their type, e.g. price(NUMERIC)
+  Plain,     // Header fields are plain column names, types are dropped
+  Schema,    // Header fields are plain column names, types go to a .schema.json sidecar file
+}
+
+// How an export is written to a file that already exists
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteMode {
+  Overwrite, // Replace the file
+  Append,    // Add to the end of the file, without repeating a matching csv header
+  Fail,      // Leave the file be and fail the export
+}
+
+impl Default for ExportOptions {
+  fn default() -> Self {
+    Self {
+      pretty: false,
+      delimiter: ',',
+      terminator: "\n".to_string(),
+      null: String::new(),
+      mode: WriteMode::Overwrite,
+      header: HeaderStyle::Annotated,
+    }
+  }
+}
+
+impl ExportOptions {
+  pub const OPTIONS: [&'static str; 6] = [
+    "pretty",
+    "delimiter",
+    "terminator",
+    "null",
+    "mode",
+    "header",
+  ];
+
+  // Sets an export option given its name and value, e.g. ("pretty", "true")
+  pub fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+    match option {
+      "pretty" => self.pretty = parse_bool(value)?,
+      "delimiter" => match parse_char(value)? {
+        '"' | '\r' | '\n' => return Err(format!("Invalid delimiter {}.", value)),
+        delimiter => self.delimiter = delimiter,
+      },
+      "terminator" => match value.to_lowercase().as_str() {
+        "lf" | "\\n" => self.terminator = "\n".to_string(),
+        "crlf" | "\\r\\n" => self.terminator = "\r\n".to_string(),
+        _ => return Err(format!("Expected lf or crlf but got {}.", value)),
+      },
+      "null" => self.null = value.to_string(),
+      "mode" => match value {
+        "overwrite" => self.mode = WriteMode::Overwrite,
+        "append" => self.mode = WriteMode::Append,
+        "fail" => self.mode = WriteMode::Fail,
+        _ => {
+          return Err(format!(
+            "Expected overwrite, append or fail but got {}.",
+            value
+          ))
+        }
+      },
+      "header" => match value {
+        "annotated" => self.header = HeaderStyle::Annotated,
+        "plain" => self.header = HeaderStyle::Plain,
+        "schema" => self.header = HeaderStyle::Schema,
+        _ => {
+          return Err(format!(
+            "Expected annotated, plain or schema but got {}.",
+            value
+          ))
+        }
+      },
+      _ => return Err(format!("Unknown export option {}.", option)),
+    }
+    Ok(())
+  }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+  match value {
+    "true" | "yes" => Ok(true),
+    "false" | "no" => Ok(false),
+    _ => Err(format!("Expected true or false but got {}.", value)),
+  }
+}
+
+// Reads csv records following RFC 4180, or the given dialect. Fields may be quoted, quotes
+// within quoted fields are escaped by doubling them (or by the escape character) and
+// quoted fields may span multiple lines. Records end with either LF or CRLF, and a leading
+// byte order mark is skipped.
+pub struct Reader<R: BufRead> {
+  input: R,
+  dialect: Dialect,
+  line: Vec<u8>,
+  is_first_record: bool,
+  record: Vec<u8>,    // Bytes of the last record, as they were read
+  quoted: Vec<bool>,  // Whether each field of the last record was quoted
+  line_number: usize, // Lines read so far
+  record_line: usize, // Line the last record started on
+}
+
+impl<R: BufRead> Reader<R> {
+  pub fn new(input: R, dialect: &Dialect) -> Self {
+    Self {
+      input,
+      dialect: dialect.clone(),
+      line: Vec::new(),
+      is_first_record: true,
+      record: Vec::new(),
+      quoted: Vec::new(),
+      line_number: 0,
+      record_line: 0,
+    }
+  }
+
+  // Bytes of the last record read, line breaks included
+  pub fn raw_record(&self) -> &[u8] {
+    &self.record
+  }
+
+  // Line of the file the last record read started on, counting from 1
+  pub fn record_line(&self) -> usize {
+    self.record_line
+  }
+
+  // Values of the last record read, given its fields. As in postgres, a field is NULL if it
+  // is the null string and unquoted, so that a quoted "" stays an empty string.
+  pub fn values(&self, record: Vec<String>) -> Vec<Option<String>> {
+    record
+      .into_iter()
+      .zip(self.quoted.iter())
+      .map(|(field, &is_quoted)| {
+        if !is_quoted && field == self.dialect.null {
+          None
+        } else {
+          Some(field)
+        }
+      })
+      .collect()
+  }
+
+  // Reads the next record, or None once the input is exhausted
+  pub fn read_record(&mut self) -> io::Result<Option<Vec<String>>> {
+    let (delimiter, quote, escape) = (
+      self.dialect.delimiter,
+      self.dialect.quote,
+      self.dialect.escape,
+    );
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut is_quoted = false;
+    let mut is_field_quoted = false;
+    let mut is_first_line = true;
+    self.record.clear();
+    self.quoted.clear();
+    self.record_line = self.line_number + 1;
+    loop {
+      self.line.clear();
+      if self.input.read_until(b'\n', &mut self.line)? == 0 {
+        if is_first_line {
+          return Ok(None);
+        }
+        // Unterminated quoted field, keep what was read
+        record.push(field);
+        self.quoted.push(is_field_quoted);
+        return Ok(Some(record));
+      }
+      self.record.extend_from_slice(&self.line);
+      self.line_number += 1;
+      let mut line = self.decode_line();
+      if self.is_first_record {
+        self.is_first_record = false;
+        if line.starts_with('\u{feff}') {
+          line.drain(..'\u{feff}'.len_utf8());
+        }
+      }
+      is_first_line = false;
+
+      let mut chars = line.chars().peekable();
+      while let Some(c) = chars.next() {
+        if is_quoted {
+          if c == escape
+            && (chars.peek() == Some(&quote) || (escape != quote && chars.peek() == Some(&escape)))
+          {
+            field.push(chars.next().unwrap());
+          } else if c == quote {
+            is_quoted = false;
+          } else {
+            field.push(c);
+          }
+        } else if c == quote && field.is_empty() {
+          is_quoted = true;
+          is_field_quoted = true;
+        } else if c == delimiter {
+          record.push(std::mem::take(&mut field));
+          self.quoted.push(std::mem::take(&mut is_field_quoted));
+        } else if c == '\n' || (c == '\r' && chars.peek() == Some(&'\n')) {
+          continue;
+        } else {
+          field.push(c);
+        }
+      }
+      // A record only ends on a line break outside of quotes
+      if !is_quoted {
+        record.push(field);
+        self.quoted.push(is_field_quoted);
+        return Ok(Some(record));
+      }
+    }
+  }
+
+  fn decode_line(&self) -> String {
+    if self.dialect.is_latin1() {
+      self.line.iter().map(|&byte| byte as char).collect()
+    } else {
+      String::from_utf8_lossy(&self.line).into_owned()
+    }
+  }
+}
+
+const SNIFF_BYTES: u64 = 64 * 1024;
+const SNIFF_DELIMITERS: [char; 5] = [',', ';', '\t', '|', ':'];
+const SNIFF_QUOTES: [char; 2] = ['"', '\''];
+
+// Guesses the delimiter, quote character and header presence of a csv file by sampling
+// its first kilobytes. Options that were given explicitly are kept as is.
+pub fn sniff(path: &path::Path, dialect: &Dialect) -> Result<Dialect, Error> {
+  let mut sample = Vec::new();
+  let read_result =
+    fs::File::open(path).and_then(|file| file.take(SNIFF_BYTES).read_to_end(&mut sample));
+  if read_result.is_err() {
+    return Err(Error::new(
+      path.to_str().unwrap().to_string(),
+      "Failed to read file.".to_string(),
+    ));
+  }
+  // Only keep complete lines, unless the whole file was read
+  if sample.len() as u64 == SNIFF_BYTES {
+    if let Some(last_newline) = sample.iter().rposition(|&byte| byte == b'\n') {
+      sample.truncate(last_newline + 1);
+    }
+  }
+
+  let mut sniffed = dialect.clone();
+  if !dialect.is_fixed("quote") {
+    sniffed.quote = sniff_quote(&sample, dialect);
+    if !dialect.is_fixed("escape") {
+      sniffed.escape = sniffed.quote;
+    }
+  }
+  if !dialect.is_fixed("delimiter") {
+    sniffed.delimiter = sniff_delimiter(&sample, &sniffed);
+  }
+  if !dialect.is_fixed("header") {
+    sniffed.header = sniff_header(&read_records(&sample, &sniffed));
+  }
+  Ok(sniffed)
+}
+
+fn read_records(sample: &[u8], dialect: &Dialect) -> Vec<Vec<String>> {
+  let mut reader = Reader::new(sample, dialect);
+  let mut records = Vec::new();
+  while let Ok(Some(record)) = reader.read_record() {
+    records.push(record);
+  }
+  records
+}
+
+// Picks the quote character that most often encloses whole fields
+fn sniff_quote(sample: &[u8], dialect: &Dialect) -> char {
+  let text = String::from_utf8_lossy(sample);
+  let count_enclosed = |quote: char| {
+    lazy_static! {
+      static ref BOUNDARY: regex::Regex = regex::Regex::new(r"(^|[,;\t|:])\s*$").unwrap();
+    }
+    text
+      .lines()
+      .flat_map(|line| {
+        // A quote enclosing a field starts the line or follows a delimiter
+        line
+          .match_indices(quote)
+          .filter(|(index, _)| BOUNDARY.is_match(&line[..*index]))
+          .collect::<Vec<_>>()
+      })
+      .count()
+  };
+  SNIFF_QUOTES
+    .iter()
+    .map(|&quote| (count_enclosed(quote), quote))
+    .filter(|(count, _)| *count > 0)
+    .max_by_key(|(count, quote)| (*count, *quote == '"'))
+    .map_or(dialect.quote, |(_, quote)| quote)
+}
+
+// Picks the delimiter that splits the most records into the same number of fields. Ties
+// go to the given delimiter, and then to the earlier of SNIFF_DELIMITERS, rather than to the
+// one making the most fields, so that e.g. the colons of time values do not beat commas.
+fn sniff_delimiter(sample: &[u8], dialect: &Dialect) -> char {
+  let candidates = std::iter::once(dialect.delimiter).chain(
+    SNIFF_DELIMITERS
+      .iter()
+      .copied()
+      .filter(|&delimiter| delimiter != dialect.delimiter),
+  );
+  let mut best = (0, dialect.delimiter);
+  for delimiter in candidates {
+    let mut candidate = dialect.clone();
+    candidate.delimiter = delimiter;
+    let records = read_records(sample, &candidate);
+    // Most common number of fields per record, and how many records have it
+    let mut counts = std::collections::HashMap::new();
+    for record in records.iter() {
+      *counts.entry(record.len()).or_insert(0) += 1;
+    }
+    let (fields, records_with_fields) = counts
+      .into_iter()
+      .max_by_key(|&(fields, count)| (count, fields))
+      .unwrap_or((0, 0));
+    if fields > 1 && records_with_fields > best.0 {
+      best = (records_with_fields, delimiter);
+    }
+  }
+  best.1
+}
+
+// Decides whether the first record is a header by comparing it against the records
+// below it, column by column. A column votes for a header when its values are all
+// numeric, or all of the same length, while the first value is not.
+fn sniff_header(records: &[Vec<String>]) -> bool {
+  let (first, rest) = match records.split_first() {
+    Some((first, rest)) if !rest.is_empty() => (first, rest),
+    _ => return true,
+  };
+  let is_numeric = |value: &str| value.trim().parse::<f64>().is_ok();
+  let mut votes = 0;
+  for (column, name) in first.iter().enumerate() {
+    if name.contains('(') && name.trim_end().ends_with(')') {
+      // Type annotations only appear in headers
+      return true;
+    }
+    let values = rest
+      .iter()
+      .filter_map(|record| record.get(column))
+      .filter(|value| !value.is_empty())
+      .collect::<Vec<_>>();
+    if values.is_empty() {
+      continue;
+    }
+    if values.iter().all(|value| is_numeric(value)) {
+      votes += if is_numeric(name) { -1 } else { 1 };
+    } else if values
+      .iter()
+      .all(|value| value.chars().count() == values[0].chars().count())
+    {
+      votes += if name.chars().count() == values[0].chars().count() {
+        -1
+      } else {
+        1
+      };
+    }
+  }
+  votes >= 0
+}
+
+// Number of records sampled from a file to infer its column types
+const INFER_SAMPLE_SIZE: usize = 1000;
+
+// Builds the header of a csv table. Annotated columns keep their annotated type, while
+// all others are TEXT, or inferred from a sample of their values if infer_types is set.
+// Constraints annotated after a type are kept alongside the header.
+pub fn import_csv(
+  path: &path::Path,
+  dialect: &Dialect,
+  infer_types: bool,
+) -> Result<table::Table, Error> {
+  let fields = read_header(path, dialect)?;
+  if fields.is_empty() {
+    return Err(Error::new(
       path.to_str().unwrap().to_string(),
       "Failed to parse Table from file. Read in Zero lines.".to_string(),
-    ))
+    ));
+  }
+
+  let columns = if dialect.header {
+    // Types of a .schema.json sidecar file apply to columns without an annotated type
+    let schema = read_schema(path)?;
+    fields
+      .iter()
+      .map(|field| match parse_annotation(field) {
+        (column_name, None, constraints) => {
+          let column_type = schema.get(&column_name).cloned();
+          (column_name, column_type, constraints)
+        }
+        annotated => annotated,
+      })
+      .collect::<Vec<_>>()
   } else {
-    let header = line
-      .split(",")
-      .map(|entry| {
-        lazy_static! {
-          // Match Key
-          // C = <letter and or number strings of length at least 1>
-          // S = <any number of space>
-          // T = <any string of characters without commas>
-          // Matches (C)S(T)S
-          static ref RE: regex::Regex = regex::Regex::new(r"([a-zA-Z\d_\s]+)\(\s*([^,]+)\s*\)").unwrap();
+    // Without a header the columns are given synthetic names
+    (1..=fields.len())
+      .map(|index| (format!("column_{}", index), None, Vec::new()))
+      .collect::<Vec<_>>()
+  };
+  let samples = if infer_types {
+    sample_columns(path, dialect, columns.len())?
+  } else {
+    Vec::new()
+  };
+  let mut constraints = table::Constraints::new();
+  let header = columns
+    .into_iter()
+    .enumerate()
+    .map(|(index, (column_name, column_type, column_constraints))| {
+      // Default column type cast to SQL TEXT
+      let column_type = column_type.unwrap_or_else(|| match samples.get(index) {
+        Some(values) => types::postgres::infer_type(values),
+        None => "TEXT".to_string(),
+      });
+      constraints.push(column_constraints);
+      (column_name, column_type)
+    })
+    .collect::<table::Header>();
+  let mut table = table::Table::with_header(header);
+  table.constraints = constraints;
+  Ok(table)
+}
+
+// Number of records of a csv file that fit its table, and of the ones that did not
+pub struct Quarantine {
+  pub loaded: usize,
+  pub rejected: usize,
+}
+
+// Checks every record of a csv file against the column types and constraints of its table
+// before it is stored. Records that fit are written to valid_path as they were read, after
+// the header, while the others are written to rejects_path along with their line number
+// and the reason they were rejected. NOT NULL, UNIQUE and PRIMARY KEY constraints are
+// checked within the file, CHECK constraints only as far as they compare numbers.
+pub fn quarantine_csv(
+  path: &path::Path,
+  dialect: &Dialect,
+  table: &table::Table,
+  valid_path: &path::Path,
+  rejects_path: &path::Path,
+) -> Result<Quarantine, Error> {
+  let error = |message: &str| Error::new(path.to_str().unwrap().to_string(), message.to_string());
+  let read_error = |_| error("Failed to read file to check its rows.");
+  let write_error = |_| error("Failed to write the rows that fit the table.");
+  let file = fs::File::open(path).map_err(read_error)?;
+  let mut reader = Reader::new(io::BufReader::new(file), dialect);
+  let mut valid = io::BufWriter::new(fs::File::create(valid_path).map_err(write_error)?);
+  if dialect.header {
+    reader.read_record().map_err(read_error)?;
+    valid.write_all(reader.raw_record()).map_err(write_error)?;
+  }
+
+  let options = ExportOptions::default();
+  let mut rejects = std::iter::once("line".to_string())
+    .chain(Some("reason".to_string()))
+    .chain(
+      table
+        .header
+        .iter()
+        .map(|(column_name, _)| to_csv_field(column_name, &options)),
+    )
+    .collect::<Vec<_>>()
+    .join(",");
+  rejects.push_str(&options.terminator);
+  let mut seen = vec![HashSet::new(); table.header.len()];
+  let mut quarantine = Quarantine {
+    loaded: 0,
+    rejected: 0,
+  };
+  while let Some(record) = reader.read_record().map_err(read_error)? {
+    let values = reader.values(record);
+    match check_record(&values, table, &seen) {
+      Ok(()) => {
+        // Values of unique columns are only taken once their record is loaded
+        take_unique_values(values, table, &mut seen);
+        valid.write_all(reader.raw_record()).map_err(write_error)?;
+        quarantine.loaded += 1;
+      }
+      Err(reason) => {
+        // NULLs are written as the unquoted null string, unlike empty strings
+        let fields = [reader.record_line().to_string(), reason]
+          .iter()
+          .map(|field| to_csv_field(field, &options))
+          .chain(values.iter().map(|value| match value {
+            Some(value) => to_csv_field(value, &options),
+            None => options.null.clone(),
+          }))
+          .collect::<Vec<_>>();
+        rejects.push_str(&fields.join(","));
+        rejects.push_str(&options.terminator);
+        quarantine.rejected += 1;
+      }
+    }
+  }
+  valid.flush().map_err(write_error)?;
+  if quarantine.rejected > 0 {
+    write_file(rejects_path, WriteMode::Overwrite, rejects)?;
+  }
+  Ok(quarantine)
+}
+
+// Checks the values of a record, None for NULL, against the types and constraints of their
+// columns, giving the reason the record does not fit otherwise. Seen holds the values taken
+// by each column.
+pub fn check_record(
+  values: &[Option<String>],
+  table: &table::Table,
+  seen: &[HashSet<String>],
+) -> Result<(), String> {
+  if values.len() != table.header.len() {
+    return Err(format!(
+      "expected {} fields but got {}",
+      table.header.len(),
+      values.len()
+    ));
+  }
+  for (index, ((column_name, column_type), value)) in table.header.iter().zip(values).enumerate() {
+    let value = value.as_deref();
+    if let Some(value) = value {
+      types::postgres::check_value(column_type, value)
+        .map_err(|reason| format!("column {}: {}", column_name, reason))?;
+    }
+    for constraint in table.constraints.get(index).map_or(&[][..], Vec::as_slice) {
+      match value {
+        None if !constraint.allows(None) => {
+          return Err(format!(
+            "null value in column {} violates {}",
+            column_name,
+            constraint.to_sql(column_name)
+          ))
         }
-        if RE.is_match(entry) {
-          let captures = RE.captures(entry).unwrap();
-          let column_name = captures.get(1).map_or("null", |m| m.as_str()); // If column name is null then call it null
-          let column_type = captures.get(2).map_or("TEXT", |m| m.as_str()); // Default is TEXT, else annotated type
-          let is_valid_type = types::postgres::is_valid_type(&column_type);
-          (
-            column_name.to_string().replace(" ", "_"),
-            if is_valid_type {
-              column_type.to_string()
-            } else {
-              println!("Invalid SQL type annotation: {}. Defaulting column type to TEXT for column with name {}.", column_type, column_name);
-              "TEXT".to_string()
-            },
-          )
-        } else {
-          // Default column type cast to SQL TEXT
-          // Could make TEXT a constant
-          // TODO: Add dynamic type guessing as well (or have that be enabled by user)
-          let formatted_entry = entry.to_string().replace(" ", "_");
-          (formatted_entry, "TEXT".to_string())
+        Some(value) if !constraint.allows(Some(value)) => {
+          return Err(format!(
+            "value \"{}\" in column {} violates {}",
+            value,
+            column_name,
+            constraint.to_sql(column_name)
+          ))
         }
-      })
-      .collect::<table::Header>();
-    // let rows = lines[1..].to_vec(); // Only need the header, since we're using sql COPY
-    Ok(table::Table::with_header(header))
+        Some(value)
+          if (*constraint == Constraint::PrimaryKey || *constraint == Constraint::Unique)
+            && seen[index].contains(value) =>
+        {
+          return Err(format!(
+            "duplicate value \"{}\" in column {} violates {}",
+            value,
+            column_name,
+            constraint.to_sql(column_name)
+          ))
+        }
+        _ => (),
+      }
+    }
   }
+  Ok(())
 }
 
-pub fn export(index: Option<usize>, path: &path::Path, table: &table::Table) -> Result<(), Error> {
+// Adds the values of a loaded record to those taken by its UNIQUE and PRIMARY KEY columns
+pub fn take_unique_values(
+  values: Vec<Option<String>>,
+  table: &table::Table,
+  seen: &mut [HashSet<String>],
+) {
+  for (index, value) in values.into_iter().enumerate() {
+    let constraints = table.constraints.get(index).map_or(&[][..], Vec::as_slice);
+    let is_unique = constraints
+      .iter()
+      .any(|constraint| *constraint == Constraint::PrimaryKey || *constraint == Constraint::Unique);
+    if let (true, Some(value)) = (is_unique, value) {
+      seen[index].insert(value);
+    }
+  }
+}
+
+// Column names and types of an exported csv file, written next to it as a sidecar file
+#[derive(Serialize, Deserialize)]
+struct Schema {
+  columns: Vec<SchemaColumn>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SchemaColumn {
+  name: String,
+  #[serde(rename = "type")]
+  column_type: String,
+}
+
+// The sidecar of data.csv is data.schema.json
+pub fn schema_path(path: &path::Path) -> path::PathBuf {
+  path.with_extension("schema.json")
+}
+
+// Reads the column types of the sidecar file of a csv file, keyed by column name. A csv
+// file without a sidecar has no column types.
+fn read_schema(path: &path::Path) -> Result<HashMap<String, String>, Error> {
+  let schema_path = schema_path(path);
+  let error = |message: String| Error::new(schema_path.to_str().unwrap().to_string(), message);
+  let contents = match fs::read_to_string(&schema_path) {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+    Err(_) => return Err(error("Failed to read schema file.".to_string())),
+  };
+  let schema = serde_json::from_str::<Schema>(&contents)
+    .map_err(|e| error(format!("Failed to parse schema file. {}", e)))?;
+  let mut column_types = HashMap::new();
+  for column in schema.columns {
+    let column_type = annotation::parse_type(&column.column_type).map_err(|e| {
+      error(format!(
+        "Invalid SQL type {} of column {} in schema file. {}",
+        column.column_type, column.name, e
+      ))
+    })?;
+    column_types.insert(to_column_name(&column.name), column_type);
+  }
+  Ok(column_types)
+}
+
+// Splits a header field into its column name, its annotated type, normalized, and its
+// constraints, if it has a valid annotation
+fn parse_annotation(field: &str) -> (String, Option<String>, Vec<Constraint>) {
+  match annotation::split_field(field) {
+    (column_name, Some(column_annotation)) => match annotation::parse_column(column_annotation) {
+      Ok((column_type, constraints)) => (to_column_name(column_name), column_type, constraints),
+      Err(e) => {
+        println!(
+          "Invalid column annotation: {}. {} Ignoring it for column with name {}.",
+          column_annotation, e, column_name
+        );
+        (to_column_name(column_name), None, Vec::new())
+      }
+    },
+    (column_name, None) => (to_column_name(column_name), None, Vec::new()),
+  }
+}
+
+// Reads the values of the first records below the header, column by column. Values
+// matching the NULL string of the dialect are left out.
+fn sample_columns(
+  path: &path::Path,
+  dialect: &Dialect,
+  column_count: usize,
+) -> Result<Vec<Vec<String>>, Error> {
+  let error = || {
+    Error::new(
+      path.to_str().unwrap().to_string(),
+      "Failed to sample file to infer column types.".to_string(),
+    )
+  };
+  let file = fs::File::open(path).map_err(|_| error())?;
+  let mut reader = Reader::new(io::BufReader::new(file), dialect);
+  if dialect.header {
+    reader.read_record().map_err(|_| error())?;
+  }
+  let mut columns = vec![Vec::new(); column_count];
+  for _ in 0..INFER_SAMPLE_SIZE {
+    let record = match reader.read_record().map_err(|_| error())? {
+      Some(record) => record,
+      None => break,
+    };
+    // Only NULLs are skipped, a quoted "" is a value the column type has to accept
+    for (column, value) in columns.iter_mut().zip(reader.values(record)) {
+      if let Some(value) = value {
+        column.push(value);
+      }
+    }
+  }
+  Ok(columns)
+}
+
+pub fn export(
+  index: Option<usize>,
+  path: &path::Path,
+  table: &table::Table,
+  options: &ExportOptions,
+) -> Result<(), Error> {
   // Validate that file directory is real
   let mut absolute_path_buf;
   if !path.is_dir() {
-    match path.parent() {
-      None => (),
-      Some(parent_path) => {
-        if !parent_path.is_dir() {
-          return Err(Error::new(
-            path.to_str().unwrap().to_string(),
-            "Failed to export query result. Invalid parent directory. Could not resolve."
-              .to_string(),
-          ));
-        }
-      }
+    // A bare file name has an empty parent, which is the working directory
+    let parent_path = match path.parent() {
+      None => path::Path::new("/"),
+      Some(parent_path) if parent_path.as_os_str().is_empty() => path::Path::new("."),
+      Some(parent_path) => parent_path,
+    };
+    if !parent_path.is_dir() {
+      return Err(Error::new(
+        path.to_str().unwrap().to_string(),
+        "Failed to export query result. Invalid parent directory. Could not resolve.".to_string(),
+      ));
     }
     // Check the file extension and decide how to export the file
-    absolute_path_buf = path
-      .parent()
-      .map_or(path::Path::new("/"), |parent| parent)
-      .canonicalize()
-      .unwrap();
+    absolute_path_buf = parent_path.canonicalize().unwrap();
     absolute_path_buf.push(path.file_name().unwrap());
   } else {
     absolute_path_buf = path.canonicalize().unwrap();
@@ -124,7 +862,6 @@ pub fn export(index: Option<usize>, path: &path::Path, table: &table::Table) ->
     let out_index = index.unwrap();
     absolute_path_buf.set_file_name(format!("out_{}", out_index).as_str());
   }
-  println!("PARENT PATH WITH TEMP: {:?}", absolute_path_buf);
 
   // Set file name and extensions
   match path.extension() {
@@ -137,73 +874,403 @@ pub fn export(index: Option<usize>, path: &path::Path, table: &table::Table) ->
   }
   let absolute_path = absolute_path_buf.as_path();
 
-  println!(
-    "PATH {:?}, ABSOLUTE PATH!! {:?}, Extension {:?}",
-    path,
-    absolute_path,
-    absolute_path_buf.extension()
-  );
-
   match absolute_path.extension() {
     Some(os_str) => match os_str.to_str().unwrap() {
-      "csv" => export_csv(absolute_path, table),
-      "json" => export_json(absolute_path, table),
+      "csv" => export_csv(absolute_path, table, options),
+      "json" => export_json(absolute_path, table, options),
+      "ndjson" | "jsonl" => export_json_lines(absolute_path, table, options),
       _ => Err(Error::new(
         path.to_str().unwrap().to_string(),
-        "Failed to export query result. Unsupported file extension. Must be .csv or .json."
+        "Failed to export query result. Unsupported file extension. Must be .csv, .json, .ndjson or .jsonl."
           .to_string(),
       )),
     },
-    None => {
-      let e = Error::new(
-        path.to_str().unwrap().to_string(),
-        "Failed to export query result. Invalid path. File has no extension.".to_string(),
-      );
-      println!("ERROR :'( : {:?}", e);
-      return Err(e);
-    }
+    None => Err(Error::new(
+      path.to_str().unwrap().to_string(),
+      "Failed to export query result. Invalid path. File has no extension.".to_string(),
+    )),
   }
 }
 
-pub fn export_csv(path: &path::Path, table: &table::Table) -> Result<(), Error> {
-  let header = table
-    .header
-    .clone()
-    .into_iter()
-    // TODO: Uncomment below when support for type annotations is added
-    // .map(|(col_name, col_type)| format!("{}({})", &col_name, &col_type))
-    .map(|(col_name, col_type)| format!("{}({})", &col_name, &col_type.to_uppercase()))
+pub fn export_csv(
+  path: &path::Path,
+  table: &table::Table,
+  options: &ExportOptions,
+) -> Result<(), Error> {
+  // An appended table only gets a header of its own if the file does not have the same one
+  let has_header = options.mode == WriteMode::Append && {
+    let dialect = Dialect {
+      delimiter: options.delimiter,
+      ..Dialect::default()
+    };
+    read_header(path, &dialect).ok() == Some(to_csv_header_fields(table, options))
+  };
+  let contents = if has_header {
+    to_csv_records(table, options)
+  } else {
+    to_csv(table, options)
+  };
+  write_file(path, options.mode, contents)?;
+  if options.header == HeaderStyle::Schema {
+    export_schema(path, table)?;
+  }
+  Ok(())
+}
+
+// Writes the column names and types of a table to the sidecar file of a csv file
+fn export_schema(path: &path::Path, table: &table::Table) -> Result<(), Error> {
+  let schema = Schema {
+    columns: table
+      .header
+      .iter()
+      .map(|(col_name, col_type)| SchemaColumn {
+        name: col_name.clone(),
+        column_type: to_annotation(col_type),
+      })
+      .collect(),
+  };
+  let contents = serde_json::to_string_pretty(&schema).unwrap();
+  write_file(
+    &schema_path(path),
+    WriteMode::Overwrite,
+    format!("{}\n", contents),
+  )
+}
+
+// Formats a table as csv following RFC 4180, annotating each header column with its type
+// unless the options ask for a plain header.
+// Fields are quoted when they hold the delimiter, a quote or a line break, or when they
+// could be mistaken for NULL. NULL values are written as the unquoted NULL string.
+pub fn to_csv(table: &table::Table, options: &ExportOptions) -> String {
+  let header = to_csv_header_fields(table, options)
+    .iter()
+    .map(|field| to_csv_field(field, options))
     .collect::<Vec<String>>()
-    .join(",");
-  let rows = table
+    .join(&options.delimiter.to_string());
+  format!(
+    "{}{}{}",
+    header,
+    options.terminator,
+    to_csv_records(table, options)
+  )
+}
+
+fn to_csv_header_fields(table: &table::Table, options: &ExportOptions) -> Vec<String> {
+  table
+    .header
+    .iter()
+    .map(|(col_name, col_type)| match options.header {
+      HeaderStyle::Annotated => format!("{}({})", col_name, to_annotation(col_type)),
+      HeaderStyle::Plain | HeaderStyle::Schema => col_name.clone(),
+    })
+    .collect()
+}
+
+// Normalizes the type of a column for annotations, e.g. _int4 is INTEGER[]
+fn to_annotation(column_type: &str) -> String {
+  annotation::parse_type(column_type).unwrap_or_else(|_| column_type.to_uppercase())
+}
+
+// Formats the rows of a table as csv records, each followed by the line terminator
+fn to_csv_records(table: &table::Table, options: &ExportOptions) -> String {
+  let delimiter = options.delimiter.to_string();
+  let mut csv = String::new();
+  for row in table.rows.iter() {
+    let record = row
+      .iter()
+      .map(|entry| {
+        if entry.is_null() {
+          options.null.clone()
+        } else {
+          to_csv_field(&entry.to_string(), options)
+        }
+      })
+      .collect::<Vec<String>>()
+      .join(&delimiter);
+    csv.push_str(&record);
+    csv.push_str(&options.terminator);
+  }
+  csv
+}
+
+fn to_csv_field(value: &str, options: &ExportOptions) -> String {
+  let needs_quotes = value == options.null
+    || value
+      .chars()
+      .any(|c| c == options.delimiter || c == '"' || c == '\r' || c == '\n');
+  if needs_quotes {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_string()
+  }
+}
+
+// Formats a table as a json array of objects keyed by column name. Values keep their type,
+// e.g. numbers and booleans are not quoted.
+pub fn to_json(table: &table::Table, pretty: bool) -> String {
+  if table.rows.is_empty() {
+    "[]".to_string()
+  } else if pretty {
+    format!("[\n{}\n]", to_json_objects(table, pretty))
+  } else {
+    format!("[{}]", to_json_objects(table, pretty))
+  }
+}
+
+// Formats the rows of a table as the comma separated elements of a json array
+fn to_json_objects(table: &table::Table, pretty: bool) -> String {
+  table
     .rows
-    .clone()
-    .into_iter()
-    .map(|row| row.join(","))
-    .collect::<Vec<String>>()
-    .join("\n");
-  write_file(path, format!("{}\n{}\n", header, rows))
+    .iter()
+    .map(|row| to_json_object(&table.header, row, pretty))
+    .collect::<Vec<_>>()
+    .join(if pretty { ",\n" } else { "," })
 }
 
-pub fn export_json(path: &path::Path, table: &table::Table) -> Result<(), Error> {
-  // let contents = ser::to_string(table)?;
-  // write_file(path, contents)?
-  todo!();
-  Ok(())
+// Formats a row as a json object, indented as an element of an array if pretty. Keys are
+// written in column order, which a serde_json::Map would sort instead.
+fn to_json_object(header: &table::Header, row: &table::Row, pretty: bool) -> String {
+  let fields = header
+    .iter()
+    .zip(row.iter())
+    .map(|((col_name, _), entry)| {
+      let key = serde_json::to_string(col_name).unwrap();
+      let value = entry.to_json();
+      if pretty {
+        let value = serde_json::to_string_pretty(&value).unwrap();
+        format!("    {}: {}", key, value.replace('\n', "\n    "))
+      } else {
+        format!("{}:{}", key, value)
+      }
+    })
+    .collect::<Vec<_>>();
+  if pretty {
+    format!("  {{\n{}\n  }}", fields.join(",\n"))
+  } else {
+    format!("{{{}}}", fields.join(","))
+  }
 }
 
-fn read_first_line(path: &path::Path) -> Result<String, Error> {
-  match fs::File::open(path) {
-    Ok(file) => {
-      let mut buffer = String::new();
+pub fn export_json(
+  path: &path::Path,
+  table: &table::Table,
+  options: &ExportOptions,
+) -> Result<(), Error> {
+  let existing = match options.mode {
+    WriteMode::Append => fs::read_to_string(path).unwrap_or_default(),
+    _ => String::new(),
+  };
+  if existing.trim().is_empty() || table.rows.is_empty() {
+    let contents = format!("{}\n", to_json(table, options.pretty));
+    return write_file(path, options.mode, contents);
+  }
+
+  // Appended objects are spliced into the existing array, so that the file stays valid json
+  let elements = match existing
+    .trim()
+    .strip_prefix('[')
+    .and_then(|elements| elements.strip_suffix(']'))
+  {
+    Some(elements) => elements.trim(),
+    None => {
+      return Err(Error::new(
+        path.to_str().unwrap().to_string(),
+        "Failed to append to file. File does not hold a json array.".to_string(),
+      ))
+    }
+  };
+  let contents = match (elements.is_empty(), options.pretty) {
+    (true, _) => to_json(table, options.pretty),
+    (false, true) => format!("[\n  {},\n{}\n]", elements, to_json_objects(table, true)),
+    (false, false) => format!("[{},{}]", elements, to_json_objects(table, false)),
+  };
+  write_file(path, WriteMode::Overwrite, format!("{}\n", contents))
+}
+
+// Writes a table as newline delimited json, one object per row
+pub fn export_json_lines(
+  path: &path::Path,
+  table: &table::Table,
+  options: &ExportOptions,
+) -> Result<(), Error> {
+  write_file_with(path, options.mode, |file| {
+    let mut writer = io::BufWriter::new(file);
+    for row in table.rows.iter() {
+      writeln!(writer, "{}", to_json_object(&table.header, row, false))?;
+    }
+    writer.flush()
+  })
+}
+
+// Whether a file holds newline delimited json, judging by its extension
+pub fn is_json_lines(path: &path::Path) -> bool {
+  match path.extension().and_then(|extension| extension.to_str()) {
+    Some(extension) => ["ndjson", "jsonl"].contains(&extension.to_lowercase().as_str()),
+    None => false,
+  }
+}
+
+// Builds the header of a newline delimited json table from the union of the keys of its
+// objects, in the order they first appear, and writes its rows to csv_path as csv that
+// can be loaded with the default dialect. Missing keys and nulls become NULL, while
+// nested objects and arrays are kept as json text.
+pub fn import_json_lines(
+  path: &path::Path,
+  csv_path: &path::Path,
+  infer_types: bool,
+) -> Result<table::Table, Error> {
+  let error = |message: String| Error::new(path.to_str().unwrap().to_string(), message);
+  let read_objects = || -> Result<_, Error> {
+    let file = fs::File::open(path).map_err(|_| error("Failed to open file.".to_string()))?;
+    Ok(
       io::BufReader::new(file)
-        .read_line(&mut buffer)
-        .expect("Reading first line of file.");
-      Ok(buffer)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(move |(index, line)| {
+          let line = line.map_err(|_| error("Failed to read file.".to_string()))?;
+          match serde_json::from_str::<JsonObject>(&line) {
+            Ok(JsonObject(entries)) => Ok(entries),
+            _ => Err(error(format!("Line {} is not a json object.", index + 1))),
+          }
+        }),
+    )
+  };
+
+  // First pass over the file collects the keys, along with samples of their values
+  let mut keys: Vec<String> = Vec::new();
+  let mut samples: Vec<Vec<String>> = Vec::new();
+  for (index, object) in read_objects()?.enumerate() {
+    for (key, value) in object?.iter() {
+      let column = match keys.iter().position(|known| known == key) {
+        Some(column) => column,
+        None => {
+          keys.push(key.clone());
+          samples.push(Vec::new());
+          keys.len() - 1
+        }
+      };
+      if infer_types && index < INFER_SAMPLE_SIZE && !value.is_null() {
+        samples[column].push(json_to_text(value));
+      }
     }
+  }
+  if keys.is_empty() {
+    return Err(error(
+      "Failed to parse Table from file. Read in Zero objects.".to_string(),
+    ));
+  }
+
+  // Second pass writes the values of every object in column order
+  let csv_file =
+    fs::File::create(csv_path).map_err(|_| error("Failed to create csv file.".to_string()))?;
+  let mut writer = io::BufWriter::new(csv_file);
+  let write_error = |_| error("Failed to write csv file.".to_string());
+  let header_fields = keys
+    .iter()
+    .map(|key| to_csv_field(key, &ExportOptions::default()))
+    .collect::<Vec<_>>();
+  writeln!(writer, "{}", header_fields.join(",")).map_err(write_error)?;
+  for object in read_objects()? {
+    let object = object?
+      .into_iter()
+      .collect::<std::collections::HashMap<_, _>>();
+    let record = keys
+      .iter()
+      .map(|key| match object.get(key) {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(value) => format!("\"{}\"", json_to_text(value).replace('"', "\"\"")),
+      })
+      .collect::<Vec<_>>()
+      .join(",");
+    writeln!(writer, "{}", record).map_err(write_error)?;
+  }
+  writer.flush().map_err(write_error)?;
+
+  let header = keys
+    .iter()
+    .zip(samples.iter())
+    .map(|(key, values)| {
+      let column_type = if infer_types {
+        types::postgres::infer_type(values)
+      } else {
+        "TEXT".to_string()
+      };
+      (to_column_name(key), column_type)
+    })
+    .collect::<table::Header>();
+  Ok(table::Table::with_header(header))
+}
+
+// A json object that keeps its keys in the order they are written in, which a
+// serde_json::Map would sort instead
+struct JsonObject(Vec<(String, serde_json::Value)>);
+
+impl<'de> serde::Deserialize<'de> for JsonObject {
+  fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    struct ObjectVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for ObjectVisitor {
+      type Value = JsonObject;
+
+      fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a json object")
+      }
+
+      fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<JsonObject, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(entry) = map.next_entry()? {
+          entries.push(entry);
+        }
+        Ok(JsonObject(entries))
+      }
+    }
+
+    deserializer.deserialize_map(ObjectVisitor)
+  }
+}
+
+// Strings are taken as is, other values as their json text
+fn json_to_text(value: &serde_json::Value) -> String {
+  match value {
+    serde_json::Value::String(string) => string.clone(),
+    _ => value.to_string(),
+  }
+}
+
+// Replaces every character that is not valid in an unquoted sql identifier with _
+fn to_column_name(entry: &str) -> String {
+  let column_name = entry
+    .trim()
+    .chars()
+    .map(|c| {
+      if c.is_alphanumeric() || c == '_' {
+        c
+      } else {
+        '_'
+      }
+    })
+    .collect::<String>();
+  // Identifiers cannot start with a digit
+  if column_name.starts_with(|c: char| c.is_ascii_digit()) {
+    format!("_{}", column_name)
+  } else {
+    column_name
+  }
+}
+
+fn read_header(path: &path::Path, dialect: &Dialect) -> Result<Vec<String>, Error> {
+  match fs::File::open(path) {
+    Ok(file) => match Reader::new(io::BufReader::new(file), dialect).read_record() {
+      Ok(record) => Ok(record.unwrap_or_default()),
+      Err(_) => Err(Error::new(
+        path.to_str().unwrap().to_string(),
+        "Failed to read header of file.".to_string(),
+      )),
+    },
     Err(_) => Err(Error::new(
       path.to_str().unwrap().to_string(),
-      "Failed to read first line of file.".to_string(),
+      "Failed to read header of file.".to_string(),
     )),
   }
 }
@@ -237,39 +1304,317 @@ fn _read_file(path: &path::Path) -> Result<Vec<String>, Error> {
   }
 }
 
-fn write_file(path: &path::Path, contents: String) -> Result<(), Error> {
-  // could use fs::write(path, contents) if I wanted to overwrite contents
-  // let file = fs::File::with_options().append(true).create(true).open(path);
-  let mut file = match fs::OpenOptions::new().append(true).create(true).open(path) {
-    Ok(file) => file,
-    Err(_) => {
-      // io_error
-      return Err(Error::new(
-        path.to_str().unwrap().to_string(),
-        "Failed open file at path.".to_string(),
-      ));
+fn write_file(path: &path::Path, mode: WriteMode, contents: String) -> Result<(), Error> {
+  write_file_with(path, mode, |file| file.write_all(contents.as_bytes()))
+}
+
+// Hands a file to write, so that large contents can be streamed into it rather than built
+// up in memory first. The file is written next to the target and then renamed over it, so
+// that a failed write never leaves a partially written export behind. In fail mode it is
+// linked into place instead, which never replaces a file created in the meantime.
+fn write_file_with<F>(path: &path::Path, mode: WriteMode, write: F) -> Result<(), Error>
+where
+  F: FnOnce(&mut fs::File) -> io::Result<()>,
+{
+  let error = |message: &str| Error::new(path.display().to_string(), message.to_string());
+  if mode == WriteMode::Fail && path.exists() {
+    return Err(error("Failed to export query result. File already exists."));
+  }
+  let temp_path = temp_path(path)
+    .ok_or_else(|| error("Failed to export query result. Path has no file name."))?;
+
+  let result = (|| {
+    let mut options = fs::OpenOptions::new();
+    if mode == WriteMode::Append && path.exists() {
+      fs::copy(path, &temp_path).map_err(|_| error("Failed to copy file to append to."))?;
+      options.append(true);
+    } else {
+      // Never reuse a file left behind by an earlier run
+      options.write(true).create_new(true);
     }
-  };
-  match file.write_all(contents.as_bytes()) {
-    Err(_) => {
-      // io_error
-      return Err(Error::new(
-        path.to_str().unwrap().to_string(),
-        "Failed to write_all to file.".to_string(),
-      ));
+    let mut file = options
+      .open(&temp_path)
+      .map_err(|_| error("Failed open file at path."))?;
+    write(&mut file).map_err(|_| error("Failed to write_all to file."))?;
+    file
+      .sync_all()
+      .map_err(|_| error("Failed to sync_all to file system."))?;
+    if mode == WriteMode::Fail {
+      // Linking fails if the target exists by now, where renaming would replace it
+      return fs::hard_link(&temp_path, path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => {
+          error("Failed to export query result. File already exists.")
+        }
+        _ => error("Failed to move file into place."),
+      });
     }
-    _ => (),
-  };
-  match file.sync_all() {
-    Err(_) => {
-      // io_error
-      return Err(Error::new(
-        path.to_str().unwrap().to_string(),
-        "Failed to sync_all to file system.".to_string(),
-      ));
+    fs::rename(&temp_path, path).map_err(|_| error("Failed to move file into place."))
+  })();
+  if result.is_err() || mode == WriteMode::Fail {
+    let _ = fs::remove_file(&temp_path);
+  }
+  result
+}
+
+// Names a hidden file next to the target that is unique to this process and this call.
+fn temp_path(path: &path::Path) -> Option<path::PathBuf> {
+  static COUNTER: AtomicUsize = AtomicUsize::new(0);
+  let file_name = path.file_name()?.to_string_lossy();
+  let nanos = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.subsec_nanos())
+    .unwrap_or(0);
+  let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+  Some(path.with_file_name(format!(
+    ".{}.{}.{}.{}.tmp",
+    file_name,
+    std::process::id(),
+    nanos,
+    count
+  )))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn read_all(input: &str, dialect: &Dialect) -> Vec<Vec<String>> {
+    let mut reader = Reader::new(input.as_bytes(), dialect);
+    let mut records = Vec::new();
+    while let Some(record) = reader.read_record().unwrap() {
+      records.push(record);
     }
-    _ => (),
-  };
+    records
+  }
 
-  Ok(())
+  #[test]
+  fn reads_plain_records() {
+    assert_eq!(
+      read_all("a,b,c\n1,,3\r\n", &Dialect::default()),
+      vec![vec!["a", "b", "c"], vec!["1", "", "3"]]
+    );
+  }
+
+  #[test]
+  fn reads_quoted_fields() {
+    assert_eq!(
+      read_all("\"a,b\",\"say \"\"hi\"\"\"\n", &Dialect::default()),
+      vec![vec!["a,b", "say \"hi\""]]
+    );
+  }
+
+  #[test]
+  fn reads_line_breaks_in_quoted_fields() {
+    let input = "id,note\n1,\"two\nlines\"\n2,x\n";
+    let mut reader = Reader::new(input.as_bytes(), &Dialect::default());
+    reader.read_record().unwrap();
+    assert_eq!(
+      reader.read_record().unwrap().unwrap(),
+      vec!["1", "two\nlines"]
+    );
+    assert_eq!(reader.record_line(), 2);
+    assert_eq!(reader.raw_record(), b"1,\"two\nlines\"\n");
+    assert_eq!(reader.read_record().unwrap().unwrap(), vec!["2", "x"]);
+    assert_eq!(reader.record_line(), 4);
+    assert_eq!(reader.read_record().unwrap(), None);
+  }
+
+  #[test]
+  fn strips_byte_order_mark() {
+    assert_eq!(
+      read_all("\u{feff}a,b\n", &Dialect::default()),
+      vec![vec!["a", "b"]]
+    );
+  }
+
+  #[test]
+  fn keeps_unterminated_quoted_field() {
+    assert_eq!(
+      read_all("1,\"open\n", &Dialect::default()),
+      vec![vec!["1", "open\n"]]
+    );
+  }
+
+  fn sniff_sample(sample: &str) -> Dialect {
+    sniff_sample_with(sample, &Dialect::default())
+  }
+
+  fn sniff_sample_with(sample: &str, dialect: &Dialect) -> Dialect {
+    static SAMPLES: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+      "csvql_sniff_{}_{}.csv",
+      std::process::id(),
+      SAMPLES.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::write(&path, sample).unwrap();
+    let sniffed = sniff(&path, dialect);
+    fs::remove_file(&path).unwrap();
+    sniffed.unwrap()
+  }
+
+  #[test]
+  fn sniffs_delimiter() {
+    assert_eq!(sniff_sample("a;b;c\n1;2;3\n4;5;6\n").delimiter, ';');
+    assert_eq!(sniff_sample("a\tb\n1\t2\n").delimiter, '\t');
+    assert_eq!(sniff_sample("a|b\n\"1|2\"|3\n4|5\n").delimiter, '|');
+  }
+
+  #[test]
+  fn sniffs_comma_over_colons_of_time_values() {
+    let dialect = sniff_sample("1,10:00:00,11:00:00\n2,12:30:00,13:45:00\n");
+    assert_eq!(dialect.delimiter, ',');
+    assert!(!dialect.header);
+  }
+
+  #[test]
+  fn sniffs_quote_and_header() {
+    let dialect = sniff_sample("name,age\n'a, b',30\n'c',40\n");
+    assert_eq!(dialect.quote, '\'');
+    assert!(dialect.header);
+  }
+
+  #[test]
+  fn keeps_given_delimiter() {
+    let mut dialect = Dialect::default();
+    dialect.set("delimiter", "pipe").unwrap();
+    assert_eq!(sniff_sample_with("a;b\n1;2\n", &dialect).delimiter, '|');
+  }
+
+  #[test]
+  fn quotes_json_keys_in_csv_header() {
+    let directory = std::env::temp_dir();
+    let json_path = directory.join(format!("csvql_keys_{}.ndjson", std::process::id()));
+    let csv_path = directory.join(format!("csvql_keys_{}.csv", std::process::id()));
+    fs::write(
+      &json_path,
+      "{\"a,b\":1,\"say \\\"x\\\"\":2,\"two\\nlines\":3}\n",
+    )
+    .unwrap();
+    let table = import_json_lines(&json_path, &csv_path, false).unwrap();
+    let contents = fs::read_to_string(&csv_path).unwrap();
+    fs::remove_file(&json_path).unwrap();
+    fs::remove_file(&csv_path).unwrap();
+    assert_eq!(
+      read_all(&contents, &Dialect::default()),
+      vec![vec!["a,b", "say \"x\"", "two\nlines"], vec!["1", "2", "3"]]
+    );
+    assert_eq!(table.header.len(), 3);
+  }
+
+  #[test]
+  fn writes_files_in_every_mode() {
+    let directory = std::env::temp_dir();
+    let path = directory.join(format!("csvql_write_{}.csv", std::process::id()));
+    let _ = fs::remove_file(&path);
+    write_file(&path, WriteMode::Fail, "a\n".to_string()).unwrap();
+    assert!(write_file(&path, WriteMode::Fail, "b\n".to_string()).is_err());
+    write_file(&path, WriteMode::Append, "b\n".to_string()).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+    write_file(&path, WriteMode::Overwrite, "c\n".to_string()).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "c\n");
+    fs::remove_file(&path).unwrap();
+    let leftovers = fs::read_dir(&directory)
+      .unwrap()
+      .filter_map(Result::ok)
+      .filter(|entry| {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        name.starts_with(&format!(".csvql_write_{}.csv.", std::process::id()))
+      })
+      .count();
+    assert_eq!(leftovers, 0);
+  }
+
+  #[test]
+  fn fail_mode_never_replaces_a_file() {
+    let path = std::env::temp_dir().join(format!("csvql_fail_{}.csv", std::process::id()));
+    let _ = fs::remove_file(&path);
+    // The file is created by someone else while the export is being written
+    let result = write_file_with(&path, WriteMode::Fail, |file| {
+      fs::write(&path, "kept\n")?;
+      file.write_all(b"lost\n")
+    });
+    assert!(result.is_err());
+    assert_eq!(fs::read_to_string(&path).unwrap(), "kept\n");
+    fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn names_a_fresh_temp_file_per_write() {
+    let path = path::Path::new("/tmp/out.csv");
+    let first = temp_path(path).unwrap();
+    assert_ne!(first, temp_path(path).unwrap());
+    assert_eq!(first.parent(), path.parent());
+    assert!(first
+      .file_name()
+      .unwrap()
+      .to_string_lossy()
+      .starts_with(".out.csv."));
+    assert!(temp_path(path::Path::new("/")).is_none());
+  }
+
+  #[test]
+  fn infers_types_from_values_but_not_nulls() {
+    let path = std::env::temp_dir().join(format!("csvql_infer_{}.csv", std::process::id()));
+    fs::write(&path, "a,b,c\n1,\"\",2020-01-01\n,\"\",\n2,,\"\"\n").unwrap();
+    let table = import_csv(&path, &Dialect::default(), true);
+    fs::remove_file(&path).unwrap();
+    let types = table
+      .unwrap()
+      .header
+      .into_iter()
+      .map(|(_, column_type)| column_type)
+      .collect::<Vec<_>>();
+    assert_eq!(types, vec!["INT4", "TEXT", "TEXT"]);
+  }
+
+  #[test]
+  fn tells_null_from_quoted_empty_string() {
+    let mut dialect = Dialect::default();
+    let mut reader = Reader::new(&b"\"\",,x\n"[..], &dialect);
+    let record = reader.read_record().unwrap().unwrap();
+    assert_eq!(
+      reader.values(record),
+      vec![Some("".to_string()), None, Some("x".to_string())]
+    );
+    dialect.set("null", "NA").unwrap();
+    let mut reader = Reader::new(&b"NA,\"NA\",\n"[..], &dialect);
+    let record = reader.read_record().unwrap().unwrap();
+    assert_eq!(
+      reader.values(record),
+      vec![None, Some("NA".to_string()), Some("".to_string())]
+    );
+  }
+
+  #[test]
+  fn quarantines_nulls_but_not_empty_strings() {
+    let directory = std::env::temp_dir();
+    let name =
+      |suffix: &str| directory.join(format!("csvql_nulls_{}.{}", std::process::id(), suffix));
+    let (path, valid_path, rejects_path) = (name("csv"), name("valid.csv"), name("rejects.csv"));
+    fs::write(&path, "a,b\n\"\",z\n,y\n").unwrap();
+    let mut table = table::Table::with_header(vec![
+      ("a".to_string(), "TEXT".to_string()),
+      ("b".to_string(), "TEXT".to_string()),
+    ]);
+    table.constraints = vec![vec![Constraint::NotNull], Vec::new()];
+    let quarantine = quarantine_csv(
+      &path,
+      &Dialect::default(),
+      &table,
+      &valid_path,
+      &rejects_path,
+    )
+    .unwrap();
+    let valid = fs::read_to_string(&valid_path).unwrap();
+    let rejects = fs::read_to_string(&rejects_path).unwrap();
+    for path in [path, valid_path, rejects_path].iter() {
+      fs::remove_file(path).unwrap();
+    }
+    assert_eq!((quarantine.loaded, quarantine.rejected), (1, 1));
+    assert_eq!(valid, "a,b\n\"\",z\n");
+    assert_eq!(
+      rejects,
+      "line,reason,a,b\n3,null value in column a violates NOT NULL,,y\n"
+    );
+  }
 }
@@ -0,0 +1,351 @@
+use crate::file;
+use crate::query::querier;
+use crate::table::{self, Purveyor, Table};
+use crate::util::{self, validate};
+
+use querier::QuerierTrait;
+use std::collections::HashMap;
+use std::{env, fs, path, process};
+use validate::Validate;
+
+// Import Error struct
+#[derive(Debug)]
+pub struct Error {
+  pub message: String,
+}
+
+impl Error {
+  fn new(message: String) -> Self {
+    Self { message }
+  }
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Import Error: {}", self.message)
+  }
+}
+
+impl std::error::Error for Error {}
+
+// Imports a csv file into the database as a table named after the given alias, or after
+// the file name without its extension. Any dialect options that were not given are sniffed
+// from the file. Unannotated columns are TEXT, unless infer_types is set. If reject_rows is
+// set, rows of csv files that do not fit their table are left out and written to a rejects
+// file next to the file. Newline delimited json files (.ndjson or .jsonl) are imported as
+// well. Returns the name of the stored table and the csv dialect it was read with, if it is
+// a csv file.
+pub async fn import(
+  db_querier: &dyn QuerierTrait,
+  path: &path::Path,
+  optional_name: Option<String>,
+  dialect: &file::Dialect,
+  infer_types: bool,
+  reject_rows: bool,
+) -> Result<(String, Option<file::Dialect>), Error> {
+  // Validate and Resolve the relative or absolute path
+  let path_info = match path.validate() {
+    Ok(path_info) => path_info,
+    Err(_) => {
+      return Err(Error::new(format!(
+        "Invalid file path to import from. Path: {}",
+        path.display()
+      )))
+    }
+  };
+  if path_info.path.is_dir() {
+    return Err(Error::new(format!(
+      "Given path is to a directory. Must be a csv file. Path: {}",
+      path.display()
+    )));
+  }
+  let table_name = match optional_name.or(path_info.filename) {
+    Some(table_name) => table_name,
+    None => {
+      return Err(Error::new(format!(
+        "Could not resolve a table name. Path: {}",
+        path.display()
+      )))
+    }
+  };
+
+  // If this table is already in the database then throw
+  match db_querier.list(false).await {
+    Err(_) => {
+      return Err(Error::new(
+        "Internal Error. Unable to confirm if table name is taken.".to_string(),
+      ))
+    }
+    Ok(Some(list))
+      if list
+        .rows
+        .iter()
+        .any(|name| table_name == name[0].to_string()) =>
+    {
+      return Err(Error::new(format!(
+        "Table name already taken. Name: {}",
+        table_name
+      )))
+    }
+    _ => (),
+  }
+
+  if file::is_json_lines(path_info.path.as_path()) {
+    // Objects are converted into a temporary csv file, which is then stored like any other
+    let csv_path = env::temp_dir().join(format!("csvql_{}_{}.csv", process::id(), table_name));
+    let result = match file::import_json_lines(path_info.path.as_path(), &csv_path, infer_types) {
+      Ok(table) => {
+        store(
+          db_querier,
+          &csv_path,
+          table_name,
+          table.header,
+          &table.constraints,
+          &file::Dialect::default(),
+        )
+        .await
+      }
+      Err(e) => Err(Error::new(format!(
+        "Table import error occurred. {} Path: {}",
+        e.message,
+        path.display()
+      ))),
+    };
+    let _ = fs::remove_file(&csv_path);
+    return result.map(|table_name| (table_name, None));
+  }
+
+  // Import table given the resolved absolute path
+  let dialect = match file::sniff(path_info.path.as_path(), dialect) {
+    Ok(dialect) => dialect,
+    Err(_) => {
+      return Err(Error::new(format!(
+        "Failed to read file. Path: {}",
+        path.display()
+      )))
+    }
+  };
+  let table = match Table::import(path_info.path.as_path(), &dialect, infer_types) {
+    Ok(table) => table,
+    Err(_) => {
+      return Err(Error::new(format!(
+        "Table import error occurred. Path: {}",
+        path.display()
+      )))
+    }
+  };
+  if reject_rows {
+    // Only the rows that fit the table are stored, from a temporary copy of the file
+    let valid_path = env::temp_dir().join(format!("csvql_{}_{}.csv", process::id(), table_name));
+    let rejects_path = path_info
+      .path
+      .with_file_name(format!("{}.rejects.csv", table_name));
+    let quarantine = match file::quarantine_csv(
+      path_info.path.as_path(),
+      &dialect,
+      &table,
+      &valid_path,
+      &rejects_path,
+    ) {
+      Ok(quarantine) => quarantine,
+      Err(e) => {
+        let _ = fs::remove_file(&valid_path);
+        return Err(Error::new(format!(
+          "Table import error occurred. {} Path: {}",
+          e.message,
+          path.display()
+        )));
+      }
+    };
+    let result = store(
+      db_querier,
+      &valid_path,
+      table_name,
+      table.header,
+      &table.constraints,
+      &dialect,
+    )
+    .await;
+    let _ = fs::remove_file(&valid_path);
+    let table_name = result?;
+    if quarantine.rejected > 0 {
+      eprintln!(
+        "Loaded {} rows into table {}, rejected {}. Rejected rows were written to {}.",
+        quarantine.loaded,
+        table_name,
+        quarantine.rejected,
+        rejects_path.display()
+      );
+    } else {
+      eprintln!(
+        "Loaded {} rows into table {}, rejected 0.",
+        quarantine.loaded, table_name
+      );
+    }
+    return Ok((table_name, Some(dialect)));
+  }
+  let table_name = store(
+    db_querier,
+    &path_info.path,
+    table_name,
+    table.header,
+    &table.constraints,
+    &dialect,
+  )
+  .await?;
+  Ok((table_name, Some(dialect)))
+}
+
+// Stores a table with the rows of the csv file at the given absolute path
+async fn store(
+  db_querier: &dyn QuerierTrait,
+  absolute_path: &path::Path,
+  table_name: String,
+  header: table::Header,
+  constraints: &table::Constraints,
+  dialect: &file::Dialect,
+) -> Result<String, Error> {
+  let absolute_path = absolute_path.as_os_str().to_str().unwrap();
+  match db_querier
+    .store(
+      absolute_path,
+      table_name.as_str(),
+      header,
+      constraints,
+      dialect,
+    )
+    .await
+  {
+    Ok(_) => Ok(table_name),
+    Err(e) => Err(Error::new(format!(
+      "Error occurred while storing table {} in database. {}",
+      table_name, e
+    ))),
+  }
+}
+
+lazy_static! {
+  // Matches #[import(path)], #[import(path) as alias] and #[import(path) = alias]
+  // where path may be wrapped in single or double quotes
+  static ref IMPORT_RE: regex::Regex = regex::Regex::new(
+    r#"#\[\s*import\s*\(\s*(?:'([^']*)'|"([^"]*)"|([^)]*?))\s*\)\s*(?:(?:(?i:as)\s+|=\s*)([a-zA-Z_][a-zA-Z\d_]*)\s*)?\]"#
+  )
+  .unwrap();
+}
+
+// Keeps track of the tables imported through #[import(...)] directives, so that
+// each file is only imported once no matter how often it is referenced
+pub struct Directives {
+  dialect: file::Dialect,
+  infer_types: bool,
+  reject_rows: bool,
+  imported: HashMap<String, path::PathBuf>,
+}
+
+impl Directives {
+  // Directives import files of the given csv dialect, inferring column types if infer_types is
+  // set and setting aside rows that do not fit their table if reject_rows is set
+  pub fn new(dialect: file::Dialect, infer_types: bool, reject_rows: bool) -> Self {
+    Self {
+      dialect,
+      infer_types,
+      reject_rows,
+      imported: HashMap::new(),
+    }
+  }
+
+  // Imports the tables referenced by all directives in sql. Directives on a line of
+  // their own are removed, while inline directives are replaced by their table name.
+  // Relative paths are resolved against base_directory if given. Returns the cleaned
+  // sql along with the names of the newly imported tables.
+  pub async fn resolve(
+    &mut self,
+    db_querier: &dyn QuerierTrait,
+    sql: &str,
+    base_directory: Option<&path::Path>,
+  ) -> Result<(String, Vec<String>), Error> {
+    let mut cleaned_sql = String::new();
+    let mut newly_imported = Vec::new();
+    let mut last_end = 0;
+    // Text that looks like a directive inside of a string literal or comment is left be
+    let literals = util::sql::literal_ranges(sql);
+    for captures in IMPORT_RE.captures_iter(sql) {
+      let directive = captures.get(0).unwrap();
+      if literals
+        .iter()
+        .any(|literal| literal.contains(&directive.start()))
+      {
+        continue;
+      }
+      let raw_path = captures
+        .get(1)
+        .or(captures.get(2))
+        .or(captures.get(3))
+        .map_or("", |m| m.as_str());
+      let mut import_path = path::PathBuf::from(raw_path);
+      if let Some(base_directory) = base_directory {
+        if import_path.is_relative() {
+          import_path = base_directory.join(import_path);
+        }
+      }
+      let absolute_path = match import_path.canonicalize() {
+        Ok(absolute_path) => absolute_path,
+        Err(_) => {
+          return Err(Error::new(format!(
+            "Invalid file path to import from. Path: {}",
+            raw_path
+          )))
+        }
+      };
+      let optional_name = captures.get(4).map(|m| m.as_str().to_string());
+      let table_name = match optional_name.clone().or_else(|| self.find(&absolute_path)) {
+        Some(table_name) if self.imported.get(&table_name) == Some(&absolute_path) => table_name,
+        Some(table_name) if self.imported.contains_key(&table_name) => {
+          return Err(Error::new(format!(
+            "Table name {} is already imported from {}.",
+            table_name,
+            self.imported[&table_name].display()
+          )))
+        }
+        _ => {
+          let (table_name, _) = import(
+            db_querier,
+            absolute_path.as_path(),
+            optional_name,
+            &self.dialect,
+            self.infer_types,
+            self.reject_rows,
+          )
+          .await?;
+          self.imported.insert(table_name.clone(), absolute_path);
+          newly_imported.push(table_name.clone());
+          table_name
+        }
+      };
+
+      cleaned_sql.push_str(&sql[last_end..directive.start()]);
+      if !is_own_line(sql, directive.start(), directive.end()) {
+        cleaned_sql.push_str(table_name.as_str());
+      }
+      last_end = directive.end();
+    }
+    cleaned_sql.push_str(&sql[last_end..]);
+    Ok((cleaned_sql, newly_imported))
+  }
+
+  // Finds the table a file was imported as without an alias
+  fn find(&self, absolute_path: &path::Path) -> Option<String> {
+    let file_stem = absolute_path.file_stem()?.to_str()?;
+    match self.imported.get(file_stem) {
+      Some(imported_path) if imported_path == absolute_path => Some(file_stem.to_string()),
+      _ => None,
+    }
+  }
+}
+
+// Returns true if the given directive is the only thing on its line (a header directive)
+fn is_own_line(sql: &str, start: usize, end: usize) -> bool {
+  let line_start = sql[..start].rfind('\n').map_or(0, |index| index + 1);
+  let line_end = sql[end..].find('\n').map_or(sql.len(), |index| end + index);
+  sql[line_start..start].trim().is_empty() && sql[end..line_end].trim().is_empty()
+}
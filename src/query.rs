@@ -1,2 +1,5 @@
+pub mod backend;
+pub mod memory;
 pub mod postgres;
 pub mod querier;
+pub mod sqlite;
@@ -0,0 +1,206 @@
+use crate::query::{memory, postgres, querier, sqlite};
+use querier::{Backend, QuerierTrait};
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::{env, fmt, fs, path};
+
+pub const DATABASE_URL_KEY: &str = "DATABASE_URL";
+pub const BACKEND_KEY: &str = "CSVQL_BACKEND";
+const CONFIG_FILE_NAME: &str = ".csvql.json";
+
+// Where a setting was given, in order of precedence
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+  Flag(&'static str),        // Command line argument, e.g. --backend
+  Environment(&'static str), // Environment variable
+  DotEnv(&'static str),      // Variable of a .env file in the working directory or above
+  ConfigFile(path::PathBuf), // .csvql.json in the working directory or the home directory
+}
+
+impl fmt::Display for Source {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Source::Flag(name) => write!(f, "the --{} flag", name),
+      Source::Environment(key) => write!(f, "the {} environment variable", key),
+      Source::DotEnv(key) => write!(f, "{} in the .env file", key),
+      Source::ConfigFile(path) => write!(f, "the config file {}", path.display()),
+    }
+  }
+}
+
+// Keys of a config file, e.g. {"backend": "postgres", "database_url": "postgres://..."}
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+  backend: Option<String>,
+  database_url: Option<String>,
+}
+
+// Database to connect to, along with where each part of it was given
+#[derive(Debug)]
+pub struct Settings {
+  pub backend: Option<(Backend, Source)>,
+  pub database_url: Option<(String, Source)>,
+}
+
+impl Settings {
+  // Resolves the settings from the command line arguments, then the environment, then a
+  // .env file and last a config file
+  pub fn resolve(
+    backend_flag: Option<&str>,
+    database_url_flag: Option<&str>,
+  ) -> Result<Self, String> {
+    let config = read_config()?;
+    // The environment is read before the .env file is loaded into it, to tell them apart
+    let environment = [BACKEND_KEY, DATABASE_URL_KEY]
+      .iter()
+      .filter_map(|key| env::var(key).ok().map(|value| (*key, value)))
+      .collect::<HashMap<_, _>>();
+    let backend = match backend_flag {
+      Some(name) => Some((name.to_string(), Source::Flag("backend"))),
+      None => lookup(&environment, BACKEND_KEY).or_else(|| {
+        config.as_ref().and_then(|(config, path)| {
+          config
+            .backend
+            .clone()
+            .map(|name| (name, Source::ConfigFile(path.clone())))
+        })
+      }),
+    };
+    let backend = match backend {
+      Some((name, source)) => match Backend::from_name(name.trim().to_lowercase().as_str()) {
+        Some(backend) => Some((backend, source)),
+        None => {
+          return Err(format!(
+            "Unknown backend {} given by {}. Expected one of {}.",
+            name,
+            source,
+            Backend::NAMES.join(", ")
+          ))
+        }
+      },
+      None => None,
+    };
+    let database_url = match database_url_flag {
+      Some(url) => Some((url.to_string(), Source::Flag("database-url"))),
+      None => lookup(&environment, DATABASE_URL_KEY).or_else(|| {
+        config.and_then(|(config, path)| {
+          config
+            .database_url
+            .map(|url| (url, Source::ConfigFile(path)))
+        })
+      }),
+    };
+    Ok(Self {
+      backend,
+      database_url,
+    })
+  }
+}
+
+// Connects to the database of the settings. Without a backend or database url given, the
+// session falls back to an embedded sqlite database.
+pub async fn connect(settings: &Settings, keep: bool) -> Result<Box<dyn QuerierTrait>, String> {
+  let backend = match (&settings.backend, &settings.database_url) {
+    (Some((backend, _)), _) => *backend,
+    (None, Some(_)) => Backend::Postgres,
+    (None, None) => {
+      eprintln!(
+        "No {} is set, working in an embedded sqlite database instead.",
+        DATABASE_URL_KEY
+      );
+      Backend::Sqlite
+    }
+  };
+  match backend {
+    Backend::Postgres => {
+      let (url, source) = match &settings.database_url {
+        Some(database_url) => database_url,
+        None => {
+          return Err(format!(
+            "The postgres backend needs a database url. Set it with --database-url, the {} environment variable, a .env file or \"database_url\" in a {} config file.",
+            DATABASE_URL_KEY, CONFIG_FILE_NAME
+          ))
+        }
+      };
+      match postgres::Querier::new("postgres", url.as_str()).await {
+        Ok(db_querier) => Ok(Box::new(db_querier)),
+        Err(e) => Err(format!(
+          "Could not connect to the postgres database {}, given by {}. Error: {}",
+          redact(url),
+          source,
+          e
+        )),
+      }
+    }
+    Backend::Sqlite => {
+      let database_path = if keep {
+        Some(sqlite::session_path())
+      } else {
+        None
+      };
+      match sqlite::Querier::new(database_path.as_deref()) {
+        Ok(db_querier) => Ok(Box::new(db_querier)),
+        Err(e) => Err(format!("Could not open a sqlite database. Error: {}", e)),
+      }
+    }
+    Backend::Memory if keep => Err(
+      "The tables of the memory backend cannot be kept, they only live as long as the session."
+        .to_string(),
+    ),
+    Backend::Memory => Ok(Box::new(memory::Querier::new())),
+  }
+}
+
+// HELPERS
+// Value of a variable of the environment, or else of a .env file
+fn lookup(environment: &HashMap<&str, String>, key: &'static str) -> Option<(String, Source)> {
+  if let Some(value) = environment.get(key) {
+    return Some((value.clone(), Source::Environment(key)));
+  }
+  // Loading the .env file never overrides variables of the environment
+  dotenv::var(key)
+    .ok()
+    .map(|value| (value, Source::DotEnv(key)))
+}
+
+// Reads the config file of the working directory, or else of the home directory, if any
+fn read_config() -> Result<Option<(Config, path::PathBuf)>, String> {
+  let directories = vec![
+    env::current_dir().ok(),
+    env::var_os("HOME").map(path::PathBuf::from),
+  ];
+  let config_path = match directories
+    .into_iter()
+    .flatten()
+    .map(|directory| directory.join(CONFIG_FILE_NAME))
+    .find(|config_path| config_path.is_file())
+  {
+    Some(config_path) => config_path,
+    None => return Ok(None),
+  };
+  let error = |e: &dyn fmt::Display| {
+    format!(
+      "Could not read config file {}. Error: {}",
+      config_path.display(),
+      e
+    )
+  };
+  let contents = fs::read_to_string(&config_path).map_err(|e| error(&e))?;
+  let config = serde_json::from_str::<Config>(contents.as_str()).map_err(|e| error(&e))?;
+  Ok(Some((config, config_path)))
+}
+
+lazy_static! {
+  static ref URL_PASSWORD_RE: Regex = Regex::new(r"^(\w+://[^:/@]*:)[^@]*@").unwrap();
+  static ref PARAMETER_PASSWORD_RE: Regex = Regex::new(r"(password\s*=\s*)('[^']*'|\S+)").unwrap();
+}
+
+// Hides the password of a database url, which is either a url or key=value parameters
+fn redact(url: &str) -> String {
+  let url = URL_PASSWORD_RE.replace(url, "${1}***@");
+  PARAMETER_PASSWORD_RE
+    .replace_all(&url, "${1}***")
+    .into_owned()
+}
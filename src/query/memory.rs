@@ -0,0 +1,366 @@
+pub mod engine;
+pub mod parser;
+
+use crate::file;
+use crate::querier::{self, QuerierTrait, StoreError};
+use crate::table;
+use crate::types::constraint::{Check, Constraint};
+use crate::types::{self, value::Value};
+use async_trait::async_trait;
+use parser::Statement;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::{fs, io};
+
+// Holds the imported tables in memory and runs a subset of SQL on them itself, so that no
+// database is needed at all. Tables only live as long as the session.
+#[derive(Debug, Default)]
+pub struct Querier {
+  tables: Mutex<engine::Tables>,
+}
+
+impl Querier {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // Reads the records of a csv file into the rows of a table, checking them against the
+  // types and constraints of their columns as a database would
+  fn read(
+    &self,
+    table_path: &str,
+    table_name: &str,
+    table: &table::Table,
+    table_dialect: &file::Dialect,
+  ) -> Result<table::Rows, StoreError> {
+    let columns = engine::columns_of(table, table_name);
+    let checks = table
+      .header
+      .iter()
+      .zip(table.constraints.iter())
+      .flat_map(|((column_name, _), constraints)| {
+        constraints
+          .iter()
+          .filter_map(move |constraint| match constraint {
+            Constraint::Check(check) => Some((column_name, constraint, check)),
+            _ => None,
+          })
+      })
+      .map(|(column_name, constraint, check)| {
+        let expression = match check {
+          Check::Comparison(operator, literal) => {
+            format!("{} {} {}", column_name, operator, literal)
+          }
+          Check::Expression(expression) => expression.clone(),
+        };
+        match parser::parse_expression(expression.as_str()) {
+          Ok(expr) => Ok((constraint.to_sql(column_name), expr)),
+          Err(e) => Err(format!(
+            "{} of column {} is not supported by the memory backend, {}.",
+            constraint.to_sql(column_name),
+            column_name,
+            e
+          )),
+        }
+      })
+      .collect::<Result<Vec<_>, String>>()?;
+
+    let file = fs::File::open(table_path)?;
+    let mut reader = file::Reader::new(io::BufReader::new(file), table_dialect);
+    if table_dialect.header {
+      reader.read_record()?;
+    }
+    let mut seen = vec![HashSet::new(); table.header.len()];
+    let mut rows = table::Rows::new();
+    while let Some(record) = reader.read_record()? {
+      let values = reader.values(record);
+      file::check_record(&values, table, &seen).map_err(|reason| {
+        format!(
+          "Row on line {} does not fit, {}.",
+          reader.record_line(),
+          reason
+        )
+      })?;
+      let row = table
+        .header
+        .iter()
+        .zip(values.iter())
+        .map(|((_, column_type), value)| match value {
+          Some(value) => types::memory::to_value(column_type, value),
+          None => Value::Null,
+        })
+        .collect::<table::Row>();
+      for (check, expr) in checks.iter() {
+        let scope_rows = [&row];
+        let scope = engine::Scope::row(&columns, &scope_rows);
+        if engine::truth(&engine::evaluate(expr, &scope)?)? == Some(false) {
+          return Err(
+            format!(
+              "Row on line {} does not fit, it violates {}.",
+              reader.record_line(),
+              check
+            )
+            .into(),
+          );
+        }
+      }
+      file::take_unique_values(values, table, &mut seen);
+      rows.push(row);
+    }
+    Ok(rows)
+  }
+}
+
+#[async_trait]
+impl QuerierTrait for Querier {
+  async fn store(
+    &self,
+    table_path: &str,
+    table_name: &str,
+    table_header: table::Header,
+    table_constraints: &table::Constraints,
+    table_dialect: &file::Dialect,
+  ) -> Result<(), StoreError> {
+    let table_name = querier::normalize_name(table_name);
+    if self.tables.lock().unwrap().contains_key(&table_name) {
+      return Err(format!("relation \"{}\" already exists", table_name).into());
+    }
+    // Unquoted column names are lower cased, as postgres does when the table is created
+    let table_header = table_header
+      .into_iter()
+      .map(|(column_name, column_type)| (querier::normalize_name(&column_name), column_type))
+      .collect::<table::Header>();
+    let mut table = table::Table::with_header(table_header);
+    table.constraints = table_constraints.clone();
+    let rows = self.read(table_path, &table_name, &table, table_dialect)?;
+    let mut stored = table::Table::new(table.header, rows);
+    stored.constraints = table.constraints;
+    self.tables.lock().unwrap().insert(table_name, stored);
+    Ok(())
+  }
+
+  async fn query(&self, query_statement: &str) -> Result<Option<table::Table>, querier::Error> {
+    let statement = parser::parse(query_statement)?;
+    let mut tables = self.tables.lock().unwrap();
+    match statement {
+      Statement::Select(select) => {
+        let table = engine::select(&select, &tables)?;
+        if table.rows.is_empty() {
+          Ok(None)
+        } else {
+          Ok(Some(table))
+        }
+      }
+      Statement::CreateTableAs(name, if_not_exists, select) => {
+        if tables.contains_key(&name) {
+          if if_not_exists {
+            return Ok(None);
+          }
+          return Err(format!("relation \"{}\" already exists", name).into());
+        }
+        let table = engine::select(&select, &tables)?;
+        tables.insert(name, table);
+        Ok(None)
+      }
+      Statement::DropTable(names, if_exists) => {
+        if let Some(name) = names.iter().find(|name| !tables.contains_key(*name)) {
+          if !if_exists {
+            return Err(format!("table \"{}\" does not exist", name).into());
+          }
+        }
+        for name in names {
+          tables.remove(&name);
+        }
+        Ok(None)
+      }
+    }
+  }
+
+  async fn load(
+    &self,
+    table_name: &str,
+    num_rows: Option<usize>,
+  ) -> Result<Option<table::Table>, querier::Error> {
+    let query = match num_rows {
+      Some(num_rows) => format!("SELECT * FROM {} LIMIT {}", table_name, num_rows),
+      None => format!("SELECT * FROM {}", table_name),
+    };
+    self.query(query.as_str()).await
+  }
+
+  async fn drop(&self, table_name: &str) -> Result<(), querier::Error> {
+    self
+      .query(format!("DROP TABLE {}", table_name).as_str())
+      .await
+      .map(|_| ())
+  }
+
+  // Every table lives in memory only, so all of them were created in the session
+  async fn clean(&self) -> Vec<String> {
+    self.tables.lock().unwrap().clear();
+    Vec::new()
+  }
+
+  fn location(&self) -> String {
+    "memory".to_string()
+  }
+
+  async fn list(&self, is_verbose: bool) -> Result<Option<table::Table>, querier::Error> {
+    let tables = self.tables.lock().unwrap();
+    let mut names = tables.keys().collect::<Vec<_>>();
+    names.sort();
+    if names.is_empty() {
+      return Ok(None);
+    }
+    let mut header = vec![("Table".to_string(), "TEXT".to_string())];
+    if is_verbose {
+      header.push(("Columns".to_string(), "BIGINT".to_string()));
+      header.push(("Rows".to_string(), "BIGINT".to_string()));
+    }
+    let rows = names
+      .into_iter()
+      .map(|name| {
+        let table = &tables[name];
+        let mut row = vec![Value::Text(name.clone())];
+        if is_verbose {
+          row.push(Value::Int(table.header.len() as i64));
+          row.push(Value::Int(table.rows.len() as i64));
+        }
+        row
+      })
+      .collect();
+    Ok(Some(table::Table::new(header, rows)))
+  }
+
+  async fn info(
+    &self,
+    table_name: &str,
+    is_verbose: bool,
+  ) -> Result<Option<table::Table>, querier::Error> {
+    let table_name = querier::normalize_name(table_name);
+    let tables = self.tables.lock().unwrap();
+    let table = match tables.get(&table_name) {
+      Some(table) => table,
+      None => return Err(format!("relation \"{}\" does not exist", table_name).into()),
+    };
+    let mut header = vec![
+      ("Column".to_string(), "TEXT".to_string()),
+      ("Datatype".to_string(), "TEXT".to_string()),
+    ];
+    if is_verbose {
+      header.push(("Nullable".to_string(), "TEXT".to_string()));
+      header.push(("Constraints".to_string(), "TEXT".to_string()));
+    }
+    let rows = table
+      .header
+      .iter()
+      .enumerate()
+      .map(|(index, (column_name, column_type))| {
+        let mut row = vec![
+          Value::Text(column_name.clone()),
+          Value::Text(column_type.clone()),
+        ];
+        if is_verbose {
+          let constraints = table.constraints.get(index).map_or(&[][..], Vec::as_slice);
+          let is_nullable = constraints.iter().all(|constraint| constraint.allows(None));
+          row.push(Value::Text(is_nullable.to_string()));
+          row.push(Value::Text(
+            constraints
+              .iter()
+              .map(|constraint| constraint.to_sql(column_name))
+              .collect::<Vec<_>>()
+              .join(" "),
+          ));
+        }
+        row
+      })
+      .collect();
+    Ok(Some(table::Table::new(header, rows)))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn stores_quoted_empty_strings_apart_from_nulls() {
+    let path = std::env::temp_dir().join(format!("csvql_memory_{}.csv", std::process::id()));
+    fs::write(&path, "a,b\n\"\",z\n,y\n").unwrap();
+    let db_querier = Querier::new();
+    let header = vec![
+      ("a".to_string(), "TEXT".to_string()),
+      ("b".to_string(), "TEXT".to_string()),
+    ];
+    let constraints = vec![vec![Constraint::NotNull], Vec::new()];
+    let stored = db_querier
+      .store(
+        path.to_str().unwrap(),
+        "t",
+        header.clone(),
+        &Vec::new(),
+        &file::Dialect::default(),
+      )
+      .await;
+    let rejected = db_querier
+      .store(
+        path.to_str().unwrap(),
+        "u",
+        header,
+        &constraints,
+        &file::Dialect::default(),
+      )
+      .await;
+    fs::remove_file(&path).unwrap();
+    stored.unwrap();
+    assert_eq!(
+      rejected.unwrap_err().to_string(),
+      "Row on line 3 does not fit, null value in column a violates NOT NULL."
+    );
+    let table = db_querier.query("SELECT a FROM t").await.unwrap().unwrap();
+    assert_eq!(
+      table.rows,
+      vec![vec![Value::Text(String::new())], vec![Value::Null]]
+    );
+  }
+
+  #[tokio::test]
+  async fn creates_and_drops_tables() {
+    let db_querier = Querier::new();
+    let run = |statement: &'static str| db_querier.query(statement);
+    run("CREATE TABLE t AS SELECT 1 AS a, 'x' AS b")
+      .await
+      .unwrap();
+    run("CREATE TABLE IF NOT EXISTS t AS SELECT 2 AS a")
+      .await
+      .unwrap();
+    assert_eq!(
+      run("CREATE TABLE t AS SELECT 2 AS a")
+        .await
+        .unwrap_err()
+        .to_string(),
+      "relation \"t\" already exists"
+    );
+    run("CREATE TABLE u AS SELECT a + 1 AS a FROM t")
+      .await
+      .unwrap();
+    let table = run("SELECT * FROM u").await.unwrap().unwrap();
+    assert_eq!(table.header, vec![("a".to_string(), "BIGINT".to_string())]);
+    assert_eq!(table.rows, vec![vec![Value::Int(2)]]);
+    let list = db_querier.list(false).await.unwrap().unwrap();
+    assert_eq!(
+      list.rows,
+      vec![
+        vec![Value::Text("t".to_string())],
+        vec![Value::Text("u".to_string())]
+      ]
+    );
+    run("DROP TABLE t, u").await.unwrap();
+    run("DROP TABLE IF EXISTS t").await.unwrap();
+    assert_eq!(
+      run("DROP TABLE t").await.unwrap_err().to_string(),
+      "table \"t\" does not exist"
+    );
+    assert!(db_querier.list(false).await.unwrap().is_none());
+  }
+}
@@ -0,0 +1,1297 @@
+// Runs parsed queries over tables held in memory. Rows are read by nested loops and
+// grouped by hashing, which is plenty for the size of files csvql is pointed at.
+use super::parser::{BinaryOperator, Expr, From, JoinKind, Select, SelectItem, Source, AGGREGATES};
+use crate::table::{self, Table};
+use crate::types;
+use crate::types::value::Value;
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+pub type Tables = HashMap<String, Table>;
+
+// A column of the rows a query reads, along with the table, or alias, it belongs to
+#[derive(Debug, Clone)]
+pub struct Column {
+  pub table: Option<String>,
+  pub name: String,
+  pub column_type: String,
+}
+
+// Rows of one or more joined tables
+struct Relation {
+  columns: Vec<Column>,
+  rows: table::Rows,
+}
+
+// Rows an expression is evaluated on, either a single row or a group of rows that
+// aggregate functions are evaluated over
+pub struct Scope<'a> {
+  columns: &'a [Column],
+  rows: &'a [&'a table::Row],
+  is_grouped: bool,
+}
+
+impl<'a> Scope<'a> {
+  pub fn row(columns: &'a [Column], row: &'a [&'a table::Row]) -> Self {
+    Self {
+      columns,
+      rows: row,
+      is_grouped: false,
+    }
+  }
+}
+
+// Columns of a stored table, as they are read by a query
+pub fn columns_of(table: &Table, table_name: &str) -> Vec<Column> {
+  table
+    .header
+    .iter()
+    .map(|(column_name, column_type)| Column {
+      table: Some(table_name.to_string()),
+      name: column_name.clone(),
+      column_type: column_type.clone(),
+    })
+    .collect()
+}
+
+pub fn select(select: &Select, tables: &Tables) -> Result<Table, String> {
+  let relation = match &select.from {
+    Some(from) => join(from, tables)?,
+    None => Relation {
+      columns: Vec::new(),
+      rows: vec![Vec::new()],
+    },
+  };
+  let columns = relation.columns.as_slice();
+  let items = expand(&select.items, columns)?;
+
+  let mut rows = Vec::new();
+  for row in relation.rows.iter() {
+    if let Some(filter) = &select.filter {
+      if filter.has_aggregate() {
+        return Err("aggregate functions are not allowed in WHERE".to_string());
+      }
+      let row = [row];
+      if truth(&evaluate(filter, &Scope::row(columns, &row))?)? != Some(true) {
+        continue;
+      }
+    }
+    rows.push(row);
+  }
+
+  // GROUP BY may name a column of the output by its position or its alias
+  let group_by = select
+    .group_by
+    .iter()
+    .map(
+      |expr| match output_position(expr, &items, columns, false)? {
+        Some(index) => Ok(items[index].0.clone()),
+        None => Ok(expr.clone()),
+      },
+    )
+    .collect::<Result<Vec<_>, String>>()?;
+  let is_grouped = !group_by.is_empty()
+    || select.having.is_some()
+    || items.iter().any(|(expr, _)| expr.has_aggregate())
+    || select.order_by.iter().any(|(expr, _)| expr.has_aggregate());
+  let groups = if !is_grouped {
+    rows.into_iter().map(|row| vec![row]).collect::<Vec<_>>()
+  } else if group_by.is_empty() {
+    vec![rows]
+  } else {
+    let mut positions: HashMap<Vec<String>, usize> = HashMap::new();
+    let mut groups: Vec<Vec<&table::Row>> = Vec::new();
+    for row in rows {
+      let key = {
+        let row = [row];
+        let scope = Scope::row(columns, &row);
+        group_by
+          .iter()
+          .map(|expr| evaluate(expr, &scope).map(|value| key(&value)))
+          .collect::<Result<Vec<_>, String>>()?
+      };
+      match positions.get(&key) {
+        Some(&position) => groups[position].push(row),
+        None => {
+          positions.insert(key, groups.len());
+          groups.push(vec![row]);
+        }
+      }
+    }
+    groups
+  };
+  if is_grouped {
+    let grouped = group_by
+      .iter()
+      .filter_map(|expr| match expr {
+        Expr::Column(table, name) => resolve(columns, table.as_deref(), name).ok(),
+        _ => None,
+      })
+      .collect::<HashSet<_>>();
+    let exprs = items
+      .iter()
+      .map(|(expr, _)| expr)
+      .chain(select.having.iter())
+      .chain(select.order_by.iter().map(|(expr, _)| expr));
+    for expr in exprs {
+      check_grouped(expr, &group_by, &grouped, columns)?;
+    }
+  }
+
+  let mut output = Vec::new();
+  for group in groups.iter() {
+    let scope = Scope {
+      columns,
+      rows: group.as_slice(),
+      is_grouped,
+    };
+    if let Some(having) = &select.having {
+      if truth(&evaluate(having, &scope)?)? != Some(true) {
+        continue;
+      }
+    }
+    let values = items
+      .iter()
+      .map(|(expr, _)| evaluate(expr, &scope))
+      .collect::<Result<table::Row, String>>()?;
+    let sort_keys = select
+      .order_by
+      .iter()
+      .map(
+        |(expr, _)| match output_position(expr, &items, columns, true)? {
+          Some(index) => Ok(values[index].clone()),
+          None => evaluate(expr, &scope),
+        },
+      )
+      .collect::<Result<Vec<_>, String>>()?;
+    output.push((values, sort_keys));
+  }
+
+  if select.distinct {
+    let mut seen = HashSet::new();
+    output.retain(|(values, _)| seen.insert(values.iter().map(key).collect::<Vec<_>>()));
+  }
+  // NULLs sort as if larger than any value, as in postgres
+  output.sort_by(|(_, left), (_, right)| {
+    left
+      .iter()
+      .zip(right)
+      .zip(&select.order_by)
+      .map(|((left, right), (_, descending))| {
+        let ordering = match (left.is_null(), right.is_null()) {
+          (true, true) => Ordering::Equal,
+          (true, false) => Ordering::Greater,
+          (false, true) => Ordering::Less,
+          (false, false) => compare(left, right).unwrap_or(Ordering::Equal),
+        };
+        if *descending {
+          ordering.reverse()
+        } else {
+          ordering
+        }
+      })
+      .find(|ordering| *ordering != Ordering::Equal)
+      .unwrap_or(Ordering::Equal)
+  });
+  let rows = output
+    .into_iter()
+    .skip(select.offset)
+    .take(select.limit.unwrap_or(usize::MAX))
+    .map(|(values, _)| values)
+    .collect::<table::Rows>();
+
+  let header = items
+    .iter()
+    .enumerate()
+    .map(|(index, (expr, name))| {
+      let column_type = static_type(expr, columns).unwrap_or_else(|| {
+        rows
+          .iter()
+          .map(|row| &row[index])
+          .find(|value| !value.is_null())
+          .map_or("TEXT".to_string(), types::memory::type_of)
+      });
+      (name.clone(), column_type)
+    })
+    .collect::<table::Header>();
+  Ok(Table::new(header, rows))
+}
+
+// Evaluates an expression. Column references of a group read its first row, since they are
+// checked to be grouped by beforehand.
+pub fn evaluate(expr: &Expr, scope: &Scope) -> Result<Value, String> {
+  match expr {
+    Expr::Literal(value) => Ok(value.clone()),
+    Expr::Column(table, name) => {
+      let index = resolve(scope.columns, table.as_deref(), name)?;
+      Ok(
+        scope
+          .rows
+          .first()
+          .map_or(Value::Null, |row| row[index].clone()),
+      )
+    }
+    Expr::Wildcard => Err("* is only allowed within count(*)".to_string()),
+    Expr::Not(operand) => Ok(match truth(&evaluate(operand, scope)?)? {
+      Some(value) => Value::Bool(!value),
+      None => Value::Null,
+    }),
+    Expr::Negate(operand) => arithmetic(
+      &Value::Int(0),
+      BinaryOperator::Minus,
+      &evaluate(operand, scope)?,
+    ),
+    Expr::Binary(left, BinaryOperator::And, right) => {
+      let left = truth(&evaluate(left, scope)?)?;
+      if left == Some(false) {
+        return Ok(Value::Bool(false));
+      }
+      Ok(match (left, truth(&evaluate(right, scope)?)?) {
+        (_, Some(false)) => Value::Bool(false),
+        (Some(true), Some(true)) => Value::Bool(true),
+        _ => Value::Null,
+      })
+    }
+    Expr::Binary(left, BinaryOperator::Or, right) => {
+      let left = truth(&evaluate(left, scope)?)?;
+      if left == Some(true) {
+        return Ok(Value::Bool(true));
+      }
+      Ok(match (left, truth(&evaluate(right, scope)?)?) {
+        (_, Some(true)) => Value::Bool(true),
+        (Some(false), Some(false)) => Value::Bool(false),
+        _ => Value::Null,
+      })
+    }
+    Expr::Binary(left, operator, right) => {
+      let left = evaluate(left, scope)?;
+      let right = evaluate(right, scope)?;
+      let comparison = |matches: fn(Ordering) -> bool| match compare(&left, &right) {
+        Some(ordering) => Value::Bool(matches(ordering)),
+        None => Value::Null,
+      };
+      Ok(match operator {
+        BinaryOperator::Eq => comparison(|ordering| ordering == Ordering::Equal),
+        BinaryOperator::NotEq => comparison(|ordering| ordering != Ordering::Equal),
+        BinaryOperator::Lt => comparison(|ordering| ordering == Ordering::Less),
+        BinaryOperator::LtEq => comparison(|ordering| ordering != Ordering::Greater),
+        BinaryOperator::Gt => comparison(|ordering| ordering == Ordering::Greater),
+        BinaryOperator::GtEq => comparison(|ordering| ordering != Ordering::Less),
+        BinaryOperator::Concat if left.is_null() || right.is_null() => Value::Null,
+        BinaryOperator::Concat => Value::Text(format!("{}{}", left, right)),
+        _ => arithmetic(&left, *operator, &right)?,
+      })
+    }
+    Expr::IsNull(operand, negated) => {
+      Ok(Value::Bool(evaluate(operand, scope)?.is_null() != *negated))
+    }
+    Expr::InList(operand, list, negated) => {
+      let value = evaluate(operand, scope)?;
+      let mut result = Some(false);
+      for element in list {
+        match compare(&value, &evaluate(element, scope)?) {
+          Some(Ordering::Equal) => {
+            result = Some(true);
+            break;
+          }
+          None => result = None,
+          _ => (),
+        }
+      }
+      Ok(match result {
+        Some(result) => Value::Bool(result != *negated),
+        None => Value::Null,
+      })
+    }
+    Expr::Between(operand, low, high, negated) => {
+      let value = evaluate(operand, scope)?;
+      let above =
+        compare(&value, &evaluate(low, scope)?).map(|ordering| ordering != Ordering::Less);
+      let below =
+        compare(&value, &evaluate(high, scope)?).map(|ordering| ordering != Ordering::Greater);
+      Ok(match (above, below) {
+        (Some(false), _) | (_, Some(false)) => Value::Bool(*negated),
+        (Some(true), Some(true)) => Value::Bool(!*negated),
+        _ => Value::Null,
+      })
+    }
+    Expr::Like(operand, pattern, negated, case_insensitive) => {
+      let value = evaluate(operand, scope)?;
+      let pattern = evaluate(pattern, scope)?;
+      if value.is_null() || pattern.is_null() {
+        return Ok(Value::Null);
+      }
+      let matches =
+        like_regex(&pattern.to_string(), *case_insensitive)?.is_match(&value.to_string());
+      Ok(Value::Bool(matches != *negated))
+    }
+    Expr::Function(name, arguments, distinct) if AGGREGATES.contains(&name.as_str()) => {
+      if !scope.is_grouped {
+        return Err(format!("aggregate function {} is not allowed here", name));
+      }
+      aggregate(name, arguments, *distinct, scope)
+    }
+    Expr::Function(name, arguments, _) => {
+      let values = arguments
+        .iter()
+        .map(|argument| evaluate(argument, scope))
+        .collect::<Result<Vec<_>, String>>()?;
+      function(name, values)
+    }
+    Expr::Cast(operand, column_type) => cast(evaluate(operand, scope)?, column_type),
+    Expr::Case(operand, whens, otherwise) => {
+      let operand = match operand {
+        Some(operand) => Some(evaluate(operand, scope)?),
+        None => None,
+      };
+      for (when, then) in whens {
+        let when = evaluate(when, scope)?;
+        let is_match = match &operand {
+          Some(operand) => compare(operand, &when) == Some(Ordering::Equal),
+          None => truth(&when)? == Some(true),
+        };
+        if is_match {
+          return evaluate(then, scope);
+        }
+      }
+      match otherwise {
+        Some(otherwise) => evaluate(otherwise, scope),
+        None => Ok(Value::Null),
+      }
+    }
+  }
+}
+
+// Truth value of a condition, where NULL is unknown
+pub fn truth(value: &Value) -> Result<Option<bool>, String> {
+  match value {
+    Value::Bool(value) => Ok(Some(*value)),
+    Value::Null => Ok(None),
+    other => Err(format!(
+      "argument of a condition must be a boolean, not {}",
+      types::memory::type_of(other)
+    )),
+  }
+}
+
+// HELPERS
+fn join(from: &From, tables: &Tables) -> Result<Relation, String> {
+  let mut relation = source(&from.source, tables)?;
+  for join in from.joins.iter() {
+    let right = source(&join.source, tables)?;
+    let columns = relation
+      .columns
+      .iter()
+      .chain(right.columns.iter())
+      .cloned()
+      .collect::<Vec<_>>();
+    if join.on.as_ref().is_some_and(Expr::has_aggregate) {
+      return Err("aggregate functions are not allowed in JOIN conditions".to_string());
+    }
+    let mut rows = Vec::new();
+    for left_row in relation.rows.iter() {
+      let mut is_matched = false;
+      for right_row in right.rows.iter() {
+        let row = left_row
+          .iter()
+          .chain(right_row.iter())
+          .cloned()
+          .collect::<table::Row>();
+        let is_match = match (&join.kind, &join.on) {
+          (JoinKind::Cross, _) | (_, None) => true,
+          (_, Some(on)) => {
+            let rows = [&row];
+            truth(&evaluate(on, &Scope::row(&columns, &rows))?)? == Some(true)
+          }
+        };
+        if is_match {
+          is_matched = true;
+          rows.push(row);
+        }
+      }
+      if join.kind == JoinKind::Left && !is_matched {
+        let row = left_row
+          .iter()
+          .cloned()
+          .chain(right.columns.iter().map(|_| Value::Null))
+          .collect();
+        rows.push(row);
+      }
+    }
+    relation = Relation { columns, rows };
+  }
+  Ok(relation)
+}
+
+fn source(source: &Source, tables: &Tables) -> Result<Relation, String> {
+  match source {
+    Source::Table(name, alias) => match tables.get(name) {
+      Some(table) => Ok(Relation {
+        columns: columns_of(table, alias.as_ref().unwrap_or(name)),
+        rows: table.rows.clone(),
+      }),
+      None => Err(format!("relation \"{}\" does not exist", name)),
+    },
+    Source::Subquery(subquery, alias) => {
+      let table = select(subquery, tables)?;
+      let alias = alias.clone().unwrap_or_default();
+      Ok(Relation {
+        columns: columns_of(&table, &alias),
+        rows: table.rows,
+      })
+    }
+  }
+}
+
+// Expressions of the select list along with their names, standing in for * and table.*
+fn expand(items: &[SelectItem], columns: &[Column]) -> Result<Vec<(Expr, String)>, String> {
+  let mut expanded = Vec::new();
+  for item in items {
+    match item {
+      SelectItem::Wildcard(table) => {
+        let matching = columns
+          .iter()
+          .filter(|column| table.is_none() || column.table == *table)
+          .map(|column| {
+            let expr = Expr::Column(column.table.clone(), column.name.clone());
+            (expr, column.name.clone())
+          })
+          .collect::<Vec<_>>();
+        if let (Some(table), true) = (table, matching.is_empty()) {
+          return Err(format!("missing FROM-clause entry for table \"{}\"", table));
+        }
+        if table.is_none() && columns.is_empty() {
+          return Err("SELECT * with no tables specified is not valid".to_string());
+        }
+        expanded.extend(matching);
+      }
+      SelectItem::Expr(expr, alias) => {
+        let name = alias.clone().unwrap_or_else(|| output_name(expr));
+        expanded.push((expr.clone(), name));
+      }
+    }
+  }
+  Ok(expanded)
+}
+
+// Name of an output column without an alias, as postgres names it
+fn output_name(expr: &Expr) -> String {
+  match expr {
+    Expr::Column(_, name) | Expr::Function(name, _, _) => name.clone(),
+    Expr::Cast(operand, _) => output_name(operand),
+    Expr::Case(_, _, _) => "case".to_string(),
+    _ => "?column?".to_string(),
+  }
+}
+
+// Output column an ORDER BY or GROUP BY expression stands for, given by its position or
+// its name. Names of the output take precedence over those of the input in ORDER BY, and
+// the other way around in GROUP BY.
+fn output_position(
+  expr: &Expr,
+  items: &[(Expr, String)],
+  columns: &[Column],
+  prefer_output: bool,
+) -> Result<Option<usize>, String> {
+  match expr {
+    Expr::Literal(Value::Int(position)) => {
+      if *position < 1 || *position as usize > items.len() {
+        return Err(format!("position {} is not in select list", position));
+      }
+      Ok(Some(*position as usize - 1))
+    }
+    Expr::Column(None, name)
+      if prefer_output || !columns.iter().any(|column| column.name == *name) =>
+    {
+      Ok(items.iter().position(|(_, item_name)| item_name == name))
+    }
+    _ => Ok(None),
+  }
+}
+
+// Checks that an expression of a grouped query only reads columns within aggregates, or
+// ones it is grouped by
+fn check_grouped(
+  expr: &Expr,
+  group_by: &[Expr],
+  grouped: &HashSet<usize>,
+  columns: &[Column],
+) -> Result<(), String> {
+  if group_by.contains(expr) {
+    return Ok(());
+  }
+  match expr {
+    Expr::Function(name, _, _) if AGGREGATES.contains(&name.as_str()) => Ok(()),
+    Expr::Column(table, name) => {
+      let index = resolve(columns, table.as_deref(), name)?;
+      if grouped.contains(&index) {
+        Ok(())
+      } else {
+        Err(format!(
+          "column \"{}\" must appear in the GROUP BY clause or be used in an aggregate function",
+          qualified_name(table.as_deref(), name)
+        ))
+      }
+    }
+    _ => expr
+      .children()
+      .into_iter()
+      .try_for_each(|child| check_grouped(child, group_by, grouped, columns)),
+  }
+}
+
+fn resolve(columns: &[Column], table: Option<&str>, name: &str) -> Result<usize, String> {
+  let mut matching = columns.iter().enumerate().filter(|(_, column)| {
+    column.name == name && (table.is_none() || column.table.as_deref() == table)
+  });
+  match (matching.next(), matching.next()) {
+    (Some((index, _)), None) => Ok(index),
+    (Some(_), Some(_)) => Err(format!(
+      "column reference \"{}\" is ambiguous",
+      qualified_name(table, name)
+    )),
+    (None, _) => match table {
+      Some(table)
+        if !columns
+          .iter()
+          .any(|column| column.table.as_deref() == Some(table)) =>
+      {
+        Err(format!("missing FROM-clause entry for table \"{}\"", table))
+      }
+      _ => Err(format!(
+        "column \"{}\" does not exist",
+        qualified_name(table, name)
+      )),
+    },
+  }
+}
+
+fn qualified_name(table: Option<&str>, name: &str) -> String {
+  match table {
+    Some(table) => format!("{}.{}", table, name),
+    None => name.to_string(),
+  }
+}
+
+// Type of an output column that is known regardless of its values
+fn static_type(expr: &Expr, columns: &[Column]) -> Option<String> {
+  match expr {
+    Expr::Column(table, name) => resolve(columns, table.as_deref(), name)
+      .ok()
+      .map(|index| columns[index].column_type.clone()),
+    Expr::Cast(_, column_type) => Some(column_type.clone()),
+    Expr::Function(name, _, _) if name == "count" => Some("BIGINT".to_string()),
+    Expr::Function(name, arguments, _) if name == "min" || name == "max" => arguments
+      .first()
+      .and_then(|argument| static_type(argument, columns)),
+    _ => None,
+  }
+}
+
+fn aggregate(
+  name: &str,
+  arguments: &[Expr],
+  distinct: bool,
+  scope: &Scope,
+) -> Result<Value, String> {
+  let argument = match (name, arguments) {
+    ("count", [Expr::Wildcard]) => return Ok(Value::Int(scope.rows.len() as i64)),
+    (_, [argument]) => argument,
+    _ => return Err(format!("function {} takes a single argument", name)),
+  };
+  let mut values = Vec::new();
+  for row in scope.rows {
+    let row = [*row];
+    let value = evaluate(argument, &Scope::row(scope.columns, &row))?;
+    if !value.is_null() {
+      values.push(value);
+    }
+  }
+  if distinct {
+    let mut seen = HashSet::new();
+    values.retain(|value| seen.insert(key(value)));
+  }
+  match name {
+    "count" => Ok(Value::Int(values.len() as i64)),
+    "sum" | "avg" => {
+      let mut values = values.into_iter();
+      let first = match values.next() {
+        Some(first) => first,
+        None => return Ok(Value::Null),
+      };
+      if number(&first).is_none() {
+        return Err(format!(
+          "function {}({}) does not exist",
+          name,
+          types::memory::type_of(&first).to_lowercase()
+        ));
+      }
+      let mut count = 1;
+      let mut sum = first;
+      for value in values {
+        count += 1;
+        // Sums of integers that overflow carry on as exact decimals
+        sum = match (&sum, &value) {
+          (Value::Int(left), Value::Int(right)) if left.checked_add(*right).is_none() => {
+            arithmetic(&to_decimal(*left as i128, 0), BinaryOperator::Plus, &value)?
+          }
+          _ => arithmetic(&sum, BinaryOperator::Plus, &value)?,
+        };
+      }
+      if name == "sum" {
+        return Ok(sum);
+      }
+      arithmetic(
+        &sum,
+        BinaryOperator::Divide,
+        &Value::Numeric(count.to_string()),
+      )
+    }
+    _ => {
+      let mut values = values.into_iter();
+      let mut extreme = match values.next() {
+        Some(first) => first,
+        None => return Ok(Value::Null),
+      };
+      for value in values {
+        let ordering = compare(&value, &extreme).unwrap_or(Ordering::Equal);
+        if (name == "min" && ordering == Ordering::Less)
+          || (name == "max" && ordering == Ordering::Greater)
+        {
+          extreme = value;
+        }
+      }
+      Ok(extreme)
+    }
+  }
+}
+
+fn function(name: &str, values: Vec<Value>) -> Result<Value, String> {
+  let text = |value: &Value, convert: fn(&str) -> String| match value {
+    Value::Null => Value::Null,
+    value => Value::Text(convert(&value.to_string())),
+  };
+  match (name, values.as_slice()) {
+    ("lower", [value]) => Ok(text(value, str::to_lowercase)),
+    ("upper", [value]) => Ok(text(value, str::to_uppercase)),
+    ("trim", [value]) => Ok(text(value, |value| value.trim().to_string())),
+    ("length", [Value::Null]) => Ok(Value::Null),
+    ("length", [value]) => Ok(Value::Int(value.to_string().chars().count() as i64)),
+    ("abs", [value]) => match compare(value, &Value::Int(0)) {
+      Some(Ordering::Less) => arithmetic(&Value::Int(0), BinaryOperator::Minus, value),
+      _ => Ok(value.clone()),
+    },
+    ("round", [value]) => round(value, 0),
+    ("round", [value, Value::Int(places)]) => round(value, *places),
+    ("coalesce", values) if !values.is_empty() => Ok(
+      values
+        .iter()
+        .find(|value| !value.is_null())
+        .cloned()
+        .unwrap_or(Value::Null),
+    ),
+    ("nullif", [left, right]) => Ok(match compare(left, right) {
+      Some(Ordering::Equal) => Value::Null,
+      _ => left.clone(),
+    }),
+    _ => Err(format!(
+      "function {}({}) is not supported by the memory backend",
+      name,
+      values
+        .iter()
+        .map(|value| types::memory::type_of(value).to_lowercase())
+        .collect::<Vec<_>>()
+        .join(", ")
+    )),
+  }
+}
+
+// Numbers are either exact, i.e. integers and decimals as a mantissa and a scale, or floats
+#[derive(Debug, Clone, Copy)]
+enum Number {
+  Exact(i128, u32),
+  Float(f64),
+}
+
+impl Number {
+  fn to_f64(self) -> f64 {
+    match self {
+      Number::Exact(mantissa, scale) => mantissa as f64 / 10f64.powi(scale as i32),
+      Number::Float(float) => float,
+    }
+  }
+}
+
+fn number(value: &Value) -> Option<Number> {
+  match value {
+    Value::Int(integer) => Some(Number::Exact(*integer as i128, 0)),
+    Value::Float(float) => Some(Number::Float(*float)),
+    Value::Numeric(digits) => parse_decimal(digits)
+      .map(|(mantissa, scale)| Number::Exact(mantissa, scale))
+      .or_else(|| digits.parse::<f64>().ok().map(Number::Float)),
+    _ => None,
+  }
+}
+
+// Reads a decimal such as -12.50 into its mantissa and scale, i.e. (-1250, 2)
+fn parse_decimal(digits: &str) -> Option<(i128, u32)> {
+  let digits = digits.trim();
+  let (negative, digits) = match digits.strip_prefix('-') {
+    Some(digits) => (true, digits),
+    None => (false, digits.strip_prefix('+').unwrap_or(digits)),
+  };
+  let (whole, fraction) = match digits.split_once('.') {
+    Some((whole, fraction)) => (whole, fraction),
+    None => (digits, ""),
+  };
+  let all_digits = format!("{}{}", whole, fraction);
+  if all_digits.is_empty() || !all_digits.chars().all(|c| c.is_ascii_digit()) {
+    return None;
+  }
+  let mantissa = all_digits.parse::<i128>().ok()?;
+  Some((
+    if negative { -mantissa } else { mantissa },
+    fraction.len() as u32,
+  ))
+}
+
+fn to_decimal(mantissa: i128, scale: u32) -> Value {
+  let digits = format!("{:0width$}", mantissa.abs(), width = scale as usize + 1);
+  let (whole, fraction) = digits.split_at(digits.len() - scale as usize);
+  let sign = if mantissa < 0 { "-" } else { "" };
+  if scale == 0 {
+    Value::Numeric(format!("{}{}", sign, whole))
+  } else {
+    Value::Numeric(format!("{}{}.{}", sign, whole, fraction))
+  }
+}
+
+// Brings a mantissa to another scale, rounding half away from zero
+fn rescale(mantissa: i128, scale: u32, new_scale: u32) -> Option<i128> {
+  if new_scale >= scale {
+    return 10i128
+      .checked_pow(new_scale - scale)
+      .and_then(|factor| mantissa.checked_mul(factor));
+  }
+  let factor = 10i128.checked_pow(scale - new_scale)?;
+  let rounded = mantissa / factor;
+  if (mantissa % factor).abs() * 2 >= factor {
+    Some(rounded + mantissa.signum())
+  } else {
+    Some(rounded)
+  }
+}
+
+// Scale of quotients of decimals, which postgres gives at least 16 digits as well
+const DIVISION_SCALE: u32 = 16;
+
+fn arithmetic(left: &Value, operator: BinaryOperator, right: &Value) -> Result<Value, String> {
+  if left.is_null() || right.is_null() {
+    return Ok(Value::Null);
+  }
+  let symbol = match operator {
+    BinaryOperator::Plus => "+",
+    BinaryOperator::Minus => "-",
+    BinaryOperator::Multiply => "*",
+    BinaryOperator::Divide => "/",
+    _ => "%",
+  };
+  let (left_number, right_number) = match (number(left), number(right)) {
+    (Some(left), Some(right)) => (left, right),
+    _ => {
+      return Err(format!(
+        "operator does not exist: {} {} {}",
+        types::memory::type_of(left).to_lowercase(),
+        symbol,
+        types::memory::type_of(right).to_lowercase()
+      ))
+    }
+  };
+  let is_zero = right_number.to_f64() == 0.0;
+  if is_zero && (operator == BinaryOperator::Divide || operator == BinaryOperator::Modulo) {
+    return Err("division by zero".to_string());
+  }
+  let out_of_range = || format!("value out of range: {} {} {}", left, symbol, right);
+  match (left, right, left_number, right_number) {
+    (Value::Int(left), Value::Int(right), _, _) => {
+      let result = match operator {
+        BinaryOperator::Plus => left.checked_add(*right),
+        BinaryOperator::Minus => left.checked_sub(*right),
+        BinaryOperator::Multiply => left.checked_mul(*right),
+        BinaryOperator::Divide => left.checked_div(*right),
+        _ => left.checked_rem(*right),
+      };
+      result.map(Value::Int).ok_or_else(out_of_range)
+    }
+    (_, _, Number::Exact(left, left_scale), Number::Exact(right, right_scale)) => {
+      let scale = left_scale.max(right_scale);
+      let result = match operator {
+        BinaryOperator::Multiply => left
+          .checked_mul(right)
+          .map(|product| (product, left_scale + right_scale)),
+        BinaryOperator::Divide => {
+          let scale = scale.max(DIVISION_SCALE);
+          // One more digit than needed, to round the last one
+          rescale(left, left_scale, scale + right_scale + 1)
+            .map(|dividend| dividend / right)
+            .and_then(|quotient| rescale(quotient, scale + 1, scale))
+            .map(|quotient| (quotient, scale))
+        }
+        _ => {
+          let left = rescale(left, left_scale, scale);
+          let right = rescale(right, right_scale, scale);
+          match (left, right) {
+            (Some(left), Some(right)) => match operator {
+              BinaryOperator::Plus => left.checked_add(right),
+              BinaryOperator::Minus => left.checked_sub(right),
+              _ => left.checked_rem(right),
+            }
+            .map(|result| (result, scale)),
+            _ => None,
+          }
+        }
+      };
+      match result {
+        Some((mantissa, scale)) => Ok(to_decimal(mantissa, scale)),
+        None => Err(out_of_range()),
+      }
+    }
+    (_, _, left, right) => {
+      let (left, right) = (left.to_f64(), right.to_f64());
+      Ok(Value::Float(match operator {
+        BinaryOperator::Plus => left + right,
+        BinaryOperator::Minus => left - right,
+        BinaryOperator::Multiply => left * right,
+        BinaryOperator::Divide => left / right,
+        _ => left % right,
+      }))
+    }
+  }
+}
+
+fn round(value: &Value, places: i64) -> Result<Value, String> {
+  match number(value) {
+    _ if value.is_null() => Ok(Value::Null),
+    Some(Number::Exact(mantissa, scale)) => {
+      let places = places.clamp(0, 38) as u32;
+      match rescale(mantissa, scale, places) {
+        Some(mantissa) if matches!(value, Value::Int(_)) && places == 0 => {
+          Ok(Value::Int(mantissa as i64))
+        }
+        Some(mantissa) => Ok(to_decimal(mantissa, places)),
+        None => Err(format!("value out of range: round({})", value)),
+      }
+    }
+    Some(Number::Float(float)) => {
+      let factor = 10f64.powi(places as i32);
+      Ok(Value::Float((float * factor).round() / factor))
+    }
+    None => Err(format!(
+      "function round({}) does not exist",
+      types::memory::type_of(value).to_lowercase()
+    )),
+  }
+}
+
+// Compares two values, None being unknown as either of them is NULL. Text is compared with
+// numbers and booleans as the literal it would be read as.
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+  if left.is_null() || right.is_null() {
+    return None;
+  }
+  let as_number = |value: &Value, other: &Value| match value {
+    Value::Text(text) if number(other).is_some() => {
+      number(&Value::Numeric(text.trim().to_string()))
+    }
+    _ => number(value),
+  };
+  if let (Some(left), Some(right)) = (as_number(left, right), as_number(right, left)) {
+    let exact = match (left, right) {
+      (Number::Exact(left, left_scale), Number::Exact(right, right_scale)) => {
+        let scale = left_scale.max(right_scale);
+        rescale(left, left_scale, scale)
+          .zip(rescale(right, right_scale, scale))
+          .map(|(left, right)| left.cmp(&right))
+      }
+      _ => None,
+    };
+    return Some(exact.unwrap_or_else(|| left.to_f64().total_cmp(&right.to_f64())));
+  }
+  let as_bool = |value: &Value| match value {
+    Value::Bool(value) => Some(*value),
+    Value::Text(text) => match text.trim().to_lowercase().as_str() {
+      "true" | "t" | "yes" | "y" | "on" | "1" => Some(true),
+      "false" | "f" | "no" | "n" | "off" | "0" => Some(false),
+      _ => None,
+    },
+    _ => None,
+  };
+  if matches!(left, Value::Bool(_)) || matches!(right, Value::Bool(_)) {
+    if let (Some(left), Some(right)) = (as_bool(left), as_bool(right)) {
+      return Some(left.cmp(&right));
+    }
+  }
+  Some(left.to_string().cmp(&right.to_string()))
+}
+
+// Key that values equal to each other share, for grouping and removing duplicates
+fn key(value: &Value) -> String {
+  match (value, number(value)) {
+    (Value::Null, _) => String::new(),
+    (_, Some(Number::Exact(mantissa, scale))) => {
+      let digits = to_decimal(mantissa, scale).to_string();
+      let digits = if digits.contains('.') {
+        digits
+          .trim_end_matches('0')
+          .trim_end_matches('.')
+          .to_string()
+      } else {
+        digits
+      };
+      format!("n{}", digits)
+    }
+    (_, Some(Number::Float(float))) if float.fract() == 0.0 && float.abs() < 1e18 => {
+      format!("n{}", float as i128)
+    }
+    (_, Some(Number::Float(float))) => format!("n{}", float),
+    (Value::Bool(value), _) => format!("b{}", value),
+    (value, _) => format!("t{}", value),
+  }
+}
+
+// LIKE patterns match the whole value, where % is any text and _ any character
+fn like_regex(pattern: &str, case_insensitive: bool) -> Result<Regex, String> {
+  let mut expression = String::from(if case_insensitive { "(?is)^" } else { "(?s)^" });
+  let mut chars = pattern.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '%' => expression.push_str(".*"),
+      '_' => expression.push('.'),
+      '\\' => {
+        if let Some(escaped) = chars.next() {
+          expression.push_str(&regex::escape(&escaped.to_string()));
+        }
+      }
+      c => expression.push_str(&regex::escape(&c.to_string())),
+    }
+  }
+  expression.push('$');
+  Regex::new(&expression).map_err(|e| e.to_string())
+}
+
+// Casts a value to a type, e.g. for CAST(price AS INTEGER) or '2020-01-31'::date
+fn cast(value: Value, column_type: &str) -> Result<Value, String> {
+  if value.is_null() {
+    return Ok(Value::Null);
+  }
+  let base_type = column_type.split('(').next().unwrap_or("");
+  let is_integer = ["SMALLINT", "INTEGER", "BIGINT", "OID"].contains(&base_type);
+  // Numbers are rounded to integers, rather than rejected for their fraction
+  let value = match (is_integer, number(&value)) {
+    (true, Some(Number::Exact(mantissa, scale))) => match rescale(mantissa, scale, 0) {
+      Some(mantissa) => to_decimal(mantissa, 0),
+      None => value,
+    },
+    (true, Some(Number::Float(float))) => Value::Numeric(format!("{}", float.round())),
+    _ => value,
+  };
+  let text = value.to_string();
+  types::postgres::check_value(column_type, &text)
+    .map_err(|reason| format!("cannot cast {} to {}: {}", text, column_type, reason))?;
+  let cast = types::memory::to_value(column_type, &text);
+  // Decimals are rounded to the scale of their type
+  match (&cast, column_type.split_once(',')) {
+    (Value::Numeric(digits), Some((_, scale))) => {
+      let scale = scale
+        .trim_end_matches(')')
+        .trim()
+        .parse::<u32>()
+        .unwrap_or(0);
+      match parse_decimal(digits).and_then(|(mantissa, old)| rescale(mantissa, old, scale)) {
+        Some(mantissa) => Ok(to_decimal(mantissa, scale)),
+        None => Ok(cast),
+      }
+    }
+    _ => Ok(cast),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::parser::{self, Statement};
+  use super::*;
+
+  fn table(header: &[(&str, &str)], rows: Vec<Vec<Value>>) -> Table {
+    let header = header
+      .iter()
+      .map(|(name, column_type)| (name.to_string(), column_type.to_string()))
+      .collect();
+    Table::new(header, rows)
+  }
+
+  fn tables() -> Tables {
+    let text = |text: &str| Value::Text(text.to_string());
+    let numeric = |digits: &str| Value::Numeric(digits.to_string());
+    let mut tables = Tables::new();
+    tables.insert(
+      "fruit".to_string(),
+      table(
+        &[
+          ("id", "INTEGER"),
+          ("name", "TEXT"),
+          ("price", "NUMERIC"),
+          ("cat", "TEXT"),
+        ],
+        vec![
+          vec![Value::Int(1), text("apple"), numeric("1.50"), text("a")],
+          vec![Value::Int(2), text("pear"), Value::Null, text("b")],
+          vec![Value::Int(3), text("fig"), numeric("3"), text("a")],
+          vec![Value::Int(4), text("kiwi"), numeric("2.25"), Value::Null],
+        ],
+      ),
+    );
+    tables.insert(
+      "cats".to_string(),
+      table(
+        &[("cat", "TEXT"), ("kind", "TEXT")],
+        vec![
+          vec![text("a"), text("tree")],
+          vec![text("b"), text("vine")],
+          vec![text("c"), text("bush")],
+        ],
+      ),
+    );
+    tables
+  }
+
+  fn query(statement: &str) -> Result<Table, String> {
+    match parser::parse(statement)? {
+      Statement::Select(query) => select(&query, &tables()),
+      statement => panic!("Expected a SELECT but got {:?}", statement),
+    }
+  }
+
+  // Rows of the result of a query as displayed, with NULL for NULL values
+  fn rows(statement: &str) -> Vec<Vec<String>> {
+    query(statement)
+      .unwrap()
+      .rows
+      .iter()
+      .map(|row| {
+        row
+          .iter()
+          .map(|value| match value {
+            Value::Null => "NULL".to_string(),
+            value => value.to_string(),
+          })
+          .collect()
+      })
+      .collect()
+  }
+
+  fn column(statement: &str) -> Vec<String> {
+    rows(statement)
+      .into_iter()
+      .map(|row| row.into_iter().next().unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn selects_filters_and_orders() {
+    assert_eq!(
+      column("SELECT name FROM fruit WHERE id > 1 ORDER BY name DESC LIMIT 2"),
+      vec!["pear", "kiwi"]
+    );
+    assert_eq!(
+      column("SELECT id FROM fruit ORDER BY id LIMIT 2 OFFSET 1"),
+      vec!["2", "3"]
+    );
+    assert_eq!(
+      column("SELECT id * 10 AS x FROM fruit ORDER BY x DESC LIMIT 1"),
+      vec!["40"]
+    );
+    let result = query("SELECT *, price * 2 AS double FROM fruit WHERE id = 1").unwrap();
+    assert_eq!(
+      result
+        .header
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>(),
+      vec!["id", "name", "price", "cat", "double"]
+    );
+    assert_eq!(
+      rows("SELECT *, price * 2 AS double FROM fruit WHERE id = 1"),
+      vec![vec!["1", "apple", "1.50", "a", "3.00"]]
+    );
+  }
+
+  #[test]
+  fn sorts_nulls_last_unless_descending() {
+    assert_eq!(
+      column("SELECT id FROM fruit ORDER BY price"),
+      vec!["1", "4", "3", "2"]
+    );
+    assert_eq!(
+      column("SELECT id FROM fruit ORDER BY price DESC"),
+      vec!["2", "3", "4", "1"]
+    );
+  }
+
+  #[test]
+  fn computes_exact_decimals() {
+    assert_eq!(
+      rows("SELECT 1.50 + 2.25, 7 / 2, 7 % 3, 4.50 / 2, 2 * 0.5"),
+      vec![vec!["3.75", "3", "1", "2.2500000000000000", "1.0"]]
+    );
+    assert_eq!(query("SELECT 1 / 0").unwrap_err(), "division by zero");
+  }
+
+  #[test]
+  fn groups_and_aggregates() {
+    assert_eq!(
+      rows(
+        "SELECT cat, count(*), count(price), sum(price), avg(price), min(name), max(name) FROM fruit GROUP BY cat ORDER BY cat"
+      ),
+      vec![
+        vec!["a", "2", "2", "4.50", "2.2500000000000000", "apple", "fig"],
+        vec!["b", "1", "0", "NULL", "NULL", "pear", "pear"],
+        vec!["NULL", "1", "1", "2.25", "2.2500000000000000", "kiwi", "kiwi"],
+      ]
+    );
+    assert_eq!(
+      rows("SELECT count(*), sum(id), count(DISTINCT cat) FROM fruit"),
+      vec![vec!["4", "10", "2"]]
+    );
+    assert_eq!(
+      rows("SELECT count(*), sum(id) FROM fruit WHERE id > 10"),
+      vec![vec!["0", "NULL"]]
+    );
+    assert_eq!(
+      column("SELECT cat FROM fruit GROUP BY 1 HAVING count(*) > 1"),
+      vec!["a"]
+    );
+  }
+
+  #[test]
+  fn rejects_ungrouped_columns() {
+    assert_eq!(
+      query("SELECT name, count(*) FROM fruit").unwrap_err(),
+      "column \"name\" must appear in the GROUP BY clause or be used in an aggregate function"
+    );
+    assert_eq!(
+      query("SELECT name FROM fruit WHERE count(*) > 1").unwrap_err(),
+      "aggregate functions are not allowed in WHERE"
+    );
+  }
+
+  #[test]
+  fn joins_tables() {
+    assert_eq!(
+      rows("SELECT f.name, c.kind FROM fruit f JOIN cats c ON f.cat = c.cat ORDER BY f.id"),
+      vec![
+        vec!["apple", "tree"],
+        vec!["pear", "vine"],
+        vec!["fig", "tree"]
+      ]
+    );
+    assert_eq!(
+      rows(
+        "SELECT f.name, c.kind FROM fruit AS f LEFT JOIN cats AS c ON f.cat = c.cat ORDER BY f.id"
+      ),
+      vec![
+        vec!["apple", "tree"],
+        vec!["pear", "vine"],
+        vec!["fig", "tree"],
+        vec!["kiwi", "NULL"]
+      ]
+    );
+    assert_eq!(
+      column("SELECT count(*) FROM fruit CROSS JOIN cats"),
+      vec!["12"]
+    );
+    assert_eq!(
+      query("SELECT cat FROM fruit JOIN cats ON true").unwrap_err(),
+      "column reference \"cat\" is ambiguous"
+    );
+  }
+
+  #[test]
+  fn selects_from_subqueries() {
+    assert_eq!(
+      column("SELECT count(*) FROM (SELECT DISTINCT cat FROM fruit) AS t"),
+      vec!["3"]
+    );
+    assert_eq!(
+      column("SELECT t.n FROM (SELECT name AS n FROM fruit WHERE id = 3) t"),
+      vec!["fig"]
+    );
+  }
+
+  #[test]
+  fn matches_distinct_like_in_and_between() {
+    assert_eq!(
+      column("SELECT DISTINCT cat FROM fruit ORDER BY cat"),
+      vec!["a", "b", "NULL"]
+    );
+    assert_eq!(
+      column("SELECT name FROM fruit WHERE name LIKE '%i%' ORDER BY id"),
+      vec!["fig", "kiwi"]
+    );
+    assert_eq!(
+      column("SELECT name FROM fruit WHERE name ILIKE 'A_P%'"),
+      vec!["apple"]
+    );
+    assert_eq!(
+      column("SELECT id FROM fruit WHERE id IN (1, 3) ORDER BY id"),
+      vec!["1", "3"]
+    );
+    assert_eq!(
+      column("SELECT id FROM fruit WHERE id NOT BETWEEN 2 AND 3 ORDER BY id"),
+      vec!["1", "4"]
+    );
+  }
+
+  #[test]
+  fn follows_null_semantics() {
+    assert_eq!(
+      column("SELECT id FROM fruit WHERE price <> 3 ORDER BY id"),
+      vec!["1", "4"]
+    );
+    assert_eq!(
+      column("SELECT id FROM fruit WHERE NOT (price = 3) ORDER BY id"),
+      vec!["1", "4"]
+    );
+    assert_eq!(
+      column("SELECT id FROM fruit WHERE price IS NULL"),
+      vec!["2"]
+    );
+    assert!(query("SELECT id FROM fruit WHERE id NOT IN (1, NULL)")
+      .unwrap()
+      .rows
+      .is_empty());
+    assert_eq!(
+      column("SELECT id FROM fruit WHERE cat IN ('b', NULL)"),
+      vec!["2"]
+    );
+    assert_eq!(
+      rows("SELECT NULL = NULL, NULL OR TRUE, NULL AND FALSE, NULL + 1, 'a' || NULL"),
+      vec![vec!["NULL", "true", "false", "NULL", "NULL"]]
+    );
+    assert_eq!(
+      column("SELECT coalesce(price, 0) FROM fruit WHERE id = 2"),
+      vec!["0"]
+    );
+  }
+
+  #[test]
+  fn evaluates_functions_casts_and_case() {
+    assert_eq!(
+      rows(
+        "SELECT upper(name), length(name), round(price, 1), '12.345'::numeric(5,2), CASE WHEN price > 2 THEN 'high' ELSE 'low' END FROM fruit WHERE id = 3"
+      ),
+      vec![vec!["FIG", "3", "3.0", "12.35", "high"]]
+    );
+    assert!(query("SELECT 'x'::int").is_err());
+  }
+}
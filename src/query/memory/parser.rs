@@ -0,0 +1,1060 @@
+// Parses the subset of SQL the in-memory engine runs: SELECT with joins, WHERE, GROUP BY,
+// HAVING, ORDER BY, LIMIT and OFFSET, along with CREATE TABLE ... AS and DROP TABLE.
+// Identifiers are resolved like postgres does, i.e. unquoted ones are lower cased.
+use crate::types::annotation;
+use crate::types::value::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+  Select(Select),
+  CreateTableAs(String, bool, Select), // Name, IF NOT EXISTS and the query of the table
+  DropTable(Vec<String>, bool),        // Names and IF EXISTS
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+  pub distinct: bool,
+  pub items: Vec<SelectItem>,
+  pub from: Option<From>,
+  pub filter: Option<Expr>,
+  pub group_by: Vec<Expr>,
+  pub having: Option<Expr>,
+  pub order_by: Vec<(Expr, bool)>, // Expression and whether it is sorted descending
+  pub limit: Option<usize>,
+  pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectItem {
+  Wildcard(Option<String>),   // * or table.*
+  Expr(Expr, Option<String>), // Expression and its alias
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct From {
+  pub source: Source,
+  pub joins: Vec<Join>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+  Table(String, Option<String>),         // Name and alias
+  Subquery(Box<Select>, Option<String>), // (SELECT ...) and alias
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+  pub kind: JoinKind,
+  pub source: Source,
+  pub on: Option<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+  Inner,
+  Left,
+  Cross,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+  Or,
+  And,
+  Eq,
+  NotEq,
+  Lt,
+  LtEq,
+  Gt,
+  GtEq,
+  Plus,
+  Minus,
+  Multiply,
+  Divide,
+  Modulo,
+  Concat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+  Literal(Value),
+  Column(Option<String>, String), // Optional table and column name
+  Wildcard,                       // * within count(*)
+  Not(Box<Expr>),
+  Negate(Box<Expr>),
+  Binary(Box<Expr>, BinaryOperator, Box<Expr>),
+  IsNull(Box<Expr>, bool),            // Operand and whether it is IS NOT NULL
+  InList(Box<Expr>, Vec<Expr>, bool), // Operand, list and whether it is NOT IN
+  Between(Box<Expr>, Box<Expr>, Box<Expr>, bool), // Operand, bounds and NOT
+  Like(Box<Expr>, Box<Expr>, bool, bool), // Operand, pattern, NOT and ILIKE
+  Function(String, Vec<Expr>, bool),  // Name, arguments and DISTINCT
+  Cast(Box<Expr>, String),
+  Case(Option<Box<Expr>>, Vec<(Expr, Expr)>, Option<Box<Expr>>), // Operand, WHENs and ELSE
+}
+
+pub const AGGREGATES: [&str; 5] = ["count", "sum", "avg", "min", "max"];
+
+impl Expr {
+  // Whether the expression holds a call of an aggregate function
+  pub fn has_aggregate(&self) -> bool {
+    match self {
+      Expr::Function(name, arguments, _) => {
+        AGGREGATES.contains(&name.as_str()) || arguments.iter().any(Expr::has_aggregate)
+      }
+      _ => self.children().into_iter().any(Expr::has_aggregate),
+    }
+  }
+
+  pub fn children(&self) -> Vec<&Expr> {
+    match self {
+      Expr::Literal(_) | Expr::Column(_, _) | Expr::Wildcard => Vec::new(),
+      Expr::Not(operand) | Expr::Negate(operand) | Expr::Cast(operand, _) => vec![operand],
+      Expr::IsNull(operand, _) => vec![operand],
+      Expr::Binary(left, _, right) | Expr::Like(left, right, _, _) => vec![left, right],
+      Expr::InList(operand, list, _) => std::iter::once(&**operand).chain(list).collect(),
+      Expr::Between(operand, low, high, _) => vec![operand, low, high],
+      Expr::Function(_, arguments, _) => arguments.iter().collect(),
+      Expr::Case(operand, whens, otherwise) => operand
+        .iter()
+        .map(|operand| &**operand)
+        .chain(whens.iter().flat_map(|(when, then)| vec![when, then]))
+        .chain(otherwise.iter().map(|otherwise| &**otherwise))
+        .collect(),
+    }
+  }
+}
+
+// Words that end an expression or a table, so that they are never read as an alias
+const RESERVED: [&str; 37] = [
+  "all", "and", "as", "asc", "between", "by", "case", "create", "cross", "desc", "distinct",
+  "drop", "else", "end", "from", "full", "group", "having", "ilike", "in", "inner", "is", "join",
+  "left", "like", "limit", "not", "offset", "on", "or", "order", "right", "select", "then",
+  "union", "when", "where",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Word(String),   // Keyword or unquoted identifier, lower cased
+  Quoted(String), // Quoted identifier
+  Number(String),
+  String(String),
+  Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 18] = [
+  "<>", "!=", "<=", ">=", "||", "::", ",", "(", ")", ".", "*", "+", "-", "/", "%", "=", "<", ">",
+];
+
+pub fn parse(statement: &str) -> Result<Statement, String> {
+  let mut parser = Parser {
+    tokens: tokenize(statement)?,
+    index: 0,
+  };
+  let statement = parser.statement()?;
+  parser.eat_symbol(";");
+  match parser.peek() {
+    None => Ok(statement),
+    Some(token) => Err(format!("syntax error at or near {}", describe(token))),
+  }
+}
+
+// Parses a lone expression, e.g. that of a CHECK constraint
+pub fn parse_expression(expression: &str) -> Result<Expr, String> {
+  let mut parser = Parser {
+    tokens: tokenize(expression)?,
+    index: 0,
+  };
+  let expr = parser.expr()?;
+  match parser.peek() {
+    None => Ok(expr),
+    Some(token) => Err(format!("syntax error at or near {}", describe(token))),
+  }
+}
+
+fn tokenize(statement: &str) -> Result<Vec<Token>, String> {
+  let chars = statement.chars().collect::<Vec<char>>();
+  let mut tokens = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    let rest = || chars[i..].iter().collect::<String>();
+    if c.is_whitespace() {
+      i += 1;
+    } else if c == '-' && chars.get(i + 1) == Some(&'-') {
+      while i < chars.len() && chars[i] != '\n' {
+        i += 1;
+      }
+    } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+      match rest().find("*/") {
+        Some(end) => i += rest()[..end].chars().count() + 2,
+        None => return Err("unterminated /* comment".to_string()),
+      }
+    } else if c == '\'' || c == '"' {
+      let mut text = String::new();
+      i += 1;
+      loop {
+        match chars.get(i) {
+          Some(&quote) if quote == c && chars.get(i + 1) == Some(&c) => {
+            text.push(c);
+            i += 2;
+          }
+          Some(&quote) if quote == c => {
+            i += 1;
+            break;
+          }
+          Some(&other) => {
+            text.push(other);
+            i += 1;
+          }
+          None if c == '\'' => return Err("unterminated quoted string".to_string()),
+          None => return Err("unterminated quoted identifier".to_string()),
+        }
+      }
+      tokens.push(if c == '\'' {
+        Token::String(text)
+      } else {
+        Token::Quoted(text)
+      });
+    } else if c.is_ascii_digit() || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+    {
+      let start = i;
+      while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+        i += 1;
+      }
+      if i < chars.len() && ['e', 'E'].contains(&chars[i]) {
+        let sign = usize::from(chars.get(i + 1).is_some_and(|c| ['+', '-'].contains(c)));
+        if chars.get(i + 1 + sign).is_some_and(char::is_ascii_digit) {
+          i += 1 + sign;
+          while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+          }
+        }
+      }
+      tokens.push(Token::Number(chars[start..i].iter().collect()));
+    } else if c.is_alphabetic() || c == '_' {
+      let start = i;
+      while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+        i += 1;
+      }
+      let word = chars[start..i].iter().collect::<String>();
+      tokens.push(Token::Word(word.to_lowercase()));
+    } else {
+      let rest = rest();
+      match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+        Some(symbol) => {
+          tokens.push(Token::Symbol(symbol));
+          i += symbol.len();
+        }
+        None if c == ';' => {
+          tokens.push(Token::Symbol(";"));
+          i += 1;
+        }
+        None => return Err(format!("syntax error at or near \"{}\"", c)),
+      }
+    }
+  }
+  Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+  match token {
+    Token::Word(word) | Token::Number(word) => format!("\"{}\"", word),
+    Token::Quoted(name) => format!("\"{}\"", name),
+    Token::String(text) => format!("\"'{}'\"", text),
+    Token::Symbol(symbol) => format!("\"{}\"", symbol),
+  }
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  index: usize,
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.index)
+  }
+
+  fn peek_word(&self, offset: usize) -> Option<&str> {
+    match self.tokens.get(self.index + offset) {
+      Some(Token::Word(word)) => Some(word.as_str()),
+      _ => None,
+    }
+  }
+
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.index).cloned();
+    self.index += 1;
+    token
+  }
+
+  fn error(&self) -> String {
+    match self.peek() {
+      Some(token) => format!("syntax error at or near {}", describe(token)),
+      None => "syntax error at end of input".to_string(),
+    }
+  }
+
+  fn eat_word(&mut self, word: &str) -> bool {
+    if self.peek_word(0) == Some(word) {
+      self.index += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn eat_words(&mut self, words: &[&str]) -> bool {
+    if words
+      .iter()
+      .enumerate()
+      .all(|(offset, word)| self.peek_word(offset) == Some(word))
+    {
+      self.index += words.len();
+      true
+    } else {
+      false
+    }
+  }
+
+  fn expect_word(&mut self, word: &str) -> Result<(), String> {
+    if self.eat_word(word) {
+      Ok(())
+    } else {
+      Err(self.error())
+    }
+  }
+
+  fn eat_symbol(&mut self, symbol: &str) -> bool {
+    if self.peek() == Some(&Token::Symbol(symbol_of(symbol))) {
+      self.index += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+    if self.eat_symbol(symbol) {
+      Ok(())
+    } else {
+      Err(self.error())
+    }
+  }
+
+  // Reads an identifier, quoted or not, that is not a reserved word
+  fn identifier(&mut self) -> Result<String, String> {
+    match self.peek() {
+      Some(Token::Word(word)) if !RESERVED.contains(&word.as_str()) => {
+        let word = word.clone();
+        self.index += 1;
+        Ok(word)
+      }
+      Some(Token::Quoted(name)) => {
+        let name = name.clone();
+        self.index += 1;
+        Ok(name)
+      }
+      _ => Err(self.error()),
+    }
+  }
+
+  // Reads a table name, of which a schema is ignored since tables live in memory
+  fn table_name(&mut self) -> Result<String, String> {
+    let mut name = self.identifier()?;
+    while self.eat_symbol(".") {
+      name = self.identifier()?;
+    }
+    Ok(name)
+  }
+
+  // Reads an alias, either after AS or on its own
+  fn alias(&mut self) -> Result<Option<String>, String> {
+    if self.eat_word("as") {
+      return self.identifier().map(Some);
+    }
+    match self.peek() {
+      Some(Token::Word(word)) if !RESERVED.contains(&word.as_str()) => self.identifier().map(Some),
+      Some(Token::Quoted(_)) => self.identifier().map(Some),
+      _ => Ok(None),
+    }
+  }
+
+  fn count(&mut self) -> Result<usize, String> {
+    match self.next() {
+      Some(Token::Number(number)) => number
+        .parse::<usize>()
+        .map_err(|_| format!("invalid row count {}", number)),
+      _ => {
+        self.index -= 1;
+        Err(self.error())
+      }
+    }
+  }
+
+  fn statement(&mut self) -> Result<Statement, String> {
+    if self.eat_word("create") {
+      self.expect_word("table")?;
+      let if_not_exists = self.eat_words(&["if", "not", "exists"]);
+      let name = self.table_name()?;
+      self.expect_word("as")?;
+      let select = self.select()?;
+      Ok(Statement::CreateTableAs(name, if_not_exists, select))
+    } else if self.eat_word("drop") {
+      self.expect_word("table")?;
+      let if_exists = self.eat_words(&["if", "exists"]);
+      let mut names = vec![self.table_name()?];
+      while self.eat_symbol(",") {
+        names.push(self.table_name()?);
+      }
+      Ok(Statement::DropTable(names, if_exists))
+    } else if self.peek_word(0) == Some("select") || self.peek() == Some(&Token::Symbol("(")) {
+      self.select().map(Statement::Select)
+    } else {
+      Err(match self.peek() {
+        Some(Token::Word(word)) => format!(
+          "{} statements are not supported by the memory backend",
+          word.to_uppercase()
+        ),
+        _ => self.error(),
+      })
+    }
+  }
+
+  fn select(&mut self) -> Result<Select, String> {
+    if self.eat_symbol("(") {
+      let select = self.select()?;
+      self.expect_symbol(")")?;
+      return Ok(select);
+    }
+    self.expect_word("select")?;
+    let distinct = self.eat_word("distinct");
+    if !distinct {
+      self.eat_word("all");
+    }
+    let mut items = vec![self.select_item()?];
+    while self.eat_symbol(",") {
+      items.push(self.select_item()?);
+    }
+    let from = if self.eat_word("from") {
+      Some(self.from()?)
+    } else {
+      None
+    };
+    let filter = if self.eat_word("where") {
+      Some(self.expr()?)
+    } else {
+      None
+    };
+    let mut group_by = Vec::new();
+    if self.eat_words(&["group", "by"]) {
+      group_by.push(self.expr()?);
+      while self.eat_symbol(",") {
+        group_by.push(self.expr()?);
+      }
+    }
+    let having = if self.eat_word("having") {
+      Some(self.expr()?)
+    } else {
+      None
+    };
+    let mut order_by = Vec::new();
+    if self.eat_words(&["order", "by"]) {
+      loop {
+        let expr = self.expr()?;
+        let descending = self.eat_word("desc");
+        if !descending {
+          self.eat_word("asc");
+        }
+        order_by.push((expr, descending));
+        if !self.eat_symbol(",") {
+          break;
+        }
+      }
+    }
+    let mut limit = None;
+    let mut offset = 0;
+    loop {
+      if self.eat_word("limit") {
+        limit = if self.eat_word("all") {
+          None
+        } else {
+          Some(self.count()?)
+        };
+      } else if self.eat_word("offset") {
+        offset = self.count()?;
+        self.eat_word("rows");
+      } else {
+        break;
+      }
+    }
+    Ok(Select {
+      distinct,
+      items,
+      from,
+      filter,
+      group_by,
+      having,
+      order_by,
+      limit,
+      offset,
+    })
+  }
+
+  fn select_item(&mut self) -> Result<SelectItem, String> {
+    if self.eat_symbol("*") {
+      return Ok(SelectItem::Wildcard(None));
+    }
+    // table.* is told apart from table.column by looking ahead
+    let is_qualified_wildcard =
+      matches!(self.peek(), Some(Token::Word(_)) | Some(Token::Quoted(_)))
+        && self.tokens.get(self.index + 1) == Some(&Token::Symbol("."))
+        && self.tokens.get(self.index + 2) == Some(&Token::Symbol("*"));
+    if is_qualified_wildcard {
+      let table = self.identifier()?;
+      self.index += 2;
+      return Ok(SelectItem::Wildcard(Some(table)));
+    }
+    let expr = self.expr()?;
+    let alias = self.alias()?;
+    Ok(SelectItem::Expr(expr, alias))
+  }
+
+  fn from(&mut self) -> Result<From, String> {
+    let source = self.source()?;
+    let mut joins = Vec::new();
+    loop {
+      let kind = if self.eat_symbol(",") || self.eat_words(&["cross", "join"]) {
+        JoinKind::Cross
+      } else if self.eat_word("join") || self.eat_words(&["inner", "join"]) {
+        JoinKind::Inner
+      } else if self.eat_words(&["left", "join"]) || self.eat_words(&["left", "outer", "join"]) {
+        JoinKind::Left
+      } else if self.peek_word(0) == Some("right") || self.peek_word(0) == Some("full") {
+        return Err(format!(
+          "{} joins are not supported by the memory backend",
+          self.peek_word(0).unwrap_or("").to_uppercase()
+        ));
+      } else {
+        break;
+      };
+      let source = self.source()?;
+      let on = if kind == JoinKind::Cross {
+        None
+      } else {
+        self.expect_word("on")?;
+        Some(self.expr()?)
+      };
+      joins.push(Join { kind, source, on });
+    }
+    Ok(From { source, joins })
+  }
+
+  fn source(&mut self) -> Result<Source, String> {
+    if self.eat_symbol("(") {
+      let select = self.select()?;
+      self.expect_symbol(")")?;
+      let alias = self.alias()?;
+      return Ok(Source::Subquery(Box::new(select), alias));
+    }
+    let name = self.table_name()?;
+    let alias = self.alias()?;
+    Ok(Source::Table(name, alias))
+  }
+
+  // Operators from the loosest to the tightest binding one
+  pub fn expr(&mut self) -> Result<Expr, String> {
+    let mut left = self.and()?;
+    while self.eat_word("or") {
+      let right = self.and()?;
+      left = Expr::Binary(Box::new(left), BinaryOperator::Or, Box::new(right));
+    }
+    Ok(left)
+  }
+
+  fn and(&mut self) -> Result<Expr, String> {
+    let mut left = self.not()?;
+    while self.eat_word("and") {
+      let right = self.not()?;
+      left = Expr::Binary(Box::new(left), BinaryOperator::And, Box::new(right));
+    }
+    Ok(left)
+  }
+
+  fn not(&mut self) -> Result<Expr, String> {
+    if self.eat_word("not") {
+      return Ok(Expr::Not(Box::new(self.not()?)));
+    }
+    self.comparison()
+  }
+
+  fn comparison(&mut self) -> Result<Expr, String> {
+    let left = self.concat()?;
+    let operator = match self.peek() {
+      Some(Token::Symbol("=")) => Some(BinaryOperator::Eq),
+      Some(Token::Symbol("<>")) | Some(Token::Symbol("!=")) => Some(BinaryOperator::NotEq),
+      Some(Token::Symbol("<")) => Some(BinaryOperator::Lt),
+      Some(Token::Symbol("<=")) => Some(BinaryOperator::LtEq),
+      Some(Token::Symbol(">")) => Some(BinaryOperator::Gt),
+      Some(Token::Symbol(">=")) => Some(BinaryOperator::GtEq),
+      _ => None,
+    };
+    if let Some(operator) = operator {
+      self.index += 1;
+      let right = self.concat()?;
+      return Ok(Expr::Binary(Box::new(left), operator, Box::new(right)));
+    }
+    if self.eat_word("is") {
+      let negated = self.eat_word("not");
+      self.expect_word("null")?;
+      return Ok(Expr::IsNull(Box::new(left), negated));
+    }
+    let negated = self.peek_word(0) == Some("not")
+      && ["in", "between", "like", "ilike"].contains(&self.peek_word(1).unwrap_or(""));
+    if negated {
+      self.index += 1;
+    }
+    if self.eat_word("in") {
+      self.expect_symbol("(")?;
+      let mut list = vec![self.expr()?];
+      while self.eat_symbol(",") {
+        list.push(self.expr()?);
+      }
+      self.expect_symbol(")")?;
+      Ok(Expr::InList(Box::new(left), list, negated))
+    } else if self.eat_word("between") {
+      let low = self.concat()?;
+      self.expect_word("and")?;
+      let high = self.concat()?;
+      Ok(Expr::Between(
+        Box::new(left),
+        Box::new(low),
+        Box::new(high),
+        negated,
+      ))
+    } else if self.peek_word(0) == Some("like") || self.peek_word(0) == Some("ilike") {
+      let case_insensitive = self.next() == Some(Token::Word("ilike".to_string()));
+      let pattern = self.concat()?;
+      Ok(Expr::Like(
+        Box::new(left),
+        Box::new(pattern),
+        negated,
+        case_insensitive,
+      ))
+    } else {
+      Ok(left)
+    }
+  }
+
+  fn concat(&mut self) -> Result<Expr, String> {
+    let mut left = self.additive()?;
+    while self.eat_symbol("||") {
+      let right = self.additive()?;
+      left = Expr::Binary(Box::new(left), BinaryOperator::Concat, Box::new(right));
+    }
+    Ok(left)
+  }
+
+  fn additive(&mut self) -> Result<Expr, String> {
+    let mut left = self.multiplicative()?;
+    loop {
+      let operator = if self.eat_symbol("+") {
+        BinaryOperator::Plus
+      } else if self.eat_symbol("-") {
+        BinaryOperator::Minus
+      } else {
+        return Ok(left);
+      };
+      let right = self.multiplicative()?;
+      left = Expr::Binary(Box::new(left), operator, Box::new(right));
+    }
+  }
+
+  fn multiplicative(&mut self) -> Result<Expr, String> {
+    let mut left = self.unary()?;
+    loop {
+      let operator = if self.eat_symbol("*") {
+        BinaryOperator::Multiply
+      } else if self.eat_symbol("/") {
+        BinaryOperator::Divide
+      } else if self.eat_symbol("%") {
+        BinaryOperator::Modulo
+      } else {
+        return Ok(left);
+      };
+      let right = self.unary()?;
+      left = Expr::Binary(Box::new(left), operator, Box::new(right));
+    }
+  }
+
+  fn unary(&mut self) -> Result<Expr, String> {
+    if self.eat_symbol("-") {
+      return Ok(Expr::Negate(Box::new(self.unary()?)));
+    }
+    if self.eat_symbol("+") {
+      return self.unary();
+    }
+    let mut expr = self.primary()?;
+    while self.eat_symbol("::") {
+      expr = Expr::Cast(Box::new(expr), self.type_name()?);
+    }
+    Ok(expr)
+  }
+
+  fn primary(&mut self) -> Result<Expr, String> {
+    match self.next() {
+      Some(Token::Number(number)) => Ok(Expr::Literal(match number.parse::<i64>() {
+        Ok(integer) => Value::Int(integer),
+        Err(_) if number.contains(['e', 'E']) => match number.parse::<f64>() {
+          Ok(float) => Value::Float(float),
+          Err(_) => return Err(format!("invalid number {}", number)),
+        },
+        Err(_) => Value::Numeric(number),
+      })),
+      Some(Token::String(text)) => Ok(Expr::Literal(Value::Text(text))),
+      Some(Token::Symbol("(")) => {
+        let expr = self.expr()?;
+        self.expect_symbol(")")?;
+        Ok(expr)
+      }
+      Some(Token::Quoted(name)) => self.column(name),
+      Some(Token::Word(word)) => match word.as_str() {
+        "null" => Ok(Expr::Literal(Value::Null)),
+        "true" => Ok(Expr::Literal(Value::Bool(true))),
+        "false" => Ok(Expr::Literal(Value::Bool(false))),
+        "case" => self.case(),
+        "cast" => {
+          self.expect_symbol("(")?;
+          let expr = self.expr()?;
+          self.expect_word("as")?;
+          let type_name = self.type_name()?;
+          self.expect_symbol(")")?;
+          Ok(Expr::Cast(Box::new(expr), type_name))
+        }
+        // Typed literals, e.g. DATE '2020-01-31'
+        "date" | "timestamp" if matches!(self.peek(), Some(Token::String(_))) => {
+          let literal = self.primary()?;
+          Ok(Expr::Cast(Box::new(literal), word.to_uppercase()))
+        }
+        _ if RESERVED.contains(&word.as_str()) => {
+          self.index -= 1;
+          Err(self.error())
+        }
+        _ if self.peek() == Some(&Token::Symbol("(")) => self.function(word),
+        _ => self.column(word),
+      },
+      _ => {
+        self.index -= 1;
+        Err(self.error())
+      }
+    }
+  }
+
+  fn column(&mut self, name: String) -> Result<Expr, String> {
+    if self.eat_symbol(".") {
+      let column = self.identifier()?;
+      return Ok(Expr::Column(Some(name), column));
+    }
+    Ok(Expr::Column(None, name))
+  }
+
+  fn function(&mut self, name: String) -> Result<Expr, String> {
+    self.expect_symbol("(")?;
+    if self.eat_symbol("*") {
+      self.expect_symbol(")")?;
+      return Ok(Expr::Function(name, vec![Expr::Wildcard], false));
+    }
+    let distinct = self.eat_word("distinct");
+    let mut arguments = Vec::new();
+    if !self.eat_symbol(")") {
+      arguments.push(self.expr()?);
+      while self.eat_symbol(",") {
+        arguments.push(self.expr()?);
+      }
+      self.expect_symbol(")")?;
+    }
+    Ok(Expr::Function(name, arguments, distinct))
+  }
+
+  fn case(&mut self) -> Result<Expr, String> {
+    let operand = if self.peek_word(0) == Some("when") {
+      None
+    } else {
+      Some(Box::new(self.expr()?))
+    };
+    let mut whens = Vec::new();
+    while self.eat_word("when") {
+      let when = self.expr()?;
+      self.expect_word("then")?;
+      whens.push((when, self.expr()?));
+    }
+    if whens.is_empty() {
+      return Err(self.error());
+    }
+    let otherwise = if self.eat_word("else") {
+      Some(Box::new(self.expr()?))
+    } else {
+      None
+    };
+    self.expect_word("end")?;
+    Ok(Expr::Case(operand, whens, otherwise))
+  }
+
+  // Reads a type name, e.g. int, numeric(10, 2) or double precision, in the form annotations
+  // are normalized to
+  fn type_name(&mut self) -> Result<String, String> {
+    let mut words = vec![match self.next() {
+      Some(Token::Word(word)) => word,
+      _ => {
+        self.index -= 1;
+        return Err(self.error());
+      }
+    }];
+    let continuations: [(&str, &[&str]); 4] = [
+      ("double", &["precision"]),
+      ("character", &["varying"]),
+      ("timestamp", &["with", "without", "time", "zone"]),
+      ("time", &["with", "without", "time", "zone"]),
+    ];
+    if let Some((_, following)) = continuations.iter().find(|(first, _)| *first == words[0]) {
+      while let Some(word) = self.peek_word(0).filter(|word| following.contains(word)) {
+        words.push(word.to_string());
+        self.index += 1;
+      }
+    }
+    let mut type_name = words.join(" ");
+    if self.eat_symbol("(") {
+      let mut parameters = vec![self.count()?.to_string()];
+      while self.eat_symbol(",") {
+        parameters.push(self.count()?.to_string());
+      }
+      self.expect_symbol(")")?;
+      type_name = format!("{}({})", type_name, parameters.join(","));
+    }
+    annotation::parse_type(type_name.as_str()).map_err(|e| e.message)
+  }
+}
+
+// The static symbol a symbol is tokenized as
+fn symbol_of(symbol: &str) -> &'static str {
+  SYMBOLS
+    .iter()
+    .chain(Some(&";"))
+    .find(|other| **other == symbol)
+    .copied()
+    .unwrap_or("")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn column(name: &str) -> Expr {
+    Expr::Column(None, name.to_string())
+  }
+
+  fn int(value: i64) -> Expr {
+    Expr::Literal(Value::Int(value))
+  }
+
+  fn binary(left: Expr, operator: BinaryOperator, right: Expr) -> Expr {
+    Expr::Binary(Box::new(left), operator, Box::new(right))
+  }
+
+  fn select(statement: &str) -> Select {
+    match parse(statement).unwrap() {
+      Statement::Select(select) => select,
+      statement => panic!("Expected a SELECT but got {:?}", statement),
+    }
+  }
+
+  #[test]
+  fn parses_literals() {
+    assert_eq!(parse_expression("1").unwrap(), int(1));
+    assert_eq!(
+      parse_expression("1.50").unwrap(),
+      Expr::Literal(Value::Numeric("1.50".to_string()))
+    );
+    assert_eq!(
+      parse_expression("'it''s'").unwrap(),
+      Expr::Literal(Value::Text("it's".to_string()))
+    );
+    assert_eq!(
+      parse_expression("TRUE").unwrap(),
+      Expr::Literal(Value::Bool(true))
+    );
+    assert_eq!(
+      parse_expression("null").unwrap(),
+      Expr::Literal(Value::Null)
+    );
+  }
+
+  #[test]
+  fn parses_operators_by_precedence() {
+    use BinaryOperator::*;
+    assert_eq!(
+      parse_expression("1 + 2 * 3").unwrap(),
+      binary(int(1), Plus, binary(int(2), Multiply, int(3)))
+    );
+    assert_eq!(
+      parse_expression("(1 + 2) * 3").unwrap(),
+      binary(binary(int(1), Plus, int(2)), Multiply, int(3))
+    );
+    assert_eq!(
+      parse_expression("a OR b AND NOT c").unwrap(),
+      binary(
+        column("a"),
+        Or,
+        binary(column("b"), And, Expr::Not(Box::new(column("c"))))
+      )
+    );
+    assert_eq!(
+      parse_expression("a = 1 AND b <> 2").unwrap(),
+      binary(
+        binary(column("a"), Eq, int(1)),
+        And,
+        binary(column("b"), NotEq, int(2))
+      )
+    );
+  }
+
+  #[test]
+  fn parses_predicates() {
+    assert_eq!(
+      parse_expression("x NOT IN (1, 2)").unwrap(),
+      Expr::InList(Box::new(column("x")), vec![int(1), int(2)], true)
+    );
+    assert_eq!(
+      parse_expression("x BETWEEN 1 AND 2").unwrap(),
+      Expr::Between(
+        Box::new(column("x")),
+        Box::new(int(1)),
+        Box::new(int(2)),
+        false
+      )
+    );
+    assert_eq!(
+      parse_expression("x IS NOT NULL").unwrap(),
+      Expr::IsNull(Box::new(column("x")), true)
+    );
+    assert_eq!(
+      parse_expression("x ILIKE 'a%'").unwrap(),
+      Expr::Like(
+        Box::new(column("x")),
+        Box::new(Expr::Literal(Value::Text("a%".to_string()))),
+        false,
+        true
+      )
+    );
+  }
+
+  #[test]
+  fn normalizes_cast_types() {
+    assert_eq!(
+      parse_expression("x::int").unwrap(),
+      Expr::Cast(Box::new(column("x")), "INTEGER".to_string())
+    );
+    assert_eq!(
+      parse_expression("CAST(x AS numeric(5, 2))").unwrap(),
+      Expr::Cast(Box::new(column("x")), "NUMERIC(5,2)".to_string())
+    );
+  }
+
+  #[test]
+  fn lower_cases_unquoted_identifiers() {
+    let select = select("SELECT \"Name\", X FROM T");
+    assert_eq!(
+      select.items,
+      vec![
+        SelectItem::Expr(column("Name"), None),
+        SelectItem::Expr(column("x"), None)
+      ]
+    );
+    assert_eq!(
+      select.from.unwrap().source,
+      Source::Table("t".to_string(), None)
+    );
+  }
+
+  #[test]
+  fn parses_select_clauses() {
+    let select = select(
+      "SELECT DISTINCT cat, count(*) AS n FROM fruit WHERE id > 1 GROUP BY cat HAVING count(*) > 1 ORDER BY n DESC, 1 LIMIT 5 OFFSET 2;",
+    );
+    assert!(select.distinct);
+    assert_eq!(
+      select.items[1],
+      SelectItem::Expr(
+        Expr::Function("count".to_string(), vec![Expr::Wildcard], false),
+        Some("n".to_string())
+      )
+    );
+    assert_eq!(
+      select.filter,
+      Some(binary(column("id"), BinaryOperator::Gt, int(1)))
+    );
+    assert_eq!(select.group_by, vec![column("cat")]);
+    assert!(select.having.is_some());
+    assert_eq!(select.order_by, vec![(column("n"), true), (int(1), false)]);
+    assert_eq!((select.limit, select.offset), (Some(5), 2));
+  }
+
+  #[test]
+  fn parses_joins_and_subqueries() {
+    let from =
+      select("SELECT * FROM fruit f LEFT JOIN cats AS c ON f.cat = c.cat CROSS JOIN (SELECT 1) s")
+        .from
+        .unwrap();
+    assert_eq!(
+      from.source,
+      Source::Table("fruit".to_string(), Some("f".to_string()))
+    );
+    assert_eq!(from.joins.len(), 2);
+    assert_eq!(from.joins[0].kind, JoinKind::Left);
+    assert_eq!(
+      from.joins[0].source,
+      Source::Table("cats".to_string(), Some("c".to_string()))
+    );
+    assert_eq!(
+      from.joins[0].on,
+      Some(binary(
+        Expr::Column(Some("f".to_string()), "cat".to_string()),
+        BinaryOperator::Eq,
+        Expr::Column(Some("c".to_string()), "cat".to_string())
+      ))
+    );
+    assert_eq!(from.joins[1].kind, JoinKind::Cross);
+    assert!(matches!(from.joins[1].source, Source::Subquery(_, Some(_))));
+  }
+
+  #[test]
+  fn parses_create_table_as_and_drop_table() {
+    assert!(matches!(
+      parse("CREATE TABLE IF NOT EXISTS Cheap AS SELECT * FROM fruit").unwrap(),
+      Statement::CreateTableAs(name, true, _) if name == "cheap"
+    ));
+    assert_eq!(
+      parse("DROP TABLE IF EXISTS a, b").unwrap(),
+      Statement::DropTable(vec!["a".to_string(), "b".to_string()], true)
+    );
+  }
+
+  #[test]
+  fn rejects_unsupported_sql() {
+    assert_eq!(
+      parse("INSERT INTO t VALUES (1)").unwrap_err(),
+      "INSERT statements are not supported by the memory backend"
+    );
+    assert_eq!(
+      parse("SELECT * FROM a RIGHT JOIN b ON true").unwrap_err(),
+      "RIGHT joins are not supported by the memory backend"
+    );
+    assert_eq!(
+      parse("SELECT 'open").unwrap_err(),
+      "unterminated quoted string"
+    );
+    assert_eq!(
+      parse("SELECT 1 2").unwrap_err(),
+      "syntax error at or near \"2\""
+    );
+  }
+}
@@ -1,15 +1,28 @@
-use crate::querier::QuerierTrait;
+use crate::file;
+use crate::querier::{self, QuerierTrait, StoreError};
 use crate::table;
 use crate::types;
 use async_trait::async_trait;
+use bytes::Bytes;
+use futures::SinkExt;
+use std::io::SeekFrom;
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_postgres::error::Error;
 use tokio_postgres::{connect, Client, NoTls, Row};
 
+// Size of the chunks a table file is streamed to the database in
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+
 #[derive(Debug)]
 pub struct Querier {
   pub name: String,
   pub url: String,
   pub client: Client,
+  pub schema: String, // Schema of this session, which everything is created in
+  owns_schema: bool,  // Whether the schema was created for this session
+  created: querier::Created, // Relations created in this session
 }
 
 impl Querier {
@@ -24,12 +37,63 @@ impl Querier {
       }
     });
 
+    // Each session works in a schema of its own, so that sessions sharing a database do not
+    // collide on table names and all that a session created is dropped along with it. The
+    // search path holds only that schema, so unqualified names never reach the tables of other
+    // users in public. Users that may not create schemas work in public instead.
+    let timestamp = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map_or(0, |duration| duration.as_millis());
+    let session_schema = format!("csvql_{}_{}", process::id(), timestamp);
+    let statement = format!(
+      "CREATE SCHEMA {}; SET search_path TO {}",
+      session_schema, session_schema
+    );
+    let (schema, owns_schema) = match client.batch_execute(statement.as_str()).await {
+      Ok(_) => (session_schema, true),
+      Err(e) => {
+        eprintln!(
+          "Could not create a schema for the session, working in public instead. {}",
+          e
+        );
+        ("public".to_string(), false)
+      }
+    };
+
     Ok(Self {
       name: String::from(querier_name),
       url: String::from(database_url),
       client: client,
+      schema,
+      owns_schema,
+      created: querier::Created::default(),
     })
   }
+
+  // Whether a relation of the given name, as it would be written in a statement, exists
+  async fn exists(&self, name: &str) -> Result<bool, Error> {
+    let row = self
+      .client
+      .query_one("SELECT to_regclass($1) IS NOT NULL", &[&name])
+      .await?;
+    Ok(row.get(0))
+  }
+
+  // Streams a file to the database with the given COPY FROM STDIN query
+  async fn copy_in(&self, mut file: tokio::fs::File, query: String) -> Result<(), StoreError> {
+    let sink = self.client.copy_in(query.as_str()).await?;
+    futures::pin_mut!(sink);
+    let mut buffer = vec![0; COPY_CHUNK_SIZE];
+    loop {
+      let read = file.read(&mut buffer).await?;
+      if read == 0 {
+        break;
+      }
+      sink.send(Bytes::copy_from_slice(&buffer[..read])).await?;
+    }
+    sink.finish().await?;
+    Ok(())
+  }
 }
 
 #[async_trait]
@@ -39,25 +103,45 @@ impl QuerierTrait for Querier {
     table_path: &str,
     table_name: &str,
     table_header: table::Header,
+    table_constraints: &table::Constraints,
+    table_dialect: &file::Dialect,
     // table_data: table::Rows,
-  ) -> Result<(), Error> {
-    self
-      .client
-      .query(create_table_query(&table_name, &table_header).as_str(), &[])
-      .await?;
+  ) -> Result<(), StoreError> {
+    // The file is read by csvql and streamed to the server, so that the server does not
+    // need access to the file system of the user
+    let mut file = tokio::fs::File::open(table_path).await?;
+    if !table_dialect.is_latin1() {
+      skip_byte_order_mark(&mut file).await?;
+    }
     self
       .client
       .query(
-        copy_into_query(&table_path, &table_name, &table_header).as_str(),
-        // insert_into_query(&table_name, &table_header, table_data).as_str(),
+        create_table_query(&table_name, &table_header, table_constraints).as_str(),
         &[],
       )
       .await?;
-    Ok(())
+    self.created.add(querier::RelationKind::Table, table_name);
+    let result = self
+      .copy_in(
+        file,
+        copy_into_query(table_name, &table_header, table_dialect),
+      )
+      .await;
+    if result.is_err() {
+      // A file that breaks the types or constraints of its table leaves no table behind
+      let _ = self.drop(table_name).await;
+    }
+    result
   }
 
-  async fn query(&self, query_statement: &str) -> Result<Option<table::Table>, Error> {
+  async fn query(&self, query_statement: &str) -> Result<Option<table::Table>, querier::Error> {
+    let statement = querier::parse_statement(query_statement);
+    let existed_before = match &statement {
+      querier::Statement::Create(_, name, true) => self.exists(name).await?,
+      _ => false,
+    };
     let rows: Vec<Row> = self.client.query(query_statement, &[]).await?;
+    self.created.track(&statement, existed_before);
     if rows.len() == 0 {
       return Ok(None);
     }
@@ -78,14 +162,11 @@ impl QuerierTrait for Querier {
     let rows_response: table::Rows = rows
       .into_iter()
       .map(|row| {
-        let mut row_vector = Vec::new();
-        for (col_index, _) in row.columns().iter().enumerate() {
-          // row_vector.push(row.get(col_index)); // TODO FIX PROBLEMS HERE!!
-          row_vector.push(types::postgres::get_value(&row, col_index))
-        }
-        row_vector
+        (0..row.columns().len())
+          .map(|col_index| types::postgres::get_value(&row, col_index))
+          .collect::<Result<Vec<_>, _>>()
       })
-      .collect::<Vec<_>>();
+      .collect::<Result<Vec<_>, _>>()?;
 
     Ok(Some(table::Table::new(header_response, rows_response)))
   }
@@ -94,7 +175,7 @@ impl QuerierTrait for Querier {
     &self,
     table_name: &str,
     num_rows: Option<usize>,
-  ) -> Result<Option<table::Table>, Error> {
+  ) -> Result<Option<table::Table>, querier::Error> {
     let query;
     if num_rows == None {
       query = format!("SELECT * FROM {}", table_name);
@@ -104,7 +185,7 @@ impl QuerierTrait for Querier {
     self.query(query.as_str()).await
   }
 
-  async fn drop(&self, table_name: &str) -> Result<(), Error> {
+  async fn drop(&self, table_name: &str) -> Result<(), querier::Error> {
     match self
       .query(format!("DROP TABLE {}", table_name).as_str())
       .await
@@ -114,22 +195,72 @@ impl QuerierTrait for Querier {
     }
   }
 
-  async fn list(&self, is_verbose: bool) -> Result<Option<table::Table>, Error> {
-    self.query(list_tables_query(is_verbose).as_str()).await
+  async fn clean(&self) -> Vec<String> {
+    let mut failed = Vec::new();
+    for (kind, name) in self.created.take() {
+      // Relations of the session schema are dropped along with it
+      let is_in_schema = match querier::schema_of(&name) {
+        Some(schema) => querier::normalize_name(schema) == self.schema,
+        None => true,
+      };
+      if self.owns_schema && is_in_schema {
+        continue;
+      }
+      let statement = format!("DROP {} IF EXISTS {}", kind.keyword(), name);
+      if self.client.batch_execute(statement.as_str()).await.is_err() {
+        failed.push(name);
+      }
+    }
+    if self.owns_schema {
+      let statement = format!("DROP SCHEMA IF EXISTS {} CASCADE", self.schema);
+      if self.client.batch_execute(statement.as_str()).await.is_err() {
+        failed.push(self.schema.clone());
+      }
+    }
+    failed
+  }
+
+  fn location(&self) -> String {
+    format!("schema {}", self.schema)
   }
 
-  async fn info(&self, table_name: &str, is_verbose: bool) -> Result<Option<table::Table>, Error> {
+  async fn list(&self, is_verbose: bool) -> Result<Option<table::Table>, querier::Error> {
+    self
+      .query(list_tables_query(&self.schema, is_verbose).as_str())
+      .await
+  }
+
+  async fn info(
+    &self,
+    table_name: &str,
+    is_verbose: bool,
+  ) -> Result<Option<table::Table>, querier::Error> {
     self
-      .query(get_table_info_query(table_name, is_verbose).as_str())
+      .query(get_table_info_query(&self.schema, table_name, is_verbose).as_str())
       .await
   }
 }
 
 // HELPERS
-fn create_table_query(table_name: &str, table_header: &table::Header) -> String {
+fn create_table_query(
+  table_name: &str,
+  table_header: &table::Header,
+  table_constraints: &table::Constraints,
+) -> String {
   let schema = table_header
     .into_iter()
-    .map(|(col_name, col_type)| format!("{} {}", col_name, col_type))
+    .enumerate()
+    .map(|(index, (col_name, col_type))| {
+      let constraints = table_constraints.get(index).map_or(&[][..], Vec::as_slice);
+      std::iter::once(format!("{} {}", col_name, col_type))
+        .chain(
+          constraints
+            .iter()
+            .map(|constraint| constraint.to_sql(col_name)),
+        )
+        .collect::<Vec<_>>()
+        .join(" ")
+    })
     .collect::<Vec<_>>()
     .join(",");
   let query = format!("CREATE TABLE {} ({})", table_name, schema);
@@ -139,26 +270,59 @@ fn create_table_query(table_name: &str, table_header: &table::Header) -> String
 
 /*
  * COPY first_table (source,text,created_at,retweet_count,favorite_count,is_retweet,id_str)
- * FROM '/Users/akhil/csvql/data/test.csv'
- * DELIMITER ','
- * CSV HEADER;
+ * FROM STDIN
+ * WITH (FORMAT csv, HEADER true, DELIMITER ',', QUOTE '"', ESCAPE '"', NULL '');
  */
-fn copy_into_query(table_path: &str, name: &str, header: &table::Header) -> String {
+// Moves past the UTF-8 byte order mark a file may start with, which COPY would otherwise read
+// as part of the first field when the file has no header
+async fn skip_byte_order_mark(file: &mut tokio::fs::File) -> Result<(), StoreError> {
+  const BYTE_ORDER_MARK: &[u8] = b"\xEF\xBB\xBF";
+  let mut start = [0; 3];
+  let mut filled = 0;
+  while filled < start.len() {
+    let read = file.read(&mut start[filled..]).await?;
+    if read == 0 {
+      break;
+    }
+    filled += read;
+  }
+  if &start[..filled] != BYTE_ORDER_MARK {
+    file.seek(SeekFrom::Start(0)).await?;
+  }
+  Ok(())
+}
+
+fn copy_into_query(name: &str, header: &table::Header, dialect: &file::Dialect) -> String {
   let header = header
     .into_iter()
     .map(|(col_name, _)| col_name.as_str())
     .collect::<Vec<_>>()
     .join(",");
-  let query = format!(
-    "COPY {} ({}) FROM '{}' DELIMITER ',' CSV HEADER",
-    name, header, table_path
+  let mut options = format!(
+    "FORMAT csv, HEADER {}, DELIMITER {}, QUOTE {}, ESCAPE {}, NULL {}",
+    dialect.header,
+    quote_literal(&dialect.delimiter.to_string()),
+    quote_literal(&dialect.quote.to_string()),
+    quote_literal(&dialect.escape.to_string()),
+    quote_literal(&dialect.null)
   );
+  if let Some(encoding) = &dialect.encoding {
+    options.push_str(format!(", ENCODING {}", quote_literal(encoding)).as_str());
+  }
+  let query = format!("COPY {} ({}) FROM STDIN WITH ({})", name, header, options);
   query
 }
 
-fn list_tables_query(is_verbose: bool) -> String {
+// Wraps a string in single quotes, escaping any single quotes within it
+fn quote_literal(value: &str) -> String {
+  format!("'{}'", value.replace("'", "''"))
+}
+
+// Tables of the session schema
+fn list_tables_query(schema: &str, is_verbose: bool) -> String {
   if is_verbose {
-    let query = "
+    let query = format!(
+      "
       SELECT
         n.nspname AS \"Schema\",
         c.relname AS \"Table\",
@@ -176,25 +340,31 @@ fn list_tables_query(is_verbose: bool) -> String {
         LEFT JOIN pg_catalog.pg_authid a
         ON c.relowner = a.oid
       WHERE c.relkind = ANY (ARRAY['r', 'i', 'S', 'p', 'f', 'v'])
-        AND n.nspname = 'public'
+        AND n.nspname = {}
       ORDER BY 1,2
-      ";
-    query.to_string()
+      ",
+      quote_literal(schema)
+    );
+    query
   } else {
-    let query = "
+    let query = format!(
+      "
       SELECT
         c.relname AS \"Table\"
       FROM pg_catalog.pg_class c
         LEFT JOIN pg_catalog.pg_namespace n
         ON n.oid = c.relnamespace
       WHERE c.relkind = ANY (ARRAY['r', 'i', 'S', 'p', 'f', 'v'])
-        AND n.nspname = 'public'
-      ";
-    query.to_string()
+        AND n.nspname = {}
+      ",
+      quote_literal(schema)
+    );
+    query
   }
 }
 
-fn get_table_info_query(table_name: &str, is_verbose: bool) -> String {
+// Columns of a table of the session schema
+fn get_table_info_query(schema: &str, table_name: &str, is_verbose: bool) -> String {
   if is_verbose {
     let query = format!(
       "
@@ -218,10 +388,11 @@ fn get_table_info_query(table_name: &str, is_verbose: bool) -> String {
           SELECT c.oid
           FROM pg_catalog.pg_class c
             LEFT JOIN pg_catalog.pg_namespace n on n.oid = c.relnamespace
-          WHERE c.relname = '{}' AND pg_catalog.pg_table_is_visible(c.oid)
+          WHERE c.relname = {} AND n.nspname = {}
         )
       ",
-      table_name
+      quote_literal(table_name),
+      quote_literal(schema)
     );
     query
   } else {
@@ -231,9 +402,10 @@ fn get_table_info_query(table_name: &str, is_verbose: bool) -> String {
         column_name AS \"Column\",
         data_type AS \"Datatype\"
       FROM information_schema.columns
-      WHERE (table_schema, table_name) = ('public', '{}')
+      WHERE (table_schema, table_name) = ({}, {})
       ",
-      table_name
+      quote_literal(schema),
+      quote_literal(table_name)
     );
     query
   }
@@ -1,16 +1,45 @@
+use crate::file;
 use crate::table;
 use async_trait::async_trait;
-use tokio_postgres::error::Error;
+use std::sync::Mutex;
+
+// Errors of the database a querier is backed by
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+// Storing a table can fail on reading its file as well as in the database
+pub type StoreError = Error;
+
+// Databases csvql can run queries in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+  Postgres, // Server given by DATABASE_URL
+  Sqlite,   // Embedded database, needing no server at all
+  Memory,   // Tables held and queried by csvql itself
+}
+
+impl Backend {
+  pub const NAMES: [&'static str; 3] = ["postgres", "sqlite", "memory"];
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "postgres" => Some(Backend::Postgres),
+      "sqlite" => Some(Backend::Sqlite),
+      "memory" => Some(Backend::Memory),
+      _ => None,
+    }
+  }
+}
 
 #[async_trait]
-pub trait QuerierTrait {
+pub trait QuerierTrait: Send + Sync {
   async fn store(
     &self,
     table_path: &str,
     table_name: &str,
     table_header: table::Header,
+    table_constraints: &table::Constraints,
+    table_dialect: &file::Dialect,
     // table_data: table::Rows,
-  ) -> Result<(), Error>;
+  ) -> Result<(), StoreError>;
   async fn load(
     &self,
     table_name: &str,
@@ -20,4 +49,186 @@ pub trait QuerierTrait {
   async fn drop(&self, table_name: &str) -> Result<(), Error>;
   async fn list(&self, is_verbose: bool) -> Result<Option<table::Table>, Error>;
   async fn info(&self, table_name: &str, is_verbose: bool) -> Result<Option<table::Table>, Error>;
+  // Drops every relation created in the session, newest first. Returns the names of the
+  // ones that could not be dropped.
+  async fn clean(&self) -> Vec<String>;
+  // Where the tables of the session are kept, e.g. schema csvql_1234_1600000000000
+  fn location(&self) -> String;
+}
+
+// Kinds of relations a session creates, which are dropped differently
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelationKind {
+  Table,
+  View,
+  MaterializedView,
+}
+
+impl RelationKind {
+  pub fn keyword(&self) -> &'static str {
+    match self {
+      RelationKind::Table => "TABLE",
+      RelationKind::View => "VIEW",
+      RelationKind::MaterializedView => "MATERIALIZED VIEW",
+    }
+  }
+
+  fn from_keyword(keyword: &str) -> Self {
+    let keyword = keyword.to_uppercase();
+    if keyword.starts_with("MATERIALIZED") {
+      RelationKind::MaterializedView
+    } else if keyword == "VIEW" {
+      RelationKind::View
+    } else {
+      RelationKind::Table
+    }
+  }
+}
+
+// Relations created in a session, oldest first, so that only those are dropped when it
+// ends. Names are kept as they were written in the statement that created them.
+#[derive(Debug, Default)]
+pub struct Created {
+  relations: Mutex<Vec<(RelationKind, String)>>,
+}
+
+impl Created {
+  pub fn add(&self, kind: RelationKind, name: &str) {
+    self
+      .relations
+      .lock()
+      .unwrap()
+      .push((kind, name.to_string()));
+  }
+
+  pub fn remove(&self, name: &str) {
+    let name = normalize_name(name);
+    self
+      .relations
+      .lock()
+      .unwrap()
+      .retain(|(_, created)| normalize_name(created) != name);
+  }
+
+  pub fn rename(&self, name: &str, new_name: &str) {
+    let name = normalize_name(name);
+    for (_, created) in self.relations.lock().unwrap().iter_mut() {
+      if normalize_name(created) == name {
+        *created = new_name.to_string();
+      }
+    }
+  }
+
+  // Takes all relations out, newest first
+  pub fn take(&self) -> Vec<(RelationKind, String)> {
+    let mut relations = std::mem::take(&mut *self.relations.lock().unwrap());
+    relations.reverse();
+    relations
+  }
+
+  // Tracks the relations a statement creates, drops or renames, once it succeeded. Relations
+  // that may have existed before, i.e. CREATE .. IF NOT EXISTS and CREATE OR REPLACE, are
+  // only tracked if existed_before is false.
+  pub fn track(&self, statement: &Statement, existed_before: bool) {
+    match statement {
+      Statement::Create(kind, name, _) if !existed_before => self.add(*kind, name),
+      Statement::Drop(names) => names.iter().for_each(|name| self.remove(name)),
+      Statement::Rename(name, new_name) => self.rename(name, new_name),
+      _ => (),
+    }
+  }
+}
+
+// Comments a statement may start with
+const LEADING_COMMENTS: &str = r"^(?:\s+|--[^\n]*(?:\n|$)|/\*.*?\*/)*";
+// Unquoted or quoted identifier, optionally qualified by a schema
+const NAME: &str = r#"(?:"(?:[^"]|"")+"|[\w$]+)(?:\s*\.\s*(?:"(?:[^"]|"")+"|[\w$]+))?"#;
+
+lazy_static! {
+  static ref CREATE_RE: regex::Regex = regex::Regex::new(&format!(
+    r"(?is){}CREATE\s+(OR\s+REPLACE\s+)?(?:(?:GLOBAL|LOCAL)\s+)?(?:(?:TEMP|TEMPORARY|UNLOGGED)\s+)?(?:RECURSIVE\s+)?(TABLE|VIEW|MATERIALIZED\s+VIEW)\s+(IF\s+NOT\s+EXISTS\s+)?({})",
+    LEADING_COMMENTS, NAME
+  ))
+  .unwrap();
+  static ref DROP_RE: regex::Regex = regex::Regex::new(&format!(
+    r"(?is){}DROP\s+(?:TABLE|VIEW|MATERIALIZED\s+VIEW)\s+(?:IF\s+EXISTS\s+)?((?:{})(?:\s*,\s*(?:{}))*)",
+    LEADING_COMMENTS, NAME, NAME
+  ))
+  .unwrap();
+  static ref RENAME_RE: regex::Regex = regex::Regex::new(&format!(
+    r"(?is){}ALTER\s+(?:TABLE|VIEW|MATERIALIZED\s+VIEW)\s+(?:IF\s+EXISTS\s+)?({})\s+RENAME\s+TO\s+({})",
+    LEADING_COMMENTS, NAME, NAME
+  ))
+  .unwrap();
+  static ref NAME_RE: regex::Regex = regex::Regex::new(&format!("^{}$", NAME)).unwrap();
+}
+
+// What a statement does to the relations of a session
+#[derive(Debug, PartialEq)]
+pub enum Statement {
+  Create(RelationKind, String, bool), // Whether the relation may have existed before
+  Drop(Vec<String>),
+  Rename(String, String),
+  Other,
+}
+
+pub fn parse_statement(statement: &str) -> Statement {
+  if let Some(captures) = CREATE_RE.captures(statement) {
+    let may_exist = captures.get(1).is_some() || captures.get(3).is_some();
+    let kind = RelationKind::from_keyword(&captures[2]);
+    return Statement::Create(kind, captures[4].to_string(), may_exist);
+  }
+  if let Some(captures) = DROP_RE.captures(statement) {
+    let names = captures[1]
+      .split(',')
+      .map(|name| name.trim().to_string())
+      .filter(|name| NAME_RE.is_match(name))
+      .collect();
+    return Statement::Drop(names);
+  }
+  if let Some(captures) = RENAME_RE.captures(statement) {
+    // A renamed relation keeps the schema of its old name
+    let new_name = match schema_of(&captures[1]) {
+      Some(schema) => format!("{}.{}", schema, &captures[2]),
+      None => captures[2].to_string(),
+    };
+    return Statement::Rename(captures[1].to_string(), new_name);
+  }
+  Statement::Other
+}
+
+// Schema a relation name is qualified by, if any
+pub fn schema_of(name: &str) -> Option<&str> {
+  let mut is_quoted = false;
+  for (index, c) in name.char_indices() {
+    match c {
+      '"' => is_quoted = !is_quoted,
+      '.' if !is_quoted => return Some(name[..index].trim()),
+      _ => (),
+    }
+  }
+  None
+}
+
+// Identifiers as postgres resolves them, i.e. unquoted ones are lower cased
+pub fn normalize_name(name: &str) -> String {
+  let mut normalized = String::new();
+  let mut chars = name.trim().chars().peekable();
+  while let Some(c) = chars.next() {
+    if c == '"' {
+      while let Some(c) = chars.next() {
+        match c {
+          '"' if chars.peek() == Some(&'"') => {
+            chars.next();
+            normalized.push('"');
+          }
+          '"' => break,
+          _ => normalized.push(c),
+        }
+      }
+    } else if !c.is_whitespace() {
+      normalized.extend(c.to_lowercase());
+    }
+  }
+  normalized
 }
@@ -0,0 +1,369 @@
+use crate::file;
+use crate::querier::{self, QuerierTrait, StoreError};
+use crate::table;
+use crate::types;
+use async_trait::async_trait;
+use rusqlite::{Connection, NO_PARAMS};
+use std::sync::Mutex;
+use std::{env, fs, io, path, process, time};
+
+// Queries an embedded SQLite database, kept in memory unless a file is given. Statements
+// run on the calling task, since there is no server to wait on.
+#[derive(Debug)]
+pub struct Querier {
+  pub path: Option<path::PathBuf>, // Database file, or None for an in-memory database
+  connection: Mutex<Connection>,
+  created: querier::Created, // Relations created in this session
+}
+
+impl Querier {
+  pub fn new(database_path: Option<&path::Path>) -> rusqlite::Result<Self> {
+    let connection = match database_path {
+      Some(database_path) => Connection::open(database_path)?,
+      None => Connection::open_in_memory()?,
+    };
+    Ok(Self {
+      path: database_path.map(path::Path::to_path_buf),
+      connection: Mutex::new(connection),
+      created: querier::Created::default(),
+    })
+  }
+
+  // Whether a relation of the given name, as it would be written in a statement, exists
+  fn exists(&self, name: &str) -> rusqlite::Result<bool> {
+    let name = querier::normalize_name(name);
+    let name = name.rsplit('.').next().unwrap_or("");
+    self.connection.lock().unwrap().query_row(
+      "SELECT count(*) > 0 FROM sqlite_master WHERE lower(name) = lower(?)",
+      &[name],
+      |row| row.get(0),
+    )
+  }
+
+  // Inserts every record of a csv file into a table, within a single transaction
+  fn insert(
+    &self,
+    table_path: &str,
+    table_name: &str,
+    table_header: &table::Header,
+    table_dialect: &file::Dialect,
+  ) -> Result<(), StoreError> {
+    let file = fs::File::open(table_path)?;
+    let mut reader = file::Reader::new(io::BufReader::new(file), table_dialect);
+    if table_dialect.header {
+      reader.read_record()?;
+    }
+    let mut connection = self.connection.lock().unwrap();
+    let transaction = connection.transaction()?;
+    {
+      let mut statement = transaction.prepare(insert_query(table_name, table_header).as_str())?;
+      while let Some(record) = reader.read_record()? {
+        if record.len() != table_header.len() {
+          return Err(
+            format!(
+              "Expected {} fields but got {} on line {}.",
+              table_header.len(),
+              record.len(),
+              reader.record_line()
+            )
+            .into(),
+          );
+        }
+        let values = table_header
+          .iter()
+          .zip(reader.values(record))
+          .map(|((_, column_type), value)| match value {
+            Some(value) => types::sqlite::to_sql_value(column_type, &value),
+            None => rusqlite::types::Value::Null,
+          })
+          .collect::<Vec<_>>();
+        statement
+          .execute(values)
+          .map_err(|e| format!("{} on line {}.", e, reader.record_line()))?;
+      }
+    }
+    transaction.commit()?;
+    Ok(())
+  }
+
+  // Runs a statement, returning the rows it results in as a table if there are any
+  fn execute(&self, query_statement: &str) -> rusqlite::Result<Option<table::Table>> {
+    let connection = self.connection.lock().unwrap();
+    let mut statement = connection.prepare(query_statement)?;
+    if statement.column_count() == 0 {
+      statement.execute(NO_PARAMS)?;
+      return Ok(None);
+    }
+    let declared_types = statement
+      .columns()
+      .iter()
+      .map(|column| {
+        (
+          column.name().to_string(),
+          column.decl_type().map(String::from),
+        )
+      })
+      .collect::<Vec<_>>();
+    let mut rows = statement.query(NO_PARAMS)?;
+    let mut table_rows = table::Rows::new();
+    while let Some(row) = rows.next()? {
+      let values = (0..declared_types.len())
+        .map(|index| row.get_raw_checked(index).map(types::sqlite::get_value))
+        .collect::<rusqlite::Result<table::Row>>()?;
+      table_rows.push(values);
+    }
+    if table_rows.is_empty() {
+      return Ok(None);
+    }
+    let header = declared_types
+      .into_iter()
+      .enumerate()
+      .map(|(index, (column_name, declared_type))| {
+        let value = table_rows
+          .iter()
+          .map(|row| &row[index])
+          .find(|value| !value.is_null());
+        let column_type = types::sqlite::result_type(declared_type.as_deref(), value);
+        (column_name, column_type)
+      })
+      .collect::<table::Header>();
+    Ok(Some(table::Table::new(header, table_rows)))
+  }
+}
+
+// File a database is kept in past the session, named like the schemas of postgres sessions
+pub fn session_path() -> path::PathBuf {
+  let millis = time::SystemTime::now()
+    .duration_since(time::UNIX_EPOCH)
+    .map_or(0, |duration| duration.as_millis());
+  env::temp_dir().join(format!("csvql_{}_{}.sqlite3", process::id(), millis))
+}
+
+#[async_trait]
+impl QuerierTrait for Querier {
+  async fn store(
+    &self,
+    table_path: &str,
+    table_name: &str,
+    table_header: table::Header,
+    table_constraints: &table::Constraints,
+    table_dialect: &file::Dialect,
+  ) -> Result<(), StoreError> {
+    self
+      .connection
+      .lock()
+      .unwrap()
+      .execute_batch(create_table_query(table_name, &table_header, table_constraints).as_str())?;
+    self.created.add(querier::RelationKind::Table, table_name);
+    let result = self.insert(table_path, table_name, &table_header, table_dialect);
+    if result.is_err() {
+      // A file that breaks the constraints of its table leaves no table behind
+      let _ = self.drop(table_name).await;
+    }
+    result
+  }
+
+  async fn query(&self, query_statement: &str) -> Result<Option<table::Table>, querier::Error> {
+    let statement = querier::parse_statement(query_statement);
+    let existed_before = match &statement {
+      querier::Statement::Create(_, name, true) => self.exists(name)?,
+      _ => false,
+    };
+    let table = self.execute(query_statement)?;
+    self.created.track(&statement, existed_before);
+    Ok(table)
+  }
+
+  async fn load(
+    &self,
+    table_name: &str,
+    num_rows: Option<usize>,
+  ) -> Result<Option<table::Table>, querier::Error> {
+    let query = match num_rows {
+      Some(num_rows) => format!("SELECT * FROM {} LIMIT {}", table_name, num_rows),
+      None => format!("SELECT * FROM {}", table_name),
+    };
+    self.query(query.as_str()).await
+  }
+
+  async fn drop(&self, table_name: &str) -> Result<(), querier::Error> {
+    self
+      .query(format!("DROP TABLE {}", table_name).as_str())
+      .await
+      .map(|_| ())
+  }
+
+  async fn clean(&self) -> Vec<String> {
+    let mut failed = Vec::new();
+    for (kind, name) in self.created.take() {
+      // SQLite has no materialized views, they are dropped like any other view
+      let keyword = match kind {
+        querier::RelationKind::Table => "TABLE",
+        _ => "VIEW",
+      };
+      let statement = format!("DROP {} IF EXISTS {}", keyword, name);
+      if self
+        .connection
+        .lock()
+        .unwrap()
+        .execute_batch(statement.as_str())
+        .is_err()
+      {
+        failed.push(name);
+      }
+    }
+    failed
+  }
+
+  fn location(&self) -> String {
+    match &self.path {
+      Some(path) => format!("database file {}", path.display()),
+      None => "an in-memory database".to_string(),
+    }
+  }
+
+  async fn list(&self, is_verbose: bool) -> Result<Option<table::Table>, querier::Error> {
+    self.query(list_tables_query(is_verbose)).await
+  }
+
+  async fn info(
+    &self,
+    table_name: &str,
+    is_verbose: bool,
+  ) -> Result<Option<table::Table>, querier::Error> {
+    self
+      .query(get_table_info_query(table_name, is_verbose).as_str())
+      .await
+  }
+}
+
+// HELPERS
+// Columns are declared with the affinity of their type, along with their constraints
+fn create_table_query(
+  table_name: &str,
+  table_header: &table::Header,
+  table_constraints: &table::Constraints,
+) -> String {
+  let schema = table_header
+    .iter()
+    .enumerate()
+    .map(|(index, (col_name, col_type))| {
+      let constraints = table_constraints.get(index).map_or(&[][..], Vec::as_slice);
+      std::iter::once(format!(
+        "{} {}",
+        col_name,
+        types::sqlite::to_affinity(col_type)
+      ))
+      .chain(
+        constraints
+          .iter()
+          .map(|constraint| constraint.to_sql(col_name)),
+      )
+      .collect::<Vec<_>>()
+      .join(" ")
+    })
+    .collect::<Vec<_>>()
+    .join(",");
+  format!("CREATE TABLE {} ({})", table_name, schema)
+}
+
+fn insert_query(table_name: &str, table_header: &table::Header) -> String {
+  let columns = table_header
+    .iter()
+    .map(|(col_name, _)| col_name.as_str())
+    .collect::<Vec<_>>()
+    .join(",");
+  let parameters = vec!["?"; table_header.len()].join(",");
+  format!(
+    "INSERT INTO {} ({}) VALUES ({})",
+    table_name, columns, parameters
+  )
+}
+
+// Wraps a string in single quotes, escaping any single quotes within it
+fn quote_literal(value: &str) -> String {
+  format!("'{}'", value.replace("'", "''"))
+}
+
+fn list_tables_query(is_verbose: bool) -> &'static str {
+  if is_verbose {
+    "
+    SELECT
+      'main' AS \"Schema\",
+      name AS \"Table\",
+      type AS \"Type\"
+    FROM sqlite_master
+    WHERE type IN ('table', 'index', 'view') AND name NOT LIKE 'sqlite_%'
+    ORDER BY 1,2
+    "
+  } else {
+    "
+    SELECT
+      name AS \"Table\"
+    FROM sqlite_master
+    WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%'
+    "
+  }
+}
+
+fn get_table_info_query(table_name: &str, is_verbose: bool) -> String {
+  if is_verbose {
+    format!(
+      "
+      SELECT
+        name AS \"Column\",
+        type AS \"Datatype\",
+        COALESCE(dflt_value, '-') AS \"Default\",
+        CASE
+          WHEN \"notnull\" THEN 'false'
+          ELSE 'true'
+        END AS \"Nullable\"
+      FROM pragma_table_info({})
+      ",
+      quote_literal(table_name)
+    )
+  } else {
+    format!(
+      "
+      SELECT
+        name AS \"Column\",
+        type AS \"Datatype\"
+      FROM pragma_table_info({})
+      ",
+      quote_literal(table_name)
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::types::value::Value;
+
+  #[tokio::test]
+  async fn stores_quoted_empty_strings_apart_from_nulls() {
+    let path = std::env::temp_dir().join(format!("csvql_sqlite_{}.csv", std::process::id()));
+    fs::write(&path, "a,b\n\"\",z\n,y\n").unwrap();
+    let db_querier = Querier::new(None).unwrap();
+    let header = vec![
+      ("a".to_string(), "TEXT".to_string()),
+      ("b".to_string(), "TEXT".to_string()),
+    ];
+    let stored = db_querier
+      .store(
+        path.to_str().unwrap(),
+        "t",
+        header,
+        &Vec::new(),
+        &file::Dialect::default(),
+      )
+      .await;
+    fs::remove_file(&path).unwrap();
+    stored.unwrap();
+    let table = db_querier.query("SELECT a FROM t").await.unwrap().unwrap();
+    assert_eq!(
+      table.rows,
+      vec![vec![Value::Text(String::new())], vec![Value::Null]]
+    );
+  }
+}
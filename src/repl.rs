@@ -1,6 +1,8 @@
-use crate::query::{postgres, querier};
+use crate::file::{Dialect, ExportOptions};
+use crate::import;
+use crate::query::querier;
 use crate::table::{Purveyor, Table};
-use crate::util::{evict, less, validate};
+use crate::util::{evict, less};
 
 use ansi_term::Color;
 use ansi_term::Style;
@@ -19,12 +21,10 @@ use rustyline::{Cmd, CompletionType, Config, EditMode, KeyEvent, Modifiers, Move
 use rustyline_derive::{Completer, Helper, Highlighter, Hinter};
 use std::io::{stdin, stdout, Write};
 use std::str::FromStr;
-use std::{collections, env, path};
-use validate::Validate;
+use std::{collections, path};
 
 const QUERY_TABLE_HISTORY_CAPACITY: usize = 20;
 const MAX_PRINTABLE_ROWS: usize = 20;
-const DATABASE_URL_KEY: &str = "DATABASE_URL";
 
 enum Repl<'a> {
   Quit,
@@ -36,15 +36,15 @@ enum Repl<'a> {
 #[derive(Debug, Clone)]
 enum Command {
   Invalid(String),
-  Quit,                                      // Quit the REPL
-  Help,                                      // Get help info for REPL
-  Usage,                                     // Get usage examples for the REPL
-  Query(String),                             // Execute a SQL query
-  Import(String, Option<String>),            // Import a csv/json input file as a table in the db
-  Export(Option<bool>, bool, usize, String), // Export a table into a csv/json output file
-  List(bool),                                // List all tables, views, seqs concisely or verbosely
-  Info(bool, String),                        // Show concise or verbose information on a table
-  Clear,                                     // Clears repl screen
+  Quit,                                                     // Quit the REPL
+  Help,                                                     // Get help info for REPL
+  Usage,                                                    // Get usage examples for the REPL
+  Query(String),                                            // Execute a SQL query
+  Import(String, Option<String>, Dialect, bool, bool), // Import a csv/json input file as a table in the db
+  Export(Option<bool>, bool, usize, String, ExportOptions), // Export a table into a csv/json output file
+  List(bool),         // List all tables, views, seqs concisely or verbosely
+  Info(bool, String), // Show concise or verbose information on a table
+  Clear,              // Clears repl screen
 }
 
 #[derive(Completer, Helper, Highlighter, Hinter)]
@@ -68,18 +68,28 @@ impl Validator for InputValidator {
   }
 }
 
-pub async fn run() {
+// Startup plan for the repl, resolved from command line arguments
+pub struct Startup {
+  pub imports: Vec<(String, Option<String>)>, // Paths of csv files to import with optional aliases
+  pub export: Option<String>,                 // Default export target for \e without a path
+  pub dialect: Dialect,                       // Default csv dialect of imported files
+  pub infer_types: bool,                      // Infer the types of unannotated columns
+  pub reject_rows: bool,                      // Set aside rows that do not fit their table
+  pub export_options: ExportOptions,          // Default options of exported files
+  pub null_marker: String,                    // What NULL values are displayed as
+  pub keep: bool,                             // Leave the tables created in the session
+}
+
+pub async fn run(startup: Startup, db_querier: &dyn QuerierTrait) {
   let mut query_history: Vec<(usize, Command)> = Vec::new();
   let mut query_table_history: VecDeque<(usize, Command, Table)> =
     VecDeque::evl_new(QUERY_TABLE_HISTORY_CAPACITY);
 
-  // Get the querier
-  // Handle these Errors...
-  // This should be at repl level?
-  let db_url = env::var(DATABASE_URL_KEY).unwrap();
-  let db_querier = postgres::Querier::new("postgres", db_url.as_str())
-    .await
-    .unwrap();
+  let mut directives = import::Directives::new(
+    startup.dialect.clone(),
+    startup.infer_types,
+    startup.reject_rows,
+  );
 
   // rustyline reader configuration
   let config = Config::builder()
@@ -115,6 +125,29 @@ pub async fn run() {
     () // do nothing, but auto create the history.txt file
   }
 
+  // Import the tables given at startup before handing control to the user
+  for (path, optional_name) in startup.imports.iter() {
+    let result = execute_command(
+      0,
+      &mut query_history,
+      &mut query_table_history,
+      db_querier,
+      &mut directives,
+      &startup.null_marker,
+      Command::Import(
+        path.clone(),
+        optional_name.clone(),
+        startup.dialect.clone(),
+        startup.infer_types,
+        startup.reject_rows,
+      ),
+    )
+    .await;
+    if let Repl::AlertThenContinue(alert) = result {
+      println!("{}\n", alert);
+    }
+  }
+
   // Read Eval Print Loop
   let mut count: i128 = -1;
   loop {
@@ -157,12 +190,14 @@ pub async fn run() {
         .paint((count as u128).to_string().as_str())
     );
 
-    let user_command = into_command(count as usize, user_input);
+    let user_command = into_command(count as usize, user_input, &startup);
     let result = execute_command(
       count as usize,
       &mut query_history,
       &mut query_table_history,
-      &db_querier,
+      db_querier,
+      &mut directives,
+      &startup.null_marker,
       user_command,
     )
     .await;
@@ -180,25 +215,37 @@ pub async fn run() {
       }
     }
   }
-  // Clean up tables_in_database HashSet
+  // Drop the tables created in the session
   match reader.append_history("history.txt") {
     Err(e) => println!("Could not append to history. Error: {:#?}", e),
     _ => (),
   }
-  clean_database(&db_querier).await;
+  if startup.keep {
+    println!(
+      "Kept the tables of the session in {}.",
+      db_querier.location()
+    );
+  } else {
+    clean_database(db_querier).await;
+  }
   println!("");
 }
 
 // TODO factor this function out a bit
-fn into_command(command_index: usize, user_input: String) -> Command {
+fn into_command(command_index: usize, user_input: String, startup: &Startup) -> Command {
+  let default_export = &startup.export;
   if line_is_invalid(&user_input) {
     return Command::Invalid(user_input);
   }
 
-  if user_input.ends_with(";") {
+  if user_input.ends_with(";") && !user_input.trim_start().starts_with("\\") {
     return Command::Query(user_input.strip_suffix(";").unwrap().to_string());
   }
 
+  if line_is_directive(&user_input) {
+    return Command::Query(user_input);
+  }
+
   let user_input_args = user_input
     .split(" ")
     .filter(|element| element != &"")
@@ -212,27 +259,79 @@ fn into_command(command_index: usize, user_input: String) -> Command {
     ["\\?"] | ["\\usage"] => return Command::Usage,
     ["\\c"] | ["\\clear"] => return Command::Clear,
     [command, tail @ ..] => match *command {
-      "\\i" | "\\import" => match tail {
-        [path] => {
-          return Command::Import(path.to_string(), None);
-        }
-        [path, name] => {
-          return Command::Import(path.to_string(), Some(name.to_string()));
+      "\\i" | "\\import" => {
+        // Trailing option=value arguments override the default csv dialect
+        let mut dialect = startup.dialect.clone();
+        let mut arguments = Vec::new();
+        for argument in tail.iter() {
+          match argument.split_once('=') {
+            Some((option, value)) if Dialect::OPTIONS.contains(&option) => {
+              if let Err(message) = dialect.set(option, value) {
+                return Command::Invalid(message);
+              }
+            }
+            _ => arguments.push(*argument),
+          }
         }
-        _ => {
-          return Command::Invalid(user_input);
+        match arguments.as_slice() {
+          [path] => Command::Import(
+            path.to_string(),
+            None,
+            dialect,
+            startup.infer_types,
+            startup.reject_rows,
+          ),
+          [path, name] => Command::Import(
+            path.to_string(),
+            Some(name.to_string()),
+            dialect,
+            startup.infer_types,
+            startup.reject_rows,
+          ),
+          _ => Command::Invalid(user_input),
         }
-      },
+      }
       "\\e" | "\\export" => {
         // Export regex for extracting i from out[i]
         lazy_static! {
           static ref OUT_RE: regex::Regex = regex::Regex::new(r"out\[(\d+)\]").unwrap();
         }
-        match tail {
-          [path] => return Command::Export(None, false, 1, path.to_string()),
+        // Trailing option=value arguments override the default export options
+        let mut export_options = startup.export_options.clone();
+        let mut arguments = Vec::new();
+        for argument in tail.iter() {
+          match argument.split_once('=') {
+            Some((option, value)) if ExportOptions::OPTIONS.contains(&option) => {
+              if let Err(message) = export_options.set(option, value) {
+                return Command::Invalid(message);
+              }
+            }
+            _ => arguments.push(*argument),
+          }
+        }
+        match arguments.as_slice() {
+          // Without a path, export to the default export target given at startup
+          [] if default_export.is_some() => Command::Export(
+            None,
+            false,
+            1,
+            default_export.clone().unwrap(),
+            export_options,
+          ),
+          [n] if default_export.is_some() && usize::from_str(n).is_ok() => {
+            let which_query = usize::from_str(n).unwrap();
+            Command::Export(
+              None,
+              false,
+              which_query,
+              default_export.clone().unwrap(),
+              export_options,
+            )
+          }
+          [path] => Command::Export(None, false, 1, path.to_string(), export_options),
           [j @ "true", path] | [j @ "false", path] => {
             let use_json = if *j == "true" { true } else { false };
-            return Command::Export(Some(use_json), false, 1, path.to_string());
+            Command::Export(Some(use_json), false, 1, path.to_string(), export_options)
           }
           [n, path] if !OUT_RE.is_match(n) => {
             let which_query;
@@ -240,7 +339,7 @@ fn into_command(command_index: usize, user_input: String) -> Command {
               Ok(num) => which_query = num,
               _ => return Command::Invalid("Could not parse query number n.".to_string()),
             };
-            return Command::Export(None, false, which_query, path.to_string());
+            Command::Export(None, false, which_query, path.to_string(), export_options)
           }
           [out, path] if OUT_RE.is_match(out) => {
             let index: i32 = OUT_RE
@@ -251,7 +350,7 @@ fn into_command(command_index: usize, user_input: String) -> Command {
             if index == -1 {
               return Command::Invalid(user_input);
             }
-            return Command::Export(None, true, index as usize, path.to_string());
+            Command::Export(None, true, index as usize, path.to_string(), export_options)
           }
           [j, n, path] => {
             let use_json = if *j == "true" { true } else { false };
@@ -260,9 +359,15 @@ fn into_command(command_index: usize, user_input: String) -> Command {
               Ok(num) => which_query = num,
               _ => return Command::Invalid("Could not parse query number n.".to_string()),
             };
-            return Command::Export(Some(use_json), false, which_query, path.to_string());
+            Command::Export(
+              Some(use_json),
+              false,
+              which_query,
+              path.to_string(),
+              export_options,
+            )
           }
-          _ => return Command::Invalid(user_input),
+          _ => Command::Invalid(user_input),
         }
       }
       "\\d" => match tail {
@@ -284,7 +389,9 @@ async fn execute_command<'a>(
   command_index: usize,
   query_history: &'a mut Vec<(usize, Command)>,
   query_table_history: &'a mut VecDeque<(usize, Command, Table)>,
-  db_querier: &postgres::Querier,
+  db_querier: &dyn QuerierTrait,
+  directives: &mut import::Directives,
+  null_marker: &str,
   command: Command,
 ) -> Repl<'a> {
   // Execute the given command
@@ -295,6 +402,23 @@ async fn execute_command<'a>(
     Command::Usage => less_usage(),
     Command::Clear => return Repl::ClearAndContinue,
     Command::Query(query_statement) => {
+      // Import tables referenced by #[import(...)] directives and strip them from the query
+      let query_statement = match directives.resolve(db_querier, &query_statement, None).await {
+        Ok((query_statement, imported_tables)) => {
+          for table_name in imported_tables.iter() {
+            print_imported(db_querier, table_name, None, null_marker).await;
+          }
+          query_statement
+        }
+        Err(e) => {
+          println!("{}", e);
+          return Repl::AlertThenContinue("Failure. Could not resolve table imports.");
+        }
+      };
+      if query_statement.trim().is_empty() {
+        return Repl::Continue;
+      }
+
       // handle this error.
       let result = db_querier.query(query_statement.as_str()).await;
       match result {
@@ -303,7 +427,8 @@ async fn execute_command<'a>(
       }
       let result = result.unwrap();
       match result {
-        Some(table) => {
+        Some(mut table) => {
+          table.set_null_marker(null_marker);
           print_table(&table);
           // TODO need to also add a table pointer as an element here...
           let query_statement_result = (
@@ -317,87 +442,36 @@ async fn execute_command<'a>(
         Option::None => return Repl::AlertThenContinue("Success!"),
       }
     }
-    Command::Import(path, optional_name) => {
-      // Validate and Resolve the relative or absolute path
+    Command::Import(path, optional_name, dialect, infer_types, reject_rows) => {
       let path = path::Path::new(path.as_str());
-      let validator_result = path.validate();
-      match validator_result {
-        Err(_) => return Repl::AlertThenContinue("Invalid file path to import from. Path: {}"),
-        _ => (),
-      }
-
-      // Yield the pathInfo associated with the relative path given
-      let path_info = validator_result.unwrap();
-      if path_info.path.is_dir() {
-        return Repl::AlertThenContinue("Given path is to a directory. Must be a csv file.");
-      }
-
-      // Import table given the resolved absolute path
-      let import_result = Table::import(path_info.path.as_path());
-      match import_result {
-        Err(_) => return Repl::AlertThenContinue("Failure. Table import error occurred."),
-        _ => (),
-      }
-      let table_name = if optional_name == None {
-        path_info.filename.unwrap()
-      } else {
-        optional_name.unwrap()
-      };
-      let mut table = import_result.unwrap();
-      table.set_name(table_name.clone());
-
-      // If this table is alrady in the database then throw
-      // TODO: Find a cheaper way to keep track of imported and query created tables?
-      let mut is_name_taken = false;
-      let result_of_list = db_querier.list(false).await;
-      match result_of_list {
-        Err(_) => {
-          return Repl::AlertThenContinue(
-            "Failure. Internal Error. Unable to confirm if table name is taken.",
+      match import::import(
+        db_querier,
+        path,
+        optional_name,
+        &dialect,
+        infer_types,
+        reject_rows,
+      )
+      .await
+      {
+        Ok((table_name, dialect)) => {
+          print_imported(
+            db_querier,
+            table_name.as_str(),
+            dialect.as_ref(),
+            null_marker,
           )
+          .await
         }
-        Ok(Some(list)) => is_name_taken = list.rows.into_iter().any(|name| table_name == name[0]),
-        Ok(None) => (),
-      }
-      if is_name_taken {
-        return Repl::AlertThenContinue("Failure. Table name already taken.");
-      } else {
-        let absolute_path = path_info.path.as_os_str().to_str().unwrap();
-        let result_of_store = db_querier
-          .store(absolute_path, table.name.unwrap().as_str(), table.header)
-          .await;
-        match result_of_store {
-          Ok(_) => {
-            let result_of_load = db_querier.load(&table_name, Some(4)).await;
-            match result_of_load {
-              Ok(Some(table)) => {
-                println!(
-                  "Success! Loaded TABLE[{}] into database. Printing the first 4 rows.\n",
-                  table_name
-                );
-                print_table(&table);
-              }
-              _ => (),
-            }
-            return Repl::Continue;
-          }
-          Err(e) => {
-            return Repl::AlertThenContinue(
-              "Failure. Error occurred while storing table in database",
-            )
-          }
+        Err(e) => {
+          println!("{}", e);
+          return Repl::AlertThenContinue("Failure. Table import error occurred.");
         }
       }
     }
-    Command::Export(to_json, use_out, query_index, path) => {
+    Command::Export(to_json, use_out, query_index, path, export_options) => {
       let export_path = path::Path::new(path.as_str());
 
-      println!(
-        "Command: {:#?}, export_path: {:?}",
-        Command::Export(to_json, use_out, query_index, path.clone()),
-        export_path
-      );
-
       if use_out {
         // let result_of_get = query_history.get(query_index);
         let result_of_get = query_history
@@ -439,7 +513,7 @@ async fn execute_command<'a>(
 
           // Retrieve exportable table and export it
           let table = result_of_query.unwrap().unwrap();
-          match table.export(&export_path, None, Some(query_index)) {
+          match table.export(&export_path, None, Some(query_index), &export_options) {
             Ok(_) => (),
             Err(_) => return Repl::AlertThenContinue("Failed to export table using out syntax."),
           }
@@ -460,7 +534,7 @@ async fn execute_command<'a>(
           }
           None => return Repl::Quit,
         }
-        match queried_table.export(&export_path, to_json, None) {
+        match queried_table.export(&export_path, to_json, None, &export_options) {
           Ok(_) => (),
           Err(_) => return Repl::AlertThenContinue("Failed to export table."),
         }
@@ -478,7 +552,8 @@ async fn execute_command<'a>(
         Err(_) => return Repl::AlertThenContinue("Failure. Internal table list error."),
         _ => (),
       }
-      let table = result.unwrap().unwrap();
+      let mut table = result.unwrap().unwrap();
+      table.set_null_marker(null_marker);
       print_table(&table);
     }
     Command::Info(is_verbose, name) => {
@@ -487,7 +562,8 @@ async fn execute_command<'a>(
         Ok(None) | Err(_) => return Repl::AlertThenContinue("Failure. Table not found."),
         _ => (),
       }
-      let table = result.unwrap().unwrap();
+      let mut table = result.unwrap().unwrap();
+      table.set_null_marker(null_marker);
       print_table(&table);
     }
   }
@@ -495,23 +571,10 @@ async fn execute_command<'a>(
   Repl::Continue
 }
 
-async fn clean_database(db_querier: &postgres::Querier) {
-  let result_of_list = db_querier.list(false).await;
-  match result_of_list {
-    Err(_) => {
-      println!("Failure. Could not clean database/i.e. drop tables.");
-      return;
-    }
-    Ok(None) => return, // no tables to drop, gracefully return
-    _ => (),
-  }
-  let tables_in_database = result_of_list.unwrap().unwrap();
-  for table_name in tables_in_database.rows.iter() {
-    // must get index 0 since table_name is technically a vector of Strings
-    match db_querier.drop(table_name[0].as_str()).await {
-      Ok(_) => (),
-      Err(_) => println!("Failure. Could not drop table with name: {}", table_name[0]),
-    }
+// Drops the tables and views created in the session, leaving all others be
+async fn clean_database(db_querier: &dyn QuerierTrait) {
+  for name in db_querier.clean().await {
+    println!("Failure. Could not drop {}.", name);
   }
 }
 
@@ -524,16 +587,24 @@ fn flush_repl() {
 
 fn line_not_terminal(line: &str) -> bool {
   let trimmed_line = line.trim();
-  !(trimmed_line.starts_with("\\") || trimmed_line.ends_with(";"))
+  !(trimmed_line.starts_with("\\") || trimmed_line.ends_with(";") || line_is_directive(line))
+}
+
+fn line_is_directive(line: &str) -> bool {
+  let trimmed_line = line.trim();
+  trimmed_line.starts_with("#[") && trimmed_line.ends_with("]")
 }
 
+// Backslash commands do not end in a semicolon, unless it is the value of their last
+// option, e.g. \i vendor.csv delimiter=;
 fn line_is_invalid(line: &str) -> bool {
   let trimmed_line = line.trim();
-  trimmed_line.starts_with("\\") && trimmed_line.ends_with(";")
+  trimmed_line.starts_with("\\") && trimmed_line.ends_with(";") && !trimmed_line.ends_with("=;")
 }
 
 fn should_be_saved_to_history(line: &str) -> bool {
   line.ends_with(";")
+    || line_is_directive(line)
     || line.starts_with("\\i")
     || line.starts_with("\\import")
     || line.starts_with("\\e")
@@ -548,6 +619,37 @@ fn print_table(table: &Table) {
   }
 }
 
+async fn print_imported(
+  db_querier: &dyn QuerierTrait,
+  table_name: &str,
+  dialect: Option<&Dialect>,
+  null_marker: &str,
+) {
+  // Show the csv dialect that was used so that users can override a wrong guess
+  let detected = match dialect {
+    Some(dialect) => format!(
+      " Read as csv with ({}), override using (\\i path [name] option=value).",
+      dialect
+    ),
+    None => "".to_string(),
+  };
+  let result_of_load = db_querier.load(table_name, Some(4)).await;
+  match result_of_load {
+    Ok(Some(mut table)) => {
+      table.set_null_marker(null_marker);
+      println!(
+        "Success! Loaded TABLE[{}] into database.{} Printing the first 4 rows.\n",
+        table_name, detected
+      );
+      print_table(&table);
+    }
+    _ => println!(
+      "Success! Loaded TABLE[{}] into database.{}\n",
+      table_name, detected
+    ),
+  }
+}
+
 fn print_error(err: std::io::Error) {
   println!(
     "
@@ -582,7 +684,8 @@ fn less_help() {
   let help = format!(
     "
     Terminology:
-      PATH              - an absolute or relative path to a csv (imports) or json file (exports can be csv or json)
+      PATH              - an absolute or relative path to a csv or ndjson/jsonl file (imports), or to a csv, json or
+                          ndjson/jsonl file (exports)
 
     General:
       \\q or \\quit     - Quit repl
@@ -593,13 +696,27 @@ fn less_help() {
     Import:
       \\i path          - Imports a csv table into the database given a PATH
       \\i path name     - Imports a csv table into the database given a PATH and aliases the table with given name
+      \\i path [name] option=value ...
+                        - Imports a csv table of a different dialect. Options are delimiter, quote, escape,
+                          null, encoding and header, e.g. (\\i data.tsv delimiter=tab null=\\N). Delimiters can
+                          be given by name: comma, semicolon, pipe, tab or space. Options that are not
+                          given are guessed from the file
       \\import          - Equivalent long form of above, same usages
+      #[import(path)]            - Imports a csv table inside of a query, see docs/imports.md
+      #[import(path) as name]    - Equivalent to (\\i path name), inline directives are replaced by name
 
     Export:
       \\e path          - Exports last query result into csv file given a PATH, equivalent to (\\e 1 path)
       \\e n path        - Exports n(th) last query (1 being most recent, max 20 query history size) into csv file
       \\e j path        - Equivalent to (e path), but exports as json
       \\e j n path      - Equivalent to (e n path), but exports as json
+      \\e [n]           - Exports to the default export target given with (csvql -e path)
+      \\e ... option=value ...
+                        - Exports with different options. Options are pretty, which indents json output, and
+                          delimiter, terminator (lf or crlf) and null of csv output. The mode option decides
+                          what happens to an existing file: overwrite (default), append or fail, e.g.
+                          (\\e out.csv mode=append null=NA). The header option writes csv headers annotated
+                          with types (default), plain, or plain with a .schema.json file of the types
       \\export          - Equivalent long form of above, same usages
 
     Informational:
@@ -710,3 +827,41 @@ fn less_usage() {
   );
   less::string(usage);
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn startup() -> Startup {
+    Startup {
+      imports: Vec::new(),
+      export: None,
+      dialect: Dialect::default(),
+      infer_types: false,
+      reject_rows: false,
+      export_options: ExportOptions::default(),
+      null_marker: String::new(),
+      keep: false,
+    }
+  }
+
+  #[test]
+  fn imports_with_semicolon_delimiter() {
+    let command = into_command(0, "\\i vendor.csv delimiter=;".to_string(), &startup());
+    match command {
+      Command::Import(path, None, dialect, _, _) => {
+        assert_eq!(path, "vendor.csv");
+        assert_eq!(dialect.delimiter, ';');
+      }
+      command => panic!("Expected an import but got {:?}", command),
+    }
+  }
+
+  #[test]
+  fn rejects_commands_ending_in_semicolon() {
+    assert!(matches!(
+      into_command(0, "\\d fruit;".to_string(), &startup()),
+      Command::Invalid(_)
+    ));
+  }
+}
@@ -1,18 +1,24 @@
 use crate::file;
+use crate::types::constraint::Constraint;
+use crate::types::value::Value;
 // use crate::query::querier;
 use async_trait::async_trait;
 // use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use ansi_term::Color;
+use ansi_term::{Color, Style};
 use std::{clone, fmt, path};
 use tokio_postgres::error as tokio_errors;
 
 type Widths = Vec<usize>;
 pub type EntryType = String; // make into tokio_postgres::types::Type
 pub type Header = Vec<(String, EntryType)>;
-pub type Row = Vec<String>;
+pub type Constraints = Vec<Vec<Constraint>>; // Constraints of each column, in header order
+pub type Row = Vec<Value>;
 pub type Rows = Vec<Row>;
 pub type Result<T> = std::result::Result<T, Error>;
 
+// What NULL values are displayed as, unless another marker is set
+pub const NULL_MARKER: &str = "NULL";
+
 #[derive(Debug)]
 enum SubError {
   BaseError,
@@ -65,18 +71,22 @@ impl std::error::Error for Error {}
 pub struct Table {
   pub name: Option<String>,
   pub header: Header,
+  pub constraints: Constraints,
   pub rows: Rows,
   widths: Widths,
+  null_marker: String,
 }
 
 impl Table {
   pub fn new(header: Header, rows: Rows) -> Self {
-    let widths = get_widths(&header, &rows);
+    let widths = get_widths(&header, &rows, NULL_MARKER);
     Self {
       name: None,
       header: header,
+      constraints: Vec::new(),
       rows: rows,
       widths: widths,
+      null_marker: NULL_MARKER.to_string(),
     }
   }
 
@@ -85,13 +95,17 @@ impl Table {
     Self {
       name: None,
       header: header,
+      constraints: Vec::new(),
       rows: Vec::new(),
       widths: Vec::new(),
+      null_marker: NULL_MARKER.to_string(),
     }
   }
 
-  pub fn set_name(&mut self, name: String) {
-    self.name = Some(name);
+  // Sets what NULL values are displayed as, which is dimmed to tell it apart from text
+  pub fn set_null_marker(&mut self, null_marker: &str) {
+    self.widths = get_widths(&self.header, &self.rows, null_marker);
+    self.null_marker = null_marker.to_string();
   }
 
   pub fn _set_header(&mut self, header: Header) {
@@ -109,14 +123,21 @@ impl Table {
     }
   }
 
-  fn fmt_row(widths: &Widths, row: &Row) -> String {
+  fn fmt_row(widths: &Widths, row: &Row, null_marker: &str) -> String {
     let zipper = widths.iter().zip(row.iter());
     format!(
       "|{}|",
       zipper
         // Adding space of padding on both sides
-        .map(|(width, entry)| format!(" {}{} ", entry, " ".repeat(width - entry.len())))
-        .collect::<Row>()
+        .map(|(width, entry)| match entry {
+          Value::Null => format!(
+            " {}{} ",
+            Style::new().dimmed().paint(null_marker),
+            " ".repeat(width - null_marker.chars().count())
+          ),
+          _ => format!(" {}{} ", entry, " ".repeat(width - entry.width())),
+        })
+        .collect::<Vec<_>>()
         .join("|")
     )
   }
@@ -137,18 +158,18 @@ impl Table {
           format!(
             " {}{} ",
             formatted_header_entry,
-            " ".repeat(width - entry.len())
+            " ".repeat(width - entry.chars().count())
           )
         })
-        .collect::<Row>()
+        .collect::<Vec<_>>()
         .join("|")
     )
   }
 
-  fn fmt_rows(widths: &Widths, rows: &Rows) -> Vec<String> {
+  fn fmt_rows(widths: &Widths, rows: &Rows, null_marker: &str) -> Vec<String> {
     rows
       .into_iter()
-      .map(|row| Self::fmt_row(widths, row))
+      .map(|row| Self::fmt_row(widths, row, null_marker))
       .collect()
   }
 
@@ -169,8 +190,14 @@ impl Table {
 
 #[async_trait]
 pub trait Purveyor {
-  fn import(path: &path::Path) -> Result<Table>;
-  fn export(&self, path: &path::Path, use_json: Option<bool>, index: Option<usize>) -> Result<()>;
+  fn import(path: &path::Path, dialect: &file::Dialect, infer_types: bool) -> Result<Table>;
+  fn export(
+    &self,
+    path: &path::Path,
+    use_json: Option<bool>,
+    index: Option<usize>,
+    options: &file::ExportOptions,
+  ) -> Result<()>;
 
   // async fn load<Q>(name: String, db_querier: Q) -> Result<Table>
   // where
@@ -183,24 +210,31 @@ pub trait Purveyor {
 
 #[async_trait]
 impl Purveyor for Table {
-  // Imports a csv table from user file system given a path
-  fn import(path: &path::Path) -> Result<Self> {
-    Ok(file::import_csv(path)?)
+  // Imports a csv table from user file system given a path and its csv dialect, inferring
+  // the types of unannotated columns if infer_types is set
+  fn import(path: &path::Path, dialect: &file::Dialect, infer_types: bool) -> Result<Self> {
+    Ok(file::import_csv(path, dialect, infer_types)?)
   }
 
   // Exports a Table to a csv or json file in the user file system
-  fn export(&self, path: &path::Path, use_json: Option<bool>, index: Option<usize>) -> Result<()> {
+  fn export(
+    &self,
+    path: &path::Path,
+    use_json: Option<bool>,
+    index: Option<usize>,
+    options: &file::ExportOptions,
+  ) -> Result<()> {
     // If no boolean is given then resolve using path file extensions
     if use_json == None {
-      return Ok(file::export(index, path, self)?);
+      return Ok(file::export(index, path, self, options)?);
     }
 
     // If explicit boolean given then can just skip file extension resolution
     // TODO: remove this and replace with just file::export, since that has parent path validation
     if use_json.unwrap() {
-      Ok(file::export_json(path, self)?)
+      Ok(file::export_json(path, self, options)?)
     } else {
-      Ok(file::export_csv(path, self)?)
+      Ok(file::export_csv(path, self, options)?)
     }
   }
 }
@@ -209,7 +243,7 @@ impl fmt::Display for Table {
   fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
     let formatted_row_separator = Table::fmt_row_separator(&self.widths);
     let formatted_header = Table::fmt_header(&self.widths, &self.header);
-    let formatted_rows = Table::fmt_rows(&self.widths, &self.rows);
+    let formatted_rows = Table::fmt_rows(&self.widths, &self.rows, &self.null_marker);
     // name
     // ----
     // head
@@ -233,8 +267,10 @@ impl clone::Clone for Table {
     Self {
       name: self.name.clone(),
       header: self.header.clone(),
+      constraints: self.constraints.clone(),
       rows: self.rows.clone(),
       widths: self.widths.clone(),
+      null_marker: self.null_marker.clone(),
     }
   }
 }
@@ -244,7 +280,7 @@ pub fn _vec_to_table_string(column_name: &str, vector: &Vec<String>) -> String {
   let header = vec![(String::from(column_name), String::from("VARCHAR(256)"))];
   let rows = vector
     .into_iter()
-    .map(|entry| vec![String::from(entry)])
+    .map(|entry| vec![Value::Text(String::from(entry))])
     .collect::<Vec<_>>();
   if rows.len() == 0 {
     "There are no tables in this database.".to_string()
@@ -253,17 +289,20 @@ pub fn _vec_to_table_string(column_name: &str, vector: &Vec<String>) -> String {
   }
 }
 
-fn get_widths(header: &Header, rows: &Rows) -> Widths {
+fn get_widths(header: &Header, rows: &Rows, null_marker: &str) -> Widths {
   let column_headers = header
     .into_iter()
-    .map(|(column_header, _type)| column_header.len())
+    .map(|(column_header, _type)| column_header.chars().count())
     .collect::<Vec<_>>();
   rows
     .into_iter()
     .map(|row| {
       row
         .into_iter()
-        .map(|column_entry| column_entry.len())
+        .map(|column_entry| match column_entry {
+          Value::Null => null_marker.chars().count(),
+          _ => column_entry.width(),
+        })
         .collect::<Vec<usize>>()
     })
     .fold(column_headers, |acc, row| {
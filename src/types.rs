@@ -1 +1,6 @@
+pub mod annotation;
+pub mod constraint;
+pub mod memory;
 pub mod postgres;
+pub mod sqlite;
+pub mod value;
@@ -0,0 +1,616 @@
+// Parses the type annotations of csv header fields, e.g. price(numeric(10, 2)) or
+// tags(TEXT[]), into normalized postgres types. Any spelling postgres accepts is accepted,
+// in any case and with any whitespace, along with the internal names postgres reports
+// query result types by (int4, bpchar, _text, ...). Constraints may follow the type, e.g.
+// id(INT PRIMARY KEY), and are parsed in the constraint module.
+
+use super::constraint::{self, Constraint};
+
+// Annotation Error struct
+#[derive(Debug)]
+pub struct Error {
+  pub message: String,
+  pub position: usize, // Character of the annotation the error was found at, starting at 1
+}
+
+impl Error {
+  pub fn new(message: String, position: usize) -> Self {
+    Self { message, position }
+  }
+}
+
+impl std::fmt::Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{} (at character {})", self.message, self.position)
+  }
+}
+
+impl std::error::Error for Error {}
+
+// Splits a header field into its column name and its type annotation, if it has one. The
+// annotation is everything within the parentheses that close the field, so that types
+// with parameters of their own, such as name(VARCHAR(512)), keep them.
+pub fn split_field(field: &str) -> (&str, Option<&str>) {
+  let trimmed = field.trim();
+  if !trimmed.ends_with(')') {
+    return (trimmed, None);
+  }
+  let mut depth = 0;
+  for (index, c) in trimmed.char_indices().rev() {
+    match c {
+      ')' => depth += 1,
+      '(' => {
+        depth -= 1;
+        if depth == 0 {
+          let column_name = trimmed[..index].trim();
+          if column_name.is_empty() {
+            return (trimmed, None);
+          }
+          return (column_name, Some(&trimmed[index + 1..trimmed.len() - 1]));
+        }
+      }
+      _ => (),
+    }
+  }
+  (trimmed, None)
+}
+
+// Parses a column annotation, i.e. a type followed by any constraints, e.g. "INT NOT NULL".
+// The type may be left out if there are constraints, in which case the column gets the
+// type it would have had without an annotation.
+pub fn parse_column(annotation: &str) -> Result<(Option<String>, Vec<Constraint>), Error> {
+  let (column_type, constraints) = match constraint::find_start(annotation) {
+    Some(start) => annotation.split_at(start),
+    None => (annotation, ""),
+  };
+  let offset = column_type.chars().count();
+  let column_type = if column_type.trim().is_empty() && !constraints.is_empty() {
+    None
+  } else {
+    Some(parse_type(column_type)?)
+  };
+  Ok((column_type, constraint::parse(constraints, offset)?))
+}
+
+// Parses a type annotation into its normalized postgres type, i.e. its canonical upper
+// case name followed by any parameters and array dimensions, e.g. "int4 [ ]" is INTEGER[]
+pub fn parse_type(annotation: &str) -> Result<String, Error> {
+  let tokens = tokenize(annotation)?;
+  let mut parser = Parser {
+    tokens,
+    index: 0,
+    end: annotation.chars().count() + 1,
+  };
+  let normalized = parser.parse_type()?;
+  match parser.peek() {
+    None => Ok(normalized),
+    Some((token, position)) => Err(Error::new(
+      format!("Unexpected {} after type {}.", token, normalized),
+      *position,
+    )),
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Word(String), // Lower cased
+  Number(u32),
+  Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Token::Word(word) => write!(f, "'{}'", word),
+      Token::Number(number) => write!(f, "'{}'", number),
+      Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+    }
+  }
+}
+
+fn tokenize(annotation: &str) -> Result<Vec<(Token, usize)>, Error> {
+  let chars = annotation.chars().collect::<Vec<char>>();
+  let mut tokens = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    let position = i + 1;
+    if c.is_whitespace() {
+      i += 1;
+    } else if c.is_ascii_alphabetic() || c == '_' {
+      let start = i;
+      while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+        i += 1;
+      }
+      let word = chars[start..i].iter().collect::<String>().to_lowercase();
+      tokens.push((Token::Word(word), position));
+    } else if c.is_ascii_digit() {
+      let start = i;
+      while i < chars.len() && chars[i].is_ascii_digit() {
+        i += 1;
+      }
+      let digits = chars[start..i].iter().collect::<String>();
+      match digits.parse::<u32>() {
+        Ok(number) => tokens.push((Token::Number(number), position)),
+        Err(_) => {
+          return Err(Error::new(
+            format!("Number {} is too large.", digits),
+            position,
+          ))
+        }
+      }
+    } else if "(),[]".contains(c) {
+      tokens.push((Token::Symbol(c), position));
+      i += 1;
+    } else if c == '"' {
+      // Quoted identifiers are only known as the single byte "char" type
+      let end = chars[i + 1..].iter().position(|&c| c == '"');
+      match end.map(|end| chars[i + 1..i + 1 + end].iter().collect::<String>()) {
+        Some(identifier) if identifier == "char" => {
+          tokens.push((Token::Word("\"char\"".to_string()), position));
+          i += identifier.len() + 2;
+        }
+        _ => {
+          return Err(Error::new(
+            "Unknown quoted type name.".to_string(),
+            position,
+          ))
+        }
+      }
+    } else {
+      return Err(Error::new(
+        format!("Unexpected character '{}'.", c),
+        position,
+      ));
+    }
+  }
+  Ok(tokens)
+}
+
+// Parameters a type may be given, e.g. the length of VARCHAR(n)
+enum Parameters {
+  None,
+  Length,    // (n), n > 0
+  Precision, // (p), 0 <= p <= 6 fractional digits of seconds
+  Numeric,   // (p) or (p, s), 1 <= p <= 1000 and 0 <= s <= p
+  Float,     // (p), 1 <= p <= 53 binary digits
+}
+
+// Canonical names of all types, keyed by every name they can be spelled as
+const TYPES: [(&[&str], &str, Parameters); 41] = [
+  (&["bool", "boolean"], "BOOLEAN", Parameters::None),
+  (&["\"char\""], "\"char\"", Parameters::None),
+  (&["smallint", "int2"], "SMALLINT", Parameters::None),
+  (&["integer", "int", "int4"], "INTEGER", Parameters::None),
+  (&["bigint", "int8"], "BIGINT", Parameters::None),
+  (&["smallserial", "serial2"], "SMALLSERIAL", Parameters::None),
+  (&["serial", "serial4"], "SERIAL", Parameters::None),
+  (&["bigserial", "serial8"], "BIGSERIAL", Parameters::None),
+  (&["real", "float4"], "REAL", Parameters::None),
+  (
+    &["double precision", "float8"],
+    "DOUBLE PRECISION",
+    Parameters::None,
+  ),
+  (&["float"], "DOUBLE PRECISION", Parameters::Float),
+  (&["numeric", "decimal"], "NUMERIC", Parameters::Numeric),
+  (&["money"], "MONEY", Parameters::None),
+  (
+    &["varchar", "character varying"],
+    "VARCHAR",
+    Parameters::Length,
+  ),
+  (&["char", "character", "bpchar"], "CHAR", Parameters::Length),
+  (&["text"], "TEXT", Parameters::None),
+  (&["citext"], "CITEXT", Parameters::None),
+  (&["name"], "NAME", Parameters::None),
+  (&["bytea"], "BYTEA", Parameters::None),
+  (&["date"], "DATE", Parameters::None),
+  (&["time"], "TIME", Parameters::Precision),
+  (&["timetz"], "TIMETZ", Parameters::Precision),
+  (&["timestamp"], "TIMESTAMP", Parameters::Precision),
+  (&["timestamptz"], "TIMESTAMPTZ", Parameters::Precision),
+  (&["interval"], "INTERVAL", Parameters::Precision),
+  (&["uuid"], "UUID", Parameters::None),
+  (&["json"], "JSON", Parameters::None),
+  (&["jsonb"], "JSONB", Parameters::None),
+  (&["xml"], "XML", Parameters::None),
+  (&["inet"], "INET", Parameters::None),
+  (&["cidr"], "CIDR", Parameters::None),
+  (&["macaddr"], "MACADDR", Parameters::None),
+  (&["macaddr8"], "MACADDR8", Parameters::None),
+  (&["bit"], "BIT", Parameters::Length),
+  (&["varbit", "bit varying"], "VARBIT", Parameters::Length),
+  (
+    &["point", "line", "lseg", "box", "path", "polygon", "circle"],
+    "",
+    Parameters::None,
+  ),
+  (&["tsvector", "tsquery"], "", Parameters::None),
+  (&["txid_snapshot"], "TXID_SNAPSHOT", Parameters::None),
+  (&["pg_lsn"], "PG_LSN", Parameters::None),
+  (&["oid"], "OID", Parameters::None),
+  (&["hstore"], "HSTORE", Parameters::None),
+];
+
+// Words that continue the name of a type, e.g. double precision
+const NAME_CONTINUATIONS: [(&str, &str); 3] = [
+  ("double", "precision"),
+  ("character", "varying"),
+  ("bit", "varying"),
+];
+
+const INTERVAL_FIELDS: [&str; 6] = ["year", "month", "day", "hour", "minute", "second"];
+
+// Ranges of interval fields, e.g. DAY TO SECOND
+const INTERVAL_RANGES: [(&str, &str); 7] = [
+  ("year", "month"),
+  ("day", "hour"),
+  ("day", "minute"),
+  ("day", "second"),
+  ("hour", "minute"),
+  ("hour", "second"),
+  ("minute", "second"),
+];
+
+struct Parser {
+  tokens: Vec<(Token, usize)>,
+  index: usize,
+  end: usize, // Position just past the annotation, where running out of tokens is reported
+}
+
+impl Parser {
+  fn peek(&self) -> Option<&(Token, usize)> {
+    self.tokens.get(self.index)
+  }
+
+  fn position(&self) -> usize {
+    self.peek().map_or(self.end, |(_, position)| *position)
+  }
+
+  fn next(&mut self) -> Option<(Token, usize)> {
+    let token = self.tokens.get(self.index).cloned();
+    self.index += 1;
+    token
+  }
+
+  fn next_is(&self, token: &Token) -> bool {
+    self.peek().map(|(next, _)| next) == Some(token)
+  }
+
+  fn next_is_word(&self, word: &str) -> bool {
+    self.next_is(&Token::Word(word.to_string()))
+  }
+
+  fn expect(&mut self, expected: &Token) -> Result<(), Error> {
+    match self.next() {
+      Some((token, _)) if token == *expected => Ok(()),
+      Some((token, position)) => Err(Error::new(
+        format!("Expected {} but got {}.", expected, token),
+        position,
+      )),
+      None => Err(Error::new(
+        format!("Expected {} but the annotation ended.", expected),
+        self.end,
+      )),
+    }
+  }
+
+  fn parse_type(&mut self) -> Result<String, Error> {
+    let position = self.position();
+    let mut name = match self.next() {
+      Some((Token::Word(word), _)) => word,
+      Some((token, position)) => {
+        return Err(Error::new(
+          format!("Expected a type name but got {}.", token),
+          position,
+        ))
+      }
+      None => return Err(Error::new("Missing type name.".to_string(), position)),
+    };
+    // Internal names of array types start with an underscore, e.g. _int4 is int4[]
+    let mut dimensions = 0;
+    if name.len() > 1 && name.starts_with('_') {
+      name.remove(0);
+      dimensions += 1;
+    }
+    for (first, continuation) in NAME_CONTINUATIONS.iter() {
+      if name == *first && self.next_is_word(continuation) {
+        self.next();
+        name = format!("{} {}", first, continuation);
+      }
+    }
+    if name == "double" {
+      self.expect(&Token::Word("precision".to_string()))?;
+    }
+
+    let (canonical, parameters) = match TYPES
+      .iter()
+      .find(|(names, _, _)| names.contains(&name.as_str()))
+    {
+      Some((_, "", parameters)) => (name.to_uppercase(), parameters),
+      Some((_, canonical, parameters)) => (canonical.to_string(), parameters),
+      None => return Err(Error::new(format!("Unknown type {}.", name), position)),
+    };
+    let mut normalized = canonical.clone();
+
+    // Interval fields go before the precision, e.g. INTERVAL DAY TO SECOND(3)
+    let mut allows_precision = true;
+    if canonical == "INTERVAL" {
+      if let Some(fields) = self.parse_interval_fields()? {
+        allows_precision = fields.ends_with("SECOND");
+        normalized = format!("{} {}", normalized, fields);
+      }
+    }
+
+    if self.next_is(&Token::Symbol('(')) {
+      let position = self.position();
+      let arguments = self.parse_arguments()?;
+      if !allows_precision {
+        return Err(Error::new(
+          "Interval precision is only allowed for SECOND fields.".to_string(),
+          position,
+        ));
+      }
+      normalized = match check_parameters(&canonical, parameters, &arguments, position)? {
+        Some(canonical) => canonical,
+        None => format!(
+          "{}({})",
+          normalized,
+          arguments
+            .iter()
+            .map(u32::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+        ),
+      };
+    }
+
+    // Time types may be followed by WITH or WITHOUT TIME ZONE
+    let is_time = canonical == "TIME" || canonical == "TIMESTAMP";
+    if is_time && (self.next_is_word("with") || self.next_is_word("without")) {
+      let (with_time_zone, _) = self.next().unwrap();
+      self.expect(&Token::Word("time".to_string()))?;
+      self.expect(&Token::Word("zone".to_string()))?;
+      if with_time_zone == Token::Word("with".to_string()) {
+        normalized = normalized.replacen(&canonical, &format!("{}TZ", canonical), 1);
+      }
+    }
+
+    // Arrays are written as type[], type[n] or type ARRAY[n], sizes are not enforced
+    if self.next_is_word("array") {
+      self.next();
+      dimensions += 1;
+      if self.next_is(&Token::Symbol('[')) {
+        self.parse_array_brackets()?;
+      }
+    } else {
+      while self.next_is(&Token::Symbol('[')) {
+        self.parse_array_brackets()?;
+        dimensions += 1;
+      }
+    }
+    Ok(format!("{}{}", normalized, "[]".repeat(dimensions)))
+  }
+
+  // Parses (n) or (n, m)
+  fn parse_arguments(&mut self) -> Result<Vec<u32>, Error> {
+    self.expect(&Token::Symbol('('))?;
+    let mut arguments = Vec::new();
+    loop {
+      match self.next() {
+        Some((Token::Number(number), _)) => arguments.push(number),
+        Some((token, position)) => {
+          return Err(Error::new(
+            format!("Expected a number but got {}.", token),
+            position,
+          ))
+        }
+        None => {
+          return Err(Error::new(
+            "Expected a number but the annotation ended.".to_string(),
+            self.end,
+          ))
+        }
+      }
+      if self.next_is(&Token::Symbol(',')) {
+        self.next();
+      } else {
+        break;
+      }
+    }
+    self.expect(&Token::Symbol(')'))?;
+    Ok(arguments)
+  }
+
+  fn parse_array_brackets(&mut self) -> Result<(), Error> {
+    self.expect(&Token::Symbol('['))?;
+    if let Some((Token::Number(_), _)) = self.peek() {
+      self.next();
+    }
+    self.expect(&Token::Symbol(']'))
+  }
+
+  // Parses the fields of an interval, e.g. YEAR or DAY TO SECOND
+  fn parse_interval_fields(&mut self) -> Result<Option<String>, Error> {
+    let is_field = |token: Option<&(Token, usize)>| match token {
+      Some((Token::Word(word), _)) => INTERVAL_FIELDS.contains(&word.as_str()),
+      _ => false,
+    };
+    if !is_field(self.peek()) {
+      return Ok(None);
+    }
+    let (first, _) = self.next().unwrap();
+    let mut fields = word_of(&first).to_uppercase();
+    if self.next_is_word("to") {
+      self.next();
+      let position = self.position();
+      if !is_field(self.peek()) {
+        return Err(Error::new(
+          "Expected an interval field after TO.".to_string(),
+          position,
+        ));
+      }
+      let (last, _) = self.next().unwrap();
+      if !INTERVAL_RANGES.contains(&(word_of(&first), word_of(&last))) {
+        return Err(Error::new(
+          format!(
+            "Invalid interval fields {} TO {}.",
+            fields,
+            word_of(&last).to_uppercase()
+          ),
+          position,
+        ));
+      }
+      fields = format!("{} TO {}", fields, word_of(&last).to_uppercase());
+    }
+    Ok(Some(fields))
+  }
+}
+
+fn word_of(token: &Token) -> &str {
+  match token {
+    Token::Word(word) => word,
+    _ => "",
+  }
+}
+
+// Checks the arguments given to a type. FLOAT(p) is normalized to the type it stands for,
+// which is returned, while other types keep their arguments.
+fn check_parameters(
+  canonical: &str,
+  parameters: &Parameters,
+  arguments: &[u32],
+  position: usize,
+) -> Result<Option<String>, Error> {
+  let error = |message: String| Err(Error::new(message, position));
+  match (parameters, arguments) {
+    (Parameters::None, _) => error(format!("Type {} does not take parameters.", canonical)),
+    (Parameters::Length, [length]) if *length >= 1 && *length <= 10_485_760 => Ok(None),
+    (Parameters::Length, [length]) => error(format!(
+      "Length of {} must be between 1 and 10485760 but got {}.",
+      canonical, length
+    )),
+    (Parameters::Precision, [precision]) if *precision <= 6 => Ok(None),
+    (Parameters::Precision, [precision]) => error(format!(
+      "Precision of {} must be between 0 and 6 but got {}.",
+      canonical, precision
+    )),
+    (Parameters::Numeric, [precision, ..]) if *precision < 1 || *precision > 1000 => {
+      error(format!(
+        "Precision of NUMERIC must be between 1 and 1000 but got {}.",
+        precision
+      ))
+    }
+    (Parameters::Numeric, [_]) => Ok(None),
+    (Parameters::Numeric, [precision, scale]) if scale <= precision => Ok(None),
+    (Parameters::Numeric, [precision, scale]) => error(format!(
+      "Scale of NUMERIC must be between 0 and its precision {} but got {}.",
+      precision, scale
+    )),
+    (Parameters::Float, [precision]) if *precision >= 1 && *precision <= 24 => {
+      Ok(Some("REAL".to_string()))
+    }
+    (Parameters::Float, [precision]) if *precision >= 25 && *precision <= 53 => {
+      Ok(Some("DOUBLE PRECISION".to_string()))
+    }
+    (Parameters::Float, [precision]) => error(format!(
+      "Precision of FLOAT must be between 1 and 53 but got {}.",
+      precision
+    )),
+    _ => error(format!(
+      "Type {} takes {} parameter(s) but got {}.",
+      canonical,
+      match parameters {
+        Parameters::Numeric => "1 or 2",
+        _ => "1",
+      },
+      arguments.len()
+    )),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn error(annotation: &str) -> String {
+    parse_type(annotation).unwrap_err().message
+  }
+
+  #[test]
+  fn splits_fields() {
+    assert_eq!(
+      split_field(" price(numeric(10, 2)) "),
+      ("price", Some("numeric(10, 2)"))
+    );
+    assert_eq!(split_field("name"), ("name", None));
+    assert_eq!(split_field("(x)"), ("(x)", None));
+  }
+
+  #[test]
+  fn normalizes_types() {
+    assert_eq!(parse_type("int4 [ ]").unwrap(), "INTEGER[]");
+    assert_eq!(parse_type("_text").unwrap(), "TEXT[]");
+    assert_eq!(
+      parse_type("Double   Precision").unwrap(),
+      "DOUBLE PRECISION"
+    );
+    assert_eq!(parse_type("character varying(12)").unwrap(), "VARCHAR(12)");
+    assert_eq!(parse_type("numeric( 10 , 2 )").unwrap(), "NUMERIC(10,2)");
+    assert_eq!(
+      parse_type("timestamp with time zone").unwrap(),
+      "TIMESTAMPTZ"
+    );
+    assert_eq!(
+      parse_type("timestamp(3) without time zone").unwrap(),
+      "TIMESTAMP(3)"
+    );
+    assert_eq!(
+      parse_type("interval day to second(3)").unwrap(),
+      "INTERVAL DAY TO SECOND(3)"
+    );
+  }
+
+  #[test]
+  fn normalizes_float_precision() {
+    assert_eq!(parse_type("float").unwrap(), "DOUBLE PRECISION");
+    assert_eq!(parse_type("float(1)").unwrap(), "REAL");
+    assert_eq!(parse_type("float(24)").unwrap(), "REAL");
+    assert_eq!(parse_type("float(25)").unwrap(), "DOUBLE PRECISION");
+    assert_eq!(parse_type("float(53)").unwrap(), "DOUBLE PRECISION");
+    assert_eq!(
+      error("float(0)"),
+      "Precision of FLOAT must be between 1 and 53 but got 0."
+    );
+    assert_eq!(
+      error("float(54)"),
+      "Precision of FLOAT must be between 1 and 53 but got 54."
+    );
+  }
+
+  #[test]
+  fn rejects_invalid_types() {
+    assert_eq!(error("inte"), "Unknown type inte.");
+    assert_eq!(error("int(3)"), "Type INTEGER does not take parameters.");
+    assert_eq!(
+      error("varchar(0)"),
+      "Length of VARCHAR must be between 1 and 10485760 but got 0."
+    );
+    assert_eq!(
+      error("numeric(2,3)"),
+      "Scale of NUMERIC must be between 0 and its precision 2 but got 3."
+    );
+    assert_eq!(parse_type("int nul").unwrap_err().position, 5);
+  }
+
+  #[test]
+  fn parses_columns() {
+    let (column_type, constraints) = parse_column("INT NOT NULL").unwrap();
+    assert_eq!(column_type.as_deref(), Some("INTEGER"));
+    assert_eq!(constraints, vec![Constraint::NotNull]);
+    let (column_type, constraints) = parse_column("PRIMARY KEY").unwrap();
+    assert_eq!(column_type, None);
+    assert_eq!(constraints, vec![Constraint::PrimaryKey]);
+  }
+}
@@ -0,0 +1,328 @@
+// Parses the integrity constraints that may follow the type of a column annotation, e.g.
+// id(INT PRIMARY KEY), email(TEXT NOT NULL UNIQUE) or age(INT CHECK >= 0). Constraints are
+// emitted as part of the column definition when the table is created.
+use super::annotation::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constraint {
+  PrimaryKey,
+  NotNull,
+  Null,
+  Unique,
+  Check(Check),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Check {
+  Comparison(String, String), // Operator and literal the column is compared with, e.g. >= 0
+  Expression(String),         // Expression as written within CHECK (...)
+}
+
+impl Constraint {
+  // The constraint as written in the definition of the given column
+  pub fn to_sql(&self, column_name: &str) -> String {
+    match self {
+      Constraint::PrimaryKey => "PRIMARY KEY".to_string(),
+      Constraint::NotNull => "NOT NULL".to_string(),
+      Constraint::Null => "NULL".to_string(),
+      Constraint::Unique => "UNIQUE".to_string(),
+      Constraint::Check(Check::Comparison(operator, literal)) => {
+        format!("CHECK ({} {} {})", column_name, operator, literal)
+      }
+      Constraint::Check(Check::Expression(expression)) => format!("CHECK ({})", expression),
+    }
+  }
+
+  // Whether a value of the column, None being NULL, satisfies the constraint. Only NULLs
+  // and comparisons of numbers are checked, anything else is left to the database.
+  pub fn allows(&self, value: Option<&str>) -> bool {
+    match (self, value) {
+      (Constraint::PrimaryKey, None) | (Constraint::NotNull, None) => false,
+      (Constraint::Check(Check::Comparison(operator, literal)), Some(value)) => {
+        match (value.trim().parse::<f64>(), literal.parse::<f64>()) {
+          (Ok(value), Ok(literal)) if !value.is_nan() && !literal.is_nan() => {
+            match operator.as_str() {
+              "=" => value == literal,
+              "<>" => value != literal,
+              "<" => value < literal,
+              "<=" => value <= literal,
+              ">" => value > literal,
+              _ => value >= literal,
+            }
+          }
+          _ => true,
+        }
+      }
+      _ => true,
+    }
+  }
+}
+
+// Words a constraint may start with. None of them is part of a type name.
+const KEYWORDS: [&str; 5] = ["primary", "not", "null", "unique", "check"];
+
+// Operators of the CHECK shorthand, longest first so that >= is not read as >
+const OPERATORS: [&str; 7] = [">=", "<=", "<>", "!=", "=", "<", ">"];
+
+// Byte index of the annotation the constraints start at, i.e. its first keyword outside of
+// any parentheses, if it has any
+pub fn find_start(annotation: &str) -> Option<usize> {
+  let mut depth = 0;
+  let mut word_start = None;
+  for (index, c) in annotation
+    .char_indices()
+    .chain(Some((annotation.len(), ' ')))
+  {
+    if c.is_ascii_alphanumeric() || c == '_' {
+      if word_start.is_none() {
+        word_start = Some(index);
+      }
+      continue;
+    }
+    if let Some(start) = word_start.take() {
+      let word = annotation[start..index].to_lowercase();
+      if depth == 0 && KEYWORDS.contains(&word.as_str()) {
+        return Some(start);
+      }
+    }
+    match c {
+      '(' => depth += 1,
+      ')' => depth -= 1,
+      _ => (),
+    }
+  }
+  None
+}
+
+// Parses the constraints of an annotation. Offset is the number of characters of the
+// annotation before them, so that errors point at the right character.
+pub fn parse(constraints: &str, offset: usize) -> Result<Vec<Constraint>, Error> {
+  let mut scanner = Scanner {
+    chars: constraints.chars().collect(),
+    index: 0,
+    offset,
+  };
+  let mut parsed: Vec<Constraint> = Vec::new();
+  loop {
+    scanner.skip_whitespace();
+    let position = scanner.position();
+    let word = match scanner.peek() {
+      None => break,
+      Some(_) => scanner.word(),
+    };
+    let constraint = match word.as_str() {
+      "primary" => {
+        scanner.expect_word("key")?;
+        Constraint::PrimaryKey
+      }
+      "not" => {
+        scanner.expect_word("null")?;
+        Constraint::NotNull
+      }
+      "null" => Constraint::Null,
+      "unique" => Constraint::Unique,
+      "check" => Constraint::Check(scanner.check()?),
+      "" => {
+        return Err(Error::new(
+          format!("Unexpected character '{}'.", scanner.peek().unwrap()),
+          position,
+        ))
+      }
+      _ => {
+        return Err(Error::new(
+          format!(
+            "Unknown constraint {}. Expected PRIMARY KEY, NOT NULL, NULL, UNIQUE or CHECK.",
+            word.to_uppercase()
+          ),
+          position,
+        ))
+      }
+    };
+    let conflicting = match constraint {
+      Constraint::Check(_) => None,
+      Constraint::NotNull => parsed
+        .iter()
+        .find(|other| **other == Constraint::NotNull || **other == Constraint::Null),
+      Constraint::Null => parsed
+        .iter()
+        .find(|other| **other == Constraint::NotNull || **other == Constraint::Null),
+      _ => parsed.iter().find(|other| **other == constraint),
+    };
+    if let Some(other) = conflicting {
+      let message = if *other == constraint {
+        format!("Duplicate constraint {}.", constraint.to_sql(""))
+      } else {
+        "A column cannot be both NULL and NOT NULL.".to_string()
+      };
+      return Err(Error::new(message, position));
+    }
+    parsed.push(constraint);
+  }
+  Ok(parsed)
+}
+
+struct Scanner {
+  chars: Vec<char>,
+  index: usize,
+  offset: usize,
+}
+
+impl Scanner {
+  fn peek(&self) -> Option<char> {
+    self.chars.get(self.index).cloned()
+  }
+
+  fn position(&self) -> usize {
+    self.offset + self.index + 1
+  }
+
+  fn skip_whitespace(&mut self) {
+    while self.peek().is_some_and(char::is_whitespace) {
+      self.index += 1;
+    }
+  }
+
+  // Reads a lower cased word, which is empty if the next character does not start one
+  fn word(&mut self) -> String {
+    let start = self.index;
+    while self
+      .peek()
+      .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+      self.index += 1;
+    }
+    self.chars[start..self.index]
+      .iter()
+      .collect::<String>()
+      .to_lowercase()
+  }
+
+  fn expect_word(&mut self, expected: &str) -> Result<(), Error> {
+    self.skip_whitespace();
+    let position = self.position();
+    match self.word() {
+      word if word == expected => Ok(()),
+      word if word.is_empty() => Err(Error::new(
+        format!("Expected {}.", expected.to_uppercase()),
+        position,
+      )),
+      word => Err(Error::new(
+        format!(
+          "Expected {} but got {}.",
+          expected.to_uppercase(),
+          word.to_uppercase()
+        ),
+        position,
+      )),
+    }
+  }
+
+  // Reads what follows CHECK, either a parenthesized expression or an operator and a
+  // literal the column is compared with
+  fn check(&mut self) -> Result<Check, Error> {
+    self.skip_whitespace();
+    let position = self.position();
+    if self.peek() == Some('(') {
+      return self.expression().map(Check::Expression);
+    }
+    let rest = self.chars[self.index..].iter().collect::<String>();
+    let operator = match OPERATORS
+      .iter()
+      .find(|operator| rest.starts_with(*operator))
+    {
+      Some(operator) => *operator,
+      None => {
+        return Err(Error::new(
+          "Expected a comparison operator or a parenthesized expression after CHECK.".to_string(),
+          position,
+        ))
+      }
+    };
+    self.index += operator.len();
+    let operator = if operator == "!=" { "<>" } else { operator };
+    self.skip_whitespace();
+    let literal = self.literal(operator)?;
+    Ok(Check::Comparison(operator.to_string(), literal))
+  }
+
+  // Reads a parenthesized expression, returning what is within the parentheses
+  fn expression(&mut self) -> Result<String, Error> {
+    let position = self.position();
+    let start = self.index + 1;
+    let mut depth = 0;
+    let mut quoted = false;
+    while let Some(c) = self.peek() {
+      self.index += 1;
+      match c {
+        '\'' => quoted = !quoted,
+        '(' if !quoted => depth += 1,
+        ')' if !quoted => {
+          depth -= 1;
+          if depth == 0 {
+            let expression = self.chars[start..self.index - 1].iter().collect::<String>();
+            if expression.trim().is_empty() {
+              return Err(Error::new("Empty CHECK expression.".to_string(), position));
+            }
+            return Ok(expression.trim().to_string());
+          }
+        }
+        _ => (),
+      }
+    }
+    Err(Error::new(
+      "Unclosed parenthesis of CHECK expression.".to_string(),
+      position,
+    ))
+  }
+
+  // Reads a number, a single quoted string or a boolean
+  fn literal(&mut self, operator: &str) -> Result<String, Error> {
+    let position = self.position();
+    let error = || {
+      Error::new(
+        format!(
+          "Expected a number, string or boolean after CHECK {}.",
+          operator
+        ),
+        position,
+      )
+    };
+    let start = self.index;
+    match self.peek() {
+      Some('\'') => {
+        self.index += 1;
+        loop {
+          match self.peek() {
+            // A doubled quote is an escaped quote within the string
+            Some('\'') if self.chars.get(self.index + 1) == Some(&'\'') => self.index += 2,
+            Some('\'') => {
+              self.index += 1;
+              break;
+            }
+            Some(_) => self.index += 1,
+            None => return Err(Error::new("Unclosed string literal.".to_string(), position)),
+          }
+        }
+        Ok(self.chars[start..self.index].iter().collect())
+      }
+      Some(c) if c.is_ascii_digit() || "+-.".contains(c) => {
+        while self
+          .peek()
+          .is_some_and(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        {
+          self.index += 1;
+        }
+        let number = self.chars[start..self.index].iter().collect::<String>();
+        match number.parse::<f64>() {
+          Ok(_) => Ok(number),
+          Err(_) => Err(Error::new(format!("Invalid number {}.", number), position)),
+        }
+      }
+      Some(_) => match self.word().as_str() {
+        word @ "true" | word @ "false" => Ok(word.to_uppercase()),
+        _ => Err(error()),
+      },
+      None => Err(error()),
+    }
+  }
+}
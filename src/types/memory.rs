@@ -0,0 +1,61 @@
+use super::annotation;
+use super::value::Value;
+
+// Converts a csv value into the value held in a column of the given type. Values are
+// checked against their type before, so that anything else is kept as text.
+pub fn to_value(column_type: &str, value: &str) -> Value {
+  let column_type = annotation::parse_type(column_type).unwrap_or_else(|_| column_type.to_string());
+  let text = || Value::Text(value.to_string());
+  if column_type.ends_with(']') {
+    return text();
+  }
+  match column_type.split('(').next().unwrap_or("") {
+    "SMALLINT" | "INTEGER" | "BIGINT" | "SMALLSERIAL" | "SERIAL" | "BIGSERIAL" | "OID" => value
+      .trim()
+      .parse::<i64>()
+      .map_or_else(|_| text(), Value::Int),
+    "REAL" | "DOUBLE PRECISION" => match value.trim().to_lowercase().as_str() {
+      "infinity" | "+infinity" | "inf" => Value::Float(f64::INFINITY),
+      "-infinity" | "-inf" => Value::Float(f64::NEG_INFINITY),
+      other => other.parse::<f64>().map_or_else(|_| text(), Value::Float),
+    },
+    "NUMERIC" => Value::Numeric(value.trim().to_string()),
+    "BOOLEAN" => match value.trim().to_lowercase().as_str() {
+      "true" | "t" | "yes" | "y" | "on" | "1" => Value::Bool(true),
+      "false" | "f" | "no" | "n" | "off" | "0" => Value::Bool(false),
+      _ => text(),
+    },
+    "DATE" => Value::Date(value.trim().to_string()),
+    "TIMESTAMP" | "TIMESTAMPTZ" => Value::Timestamp(value.trim().to_string()),
+    "JSON" | "JSONB" => serde_json::from_str(value).map_or_else(|_| text(), Value::Json),
+    // Bytes are written in the hex format postgres outputs bytea in, e.g. \x0aff
+    "BYTEA" => match value.strip_prefix("\\x") {
+      Some(hex) if hex.len() % 2 == 0 => (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_or_else(|_| text(), Value::Bytes),
+      _ => text(),
+    },
+    _ => text(),
+  }
+}
+
+// Type of a column of computed values, named after the postgres type of its first value
+pub fn type_of(value: &Value) -> String {
+  match value {
+    Value::Bool(_) => "BOOLEAN".to_string(),
+    Value::Int(_) => "BIGINT".to_string(),
+    Value::Float(_) => "DOUBLE PRECISION".to_string(),
+    Value::Numeric(_) => "NUMERIC".to_string(),
+    Value::Bytes(_) => "BYTEA".to_string(),
+    Value::Date(_) => "DATE".to_string(),
+    Value::Timestamp(_) => "TIMESTAMP".to_string(),
+    Value::Json(_) => "JSON".to_string(),
+    Value::Array(values) => match values.iter().find(|value| !value.is_null()) {
+      Some(value) => format!("{}[]", type_of(value)),
+      None => "TEXT[]".to_string(),
+    },
+    Value::Null | Value::Text(_) => "TEXT".to_string(),
+  }
+}
@@ -1,4 +1,10 @@
+use super::annotation;
+use super::value::Value;
+use byteorder::{BigEndian, ReadBytesExt};
+use fallible_iterator::FallibleIterator;
+use postgres_protocol::types as binary;
 use regex::Regex as Reg;
+use tokio_postgres::types::{FromSql, Kind, Type};
 
 // Taken from tokio_postgres FromSql lib.rs
 
@@ -18,35 +24,6 @@ use regex::Regex as Reg;
 // | `SystemTime`                      | TIMESTAMP, TIMESTAMP WITH TIME ZONE  |
 // | `IpAddr`                          | INET                                 |
 
-// TODO: make all regexes space invariant/assuming Postgres SQL types allow for them to be
-// TODO: allow for lowercased type annotations
-lazy_static! {
-  static ref BOOL_RE: Reg = Reg::new(r"^(BOOL|BOOLEAN)$").unwrap();
-  static ref CHAR_RE: Reg = Reg::new(r"^char$").unwrap();
-  static ref SMALL_INT_RE: Reg = Reg::new(r"^(SMALLINT|INT2)$").unwrap();
-  static ref SMALL_SERIAL_RE: Reg = Reg::new(r"^SMALLSERIAL$").unwrap();
-  static ref INT_RE: Reg = Reg::new(r"^(INT|INT4)$").unwrap();
-  static ref SERIAL_RE: Reg = Reg::new(r"^SERIAL$").unwrap();
-  static ref OID_RE: Reg = Reg::new(r"^OID$").unwrap();
-  static ref BIGINT_RE: Reg = Reg::new(r"^BIGINT$").unwrap();
-  static ref BIGSERIAL_RE: Reg = Reg::new(r"^BIGSERIAL$").unwrap();
-  static ref REAL_RE: Reg = Reg::new(r"^(REAL|FLOAT4)$").unwrap();
-  static ref DOUBLE_PRECISION_RE: Reg = Reg::new(r"^DOUBLE PRECISION$").unwrap();
-  static ref NUMERIC_RE: Reg = Reg::new(r"^NUMERIC\s*\(\s*\d+\s*,\s*\d+\s*\)$").unwrap();
-  static ref VARCHAR_N_RE: Reg = Reg::new(r"^VARCHAR(\(\d+\))?$").unwrap();
-  static ref CHAR_N_RE: Reg = Reg::new(r"^CHAR(\(\d+\))?$").unwrap();
-  static ref TEXT_RE: Reg = Reg::new(r"^TEXT$").unwrap();
-  static ref CITEXT_RE: Reg = Reg::new(r"^CITEXT$").unwrap();
-  static ref NAME_RE: Reg = Reg::new(r"^NAME$").unwrap();
-  static ref BYTEA_RE: Reg = Reg::new(r"^BYTEA$").unwrap();
-  static ref HSTORE_RE: Reg = Reg::new(r"^HSTORE$").unwrap();
-  static ref TIMESTAMP_RE: Reg = Reg::new(r"^TIMESTAMP$").unwrap();
-  static ref TIMESTAMP_NO_TIME_ZONE_RE: Reg = Reg::new(r"^TIMESTAMP WITHOUT TIME ZONE").unwrap();
-  static ref TIMESTAMP_WITH_TIME_ZONE_RE: Reg =
-    Reg::new(r"^TIMESTAMP WITH TIME ZONE [a-zA-Z]+").unwrap();
-  static ref INET_RE: Reg = Reg::new(r"^INET$").unwrap();
-}
-
 // In addition, some implementations are provided for types in third party
 // crates. These are disabled by default; to opt into one of these
 // implementations, activate the Cargo feature corresponding to the crate's
@@ -74,200 +51,734 @@ lazy_static! {
 // | `bit_vec::BitVec`               | BIT, VARBIT                         |
 // | `eui48::MacAddress`             | MACADDR                             |
 
-lazy_static! {
-  static ref DATE_RE: Reg = Reg::new(r"^(DATA|data)$").unwrap();
-  static ref TIME_RE: Reg = Reg::new(r"^(TIME|time)$").unwrap();
-  static ref TIME_NO_TIMEZONE_RE: Reg = Reg::new(r"^TIME WITHOUT TIME ZONE$").unwrap();
-  static ref INTERVAL_RE: Reg = Reg::new(r"^INTERVAL$").unwrap();
-  static ref MACADDR_RE: Reg = Reg::new(r"MACADDR^$").unwrap();
-  static ref CIDR_RE: Reg = Reg::new(r"^CIDR$").unwrap();
-  static ref PATH_RE: Reg = Reg::new(r"^PATH$").unwrap();
-  static ref JSON_RE: Reg = Reg::new(r"^JSON$").unwrap();
-  static ref JSONB_RE: Reg = Reg::new(r"^JSONB$").unwrap();
-  static ref UUID_RE: Reg = Reg::new(r"^UUID$").unwrap();
-  static ref BIT_RE: Reg = Reg::new(r"^BIT(\(\d+\))?$").unwrap();
-  static ref VARBIT_RE: Reg = Reg::new(r"^(VARBIT|BIT VARYING)(\(\d+\))?$").unwrap();
+// Validators
+// Any type a column can be annotated with, in any spelling postgres accepts
+pub fn is_valid_type(type_str: &str) -> bool {
+  annotation::parse_type(type_str).is_ok()
 }
 
-// Geometry Types
 lazy_static! {
-  static ref POINT_RE: Reg = Reg::new(r"POINT^$").unwrap();
-  static ref BOX_RE: Reg = Reg::new(r"^BOX$").unwrap();
-  static ref CIRCLE_RE: Reg = Reg::new(r"^CIRCLE$").unwrap();
-  static ref LINE_RE: Reg = Reg::new(r"^LINE$").unwrap();
-  static ref POLYGON_RE: Reg = Reg::new(r"^POLYGON$").unwrap();
-  static ref LSEG_RE: Reg = Reg::new(r"^LSEG$").unwrap();
+  static ref ISO_DATE_PREFIX_RE: Reg = Reg::new(r"^(\d{4}-\d{1,2}-\d{1,2})(?:[ T]|$)").unwrap();
 }
 
-// Miscellaneous Types
-lazy_static! {
-  static ref TSQUERY_RE: Reg = Reg::new(r"^TSQUERY$").unwrap();
-  static ref TSVECTOR_RE: Reg = Reg::new(r"^TSVECTOR$").unwrap();
-  static ref TXID_SNAPSHOT_RE: Reg = Reg::new(r"^TXID SNAPSHOT$").unwrap();
-  static ref XML_RE: Reg = Reg::new(r"^XML$").unwrap();
-  static ref MONEY_RE: Reg = Reg::new(r"^MONEY$").unwrap();
-  static ref PG_LSN_RE: Reg = Reg::new(r"^PG_LSN$").unwrap();
+// Checks whether a csv value can be read as the given column type, giving the reason it
+// cannot otherwise. Values the checks are not sure about, such as dates in formats other
+// than ISO 8601 or types without a check, are left for the database to read.
+pub fn check_value(column_type: &str, value: &str) -> Result<(), String> {
+  let column_type = annotation::parse_type(column_type).unwrap_or_else(|_| column_type.to_string());
+  if column_type.ends_with(']') {
+    return Ok(());
+  }
+  let (name, parameters) = match column_type.find('(') {
+    Some(start) => (
+      &column_type[..start],
+      column_type[start + 1..column_type.len() - 1]
+        .split(',')
+        .filter_map(|parameter| parameter.trim().parse::<usize>().ok())
+        .collect::<Vec<_>>(),
+    ),
+    None => (column_type.as_str(), Vec::new()),
+  };
+  let trimmed = value.trim();
+  let invalid = || {
+    Err(format!(
+      "invalid input syntax for type {}: \"{}\"",
+      column_type, value
+    ))
+  };
+  let out_of_range = || {
+    Err(format!(
+      "value \"{}\" is out of range for type {}",
+      value, column_type
+    ))
+  };
+  let integer = |fits: bool| {
+    if !INTEGER_INPUT_RE.is_match(trimmed) {
+      invalid()
+    } else if !fits {
+      out_of_range()
+    } else {
+      Ok(())
+    }
+  };
+  match name {
+    "SMALLINT" | "SMALLSERIAL" => integer(trimmed.parse::<i16>().is_ok()),
+    "INTEGER" | "SERIAL" => integer(trimmed.parse::<i32>().is_ok()),
+    "BIGINT" | "BIGSERIAL" => integer(trimmed.parse::<i64>().is_ok()),
+    "OID" => integer(trimmed.parse::<u32>().is_ok()),
+    "REAL" | "DOUBLE PRECISION" => match trimmed.parse::<f64>() {
+      Err(_) => invalid(),
+      Ok(number) if name == "REAL" && number.is_finite() && number.abs() > f32::MAX as f64 => {
+        out_of_range()
+      }
+      Ok(_) => Ok(()),
+    },
+    "NUMERIC" => {
+      if FLOAT_VALUE_RE.is_match(trimmed) {
+        return Ok(());
+      }
+      if !DECIMAL_VALUE_RE.is_match(trimmed) && !INTEGER_INPUT_RE.is_match(trimmed) {
+        return invalid();
+      }
+      // Fractional digits are rounded to the scale, but integer digits must fit
+      let (precision, scale) = match parameters.as_slice() {
+        [precision] => (*precision, 0),
+        [precision, scale] => (*precision, *scale),
+        _ => return Ok(()),
+      };
+      let mut parts = trimmed.trim_start_matches(['+', '-']).splitn(2, '.');
+      let integer = parts.next().unwrap_or("").trim_start_matches('0');
+      let fraction = parts.next().unwrap_or("");
+      let kept = format!(
+        "{}{}",
+        integer,
+        fraction.chars().take(scale).collect::<String>()
+      );
+      // Rounding up a value of only nines, e.g. 99.999 to 100.00, adds an integer digit
+      let rounds_up = fraction
+        .chars()
+        .nth(scale)
+        .is_some_and(|digit| digit >= '5');
+      let integer_digits = if rounds_up && kept.chars().all(|digit| digit == '9') {
+        integer.len() + 1
+      } else {
+        integer.len()
+      };
+      if integer_digits > precision - scale {
+        out_of_range()
+      } else {
+        Ok(())
+      }
+    }
+    "BOOLEAN" => {
+      let lower = trimmed.to_lowercase();
+      let is_prefix = !lower.is_empty()
+        && ["true", "false", "yes"]
+          .iter()
+          .any(|word| word.starts_with(lower.as_str()));
+      if is_bool_value(&lower) || lower == "1" || lower == "0" || is_prefix {
+        Ok(())
+      } else {
+        invalid()
+      }
+    }
+    "DATE" | "TIMESTAMP" | "TIMESTAMPTZ" => match ISO_DATE_PREFIX_RE.captures(trimmed) {
+      Some(captures) => {
+        let date = captures[1]
+          .split('-')
+          .map(|part| format!("{:0>2}", part))
+          .collect::<Vec<_>>()
+          .join("-");
+        if is_date_value(&date) {
+          Ok(())
+        } else {
+          Err(format!("date/time field value out of range: \"{}\"", value))
+        }
+      }
+      None => Ok(()),
+    },
+    "UUID" => {
+      let digits = trimmed
+        .trim_start_matches('{')
+        .trim_end_matches('}')
+        .replace('-', "");
+      if digits.len() == 32 && digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+      } else {
+        invalid()
+      }
+    }
+    "JSON" | "JSONB" => match serde_json::from_str::<serde_json::Value>(value) {
+      Ok(_) => Ok(()),
+      Err(_) => invalid(),
+    },
+    // Longer values are only cut to length if the excess characters are spaces
+    "VARCHAR" | "CHAR" => match parameters.as_slice() {
+      [length] if value.trim_end_matches(' ').chars().count() > *length => {
+        Err(format!("value too long for type {}", column_type))
+      }
+      _ => Ok(()),
+    },
+    "BIT" | "VARBIT" if !value.chars().all(|c| c == '0' || c == '1') => {
+      Err(format!("\"{}\" is not a valid binary digit string", value))
+    }
+    "BIT" => match parameters.as_slice() {
+      [length] if value.len() != *length => Err(format!(
+        "bit string length {} does not match type {}",
+        value.len(),
+        column_type
+      )),
+      _ => Ok(()),
+    },
+    "VARBIT" => match parameters.as_slice() {
+      [length] if value.len() > *length => {
+        Err(format!("bit string too long for type {}", column_type))
+      }
+      _ => Ok(()),
+    },
+    _ => Ok(()),
+  }
 }
 
-// Postgres SQL Type Vector
+// Inference
 lazy_static! {
-  pub static ref TYPES: Vec<&'static Reg> = vec![
-    &BOOL_RE,
-    &CHAR_RE,
-    &SMALL_INT_RE,
-    &SMALL_SERIAL_RE,
-    &INT_RE,
-    &SERIAL_RE,
-    &OID_RE,
-    &BIGINT_RE,
-    &BIGSERIAL_RE,
-    &REAL_RE,
-    &DOUBLE_PRECISION_RE,
-    // &NUMERIC_RE,
-    &VARCHAR_N_RE,
-    &CHAR_N_RE,
-    &TEXT_RE,
-    // &CITEXT_RE,
-    &NAME_RE,
-    &BYTEA_RE,
-    // &HSTORE_RE,
-    &TIMESTAMP_RE,
-    &TIMESTAMP_NO_TIME_ZONE_RE,
-    &TIMESTAMP_WITH_TIME_ZONE_RE,
-    &INET_RE,
-    &DATE_RE,
-    &TIME_RE,
-    &TIME_NO_TIMEZONE_RE,
-    // &INTERVAL_RE,
-    // &MACADDR_RE,
-    // &CIDR_RE,
-    // &PATH_RE,
-    // &JSON_RE,
-    // &JSONB_RE,
-    // &UUID_RE,
-    // &BIT_RE,
-    // &VARBIT_RE,
-    // &POINT_RE,
-    // &BOX_RE,
-    // &CIRCLE_RE,
-    // &LINE_RE,
-    // &POLYGON_RE,
-    // &LSEG_RE,
-    // &TSQUERY_RE,
-    // &TSVECTOR_RE,
-    // &TXID_SNAPSHOT_RE,
-    // &XML_RE,
-    // &MONEY_RE,
-    // &PG_LSN_RE
-  ];
+  // Integers without leading zeros, so that codes such as 007 stay TEXT
+  static ref INTEGER_VALUE_RE: Reg = Reg::new(r"^[+-]?(0|[1-9]\d*)$").unwrap();
+  // Integers as postgres reads them, leading zeros included
+  static ref INTEGER_INPUT_RE: Reg = Reg::new(r"^[+-]?\d+$").unwrap();
+  static ref DECIMAL_VALUE_RE: Reg = Reg::new(r"^[+-]?((0|[1-9]\d*)(\.\d*)?|\.\d+)$").unwrap();
+  static ref FLOAT_VALUE_RE: Reg =
+    Reg::new(r"^[+-]?(((0|[1-9]\d*)(\.\d*)?|\.\d+)[eE][+-]?\d+|(?i:nan|infinity))$").unwrap();
+  static ref DATE_VALUE_RE: Reg = Reg::new(r"^(\d{4})-(\d{2})-(\d{2})$").unwrap();
+  static ref TIMESTAMP_VALUE_RE: Reg =
+    Reg::new(r"^(\d{4}-\d{2}-\d{2})[ T]([01]\d|2[0-3]):[0-5]\d(:[0-5]\d(\.\d{1,6})?)?$").unwrap();
+  static ref UUID_VALUE_RE: Reg =
+    Reg::new(r"^[[:xdigit:]]{8}-[[:xdigit:]]{4}-[[:xdigit:]]{4}-[[:xdigit:]]{4}-[[:xdigit:]]{12}$")
+      .unwrap();
 }
 
-// Validators
-pub fn is_valid_type(type_str: &str) -> bool {
-  // println!("Validating this supposed type: {}", type_str);
-  if type_str.is_empty() {
-    false
-  } else {
-    for sql_type_re in TYPES.iter() {
-      if (*sql_type_re).is_match(type_str) {
-        return true;
-      }
-    }
-    false
+// Whether a value can be read as a type
+type Fits = fn(&str) -> bool;
+
+// Candidate types from narrowest to widest
+const INFERRED_TYPES: [(&str, Fits); 9] = [
+  ("BOOL", is_bool_value),
+  ("INT4", |value| {
+    is_integer_value(value) && value.parse::<i32>().is_ok()
+  }),
+  ("INT8", |value| {
+    is_integer_value(value) && value.parse::<i64>().is_ok()
+  }),
+  ("NUMERIC", |value| DECIMAL_VALUE_RE.is_match(value)),
+  ("DOUBLE PRECISION", |value| {
+    DECIMAL_VALUE_RE.is_match(value) || FLOAT_VALUE_RE.is_match(value)
+  }),
+  ("DATE", is_date_value),
+  ("TIMESTAMP", |value| {
+    is_date_value(value)
+      || TIMESTAMP_VALUE_RE
+        .captures(value)
+        .is_some_and(|captures| is_date_value(&captures[1]))
+  }),
+  ("UUID", |value| UUID_VALUE_RE.is_match(value)),
+  ("JSONB", |value| {
+    (value.starts_with('{') || value.starts_with('['))
+      && serde_json::from_str::<serde_json::Value>(value).is_ok()
+  }),
+];
+
+// Infers the narrowest type that every one of the sampled values of a column can be read
+// as. The values leave out NULLs, which fit any type, so empty strings among them are values
+// that only TEXT reads. Columns of only NULLs default to TEXT.
+pub fn infer_type(values: &[String]) -> String {
+  if values.is_empty() {
+    return "TEXT".to_string();
   }
+  INFERRED_TYPES
+    .iter()
+    .find(|(_, fits)| values.iter().all(|value| fits(value)))
+    .map(|(sql_type, _)| sql_type.to_string())
+    .filter(|sql_type| is_valid_type(sql_type))
+    .unwrap_or_else(|| "TEXT".to_string())
 }
 
-// Resolvers
-pub fn _get_type(type_str: &str) -> Option<String> {
-  if type_str.is_empty() {
-    None
-  } else {
-    for sql_type_re in TYPES.iter() {
-      if sql_type_re.is_match(type_str) {
-        return sql_type_re
-          .captures(type_str)
-          .unwrap()
-          .get(0)
-          .map_or(None, |m| Some(String::from(m.as_str())));
-      }
-    }
-    None
-  }
+// Postgres also reads 1 and 0 as booleans, but those columns are more likely integers
+fn is_bool_value(value: &str) -> bool {
+  matches!(
+    value.to_lowercase().as_str(),
+    "true" | "false" | "t" | "f" | "yes" | "no" | "y" | "n" | "on" | "off"
+  )
+}
+
+fn is_integer_value(value: &str) -> bool {
+  INTEGER_VALUE_RE.is_match(value)
+}
+
+// ISO 8601 dates, checked against the calendar
+fn is_date_value(value: &str) -> bool {
+  let captures = match DATE_VALUE_RE.captures(value) {
+    Some(captures) => captures,
+    None => return false,
+  };
+  let year = captures[1].parse::<u32>().unwrap();
+  let month = captures[2].parse::<u32>().unwrap();
+  let day = captures[3].parse::<u32>().unwrap();
+  let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+  let days_in_month = match month {
+    1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+    4 | 6 | 9 | 11 => 30,
+    2 if is_leap_year => 29,
+    2 => 28,
+    _ => return false,
+  };
+  year > 0 && day >= 1 && day <= days_in_month
 }
 
-pub fn get_value(row: &tokio_postgres::Row, index: usize) -> String {
+// Reads the value of a column of a row. NULLs of any type are read as Value::Null, values
+// that cannot be decoded fail the query rather than pass for NULLs.
+pub fn get_value(row: &tokio_postgres::Row, index: usize) -> Result<Value, DecodeError> {
   // guaranteed that index is in bounds, so can unwrap
-  let column = row.columns().get(index).unwrap();
-  // let column_name = column.name();
-  let column_type = column.type_();
-
-  // DEBUGGING
-  // println!(
-  //   "Getting value for column ({}, {})",
-  //   column_name,
-  //   column_type.name()
-  // );
-  match column_type.name() {
-    "BOOL" | "bool" => {
-      let value: bool = row.get(index);
-      format!("{}", value)
+  let column_type = row.columns().get(index).unwrap().type_();
+  let decoded = match row.try_get::<_, Option<Raw>>(index) {
+    Ok(Some(Raw(raw))) => decode(column_type, raw),
+    Ok(None) => Ok(Value::Null),
+    Err(e) => Err(e.into()),
+  };
+  decoded.map_err(|e| format!("Cannot resolve type {}. {}", column_type.name(), e).into())
+}
+
+// Decoding
+// Values are read in the binary format postgres sends them in, and decoded by their type
+pub type DecodeError = Box<dyn std::error::Error + Sync + Send>;
+
+// The binary value of a column of any type
+struct Raw<'a>(&'a [u8]);
+
+impl<'a> FromSql<'a> for Raw<'a> {
+  fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, DecodeError> {
+    Ok(Raw(raw))
+  }
+
+  fn accepts(_: &Type) -> bool {
+    true
+  }
+}
+
+fn decode(column_type: &Type, raw: &[u8]) -> Result<Value, DecodeError> {
+  match column_type.kind() {
+    Kind::Array(member_type) => return decode_array(member_type, raw),
+    Kind::Domain(base_type) => return decode(base_type, raw),
+    Kind::Enum(_) => return Ok(Value::Text(binary::text_from_sql(raw)?.to_string())),
+    _ => (),
+  }
+  let value = match *column_type {
+    Type::BOOL => Value::Bool(binary::bool_from_sql(raw)?),
+    Type::CHAR => Value::Text((binary::char_from_sql(raw)? as u8 as char).to_string()),
+    Type::INT2 => Value::Int(binary::int2_from_sql(raw)?.into()),
+    Type::INT4 => Value::Int(binary::int4_from_sql(raw)?.into()),
+    Type::INT8 => Value::Int(binary::int8_from_sql(raw)?),
+    Type::OID | Type::XID | Type::CID | Type::REGPROC | Type::REGCLASS | Type::REGTYPE => {
+      Value::Int(binary::oid_from_sql(raw)?.into())
     }
-    "CHAR" | "char" => {
-      let value: i8 = row.get(index);
-      format!("{}", value)
+    Type::FLOAT4 => Value::Float(binary::float4_from_sql(raw)?.into()),
+    Type::FLOAT8 => Value::Float(binary::float8_from_sql(raw)?),
+    Type::NUMERIC => Value::Numeric(decode_numeric(raw)?),
+    Type::MONEY => Value::Text(format_money(binary::int8_from_sql(raw)?)),
+    Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN | Type::XML => {
+      Value::Text(binary::text_from_sql(raw)?.to_string())
     }
-    "INT2" | "int2" => {
-      let value: i16 = row.get(index);
-      format!("{}", value)
+    Type::BYTEA => Value::Bytes(binary::bytea_from_sql(raw).to_vec()),
+    Type::DATE => Value::Date(format_date(binary::date_from_sql(raw)?)),
+    Type::TIME => Value::Text(format_time(binary::time_from_sql(raw)?)),
+    Type::TIMETZ => {
+      let mut raw = raw;
+      let time = format_time(raw.read_i64::<BigEndian>()?);
+      // Postgres counts offsets west of UTC as positive
+      let offset = raw.read_i32::<BigEndian>()?;
+      Value::Text(format!("{}{}", time, format_offset(-offset)))
     }
-    "INT4" | "int4" => {
-      let value: i32 = row.get(index);
-      format!("{}", value)
+    Type::TIMESTAMP => Value::Timestamp(format_timestamp(binary::timestamp_from_sql(raw)?)),
+    Type::TIMESTAMPTZ => {
+      let timestamp = binary::timestamp_from_sql(raw)?;
+      match timestamp {
+        i64::MAX | i64::MIN => Value::Timestamp(format_timestamp(timestamp)),
+        _ => Value::Timestamp(format!("{}+00:00", format_timestamp(timestamp))),
+      }
     }
-    "INT8" | "int8" => {
-      let value: i64 = row.get(index);
-      format!("{}", value)
+    Type::INTERVAL => Value::Text(decode_interval(raw)?),
+    Type::UUID => Value::Text(format_uuid(&binary::uuid_from_sql(raw)?)),
+    Type::JSON => Value::Json(serde_json::from_slice(raw)?),
+    Type::JSONB => match raw.split_first() {
+      Some((1, json)) => Value::Json(serde_json::from_slice(json)?),
+      _ => return Err("unsupported jsonb version".into()),
+    },
+    Type::INET | Type::CIDR => {
+      let inet = binary::inet_from_sql(raw)?;
+      let full_netmask = if inet.addr().is_ipv4() { 32 } else { 128 };
+      if *column_type == Type::INET && inet.netmask() == full_netmask {
+        Value::Text(inet.addr().to_string())
+      } else {
+        Value::Text(format!("{}/{}", inet.addr(), inet.netmask()))
+      }
     }
-    "OID" | "oid" => {
-      let value: u32 = row.get(index);
-      format!("{}", value)
+    Type::MACADDR | Type::MACADDR8 => Value::Text(
+      raw
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(":"),
+    ),
+    Type::BIT | Type::VARBIT => {
+      let varbit = binary::varbit_from_sql(raw)?;
+      let bits = (0..varbit.len())
+        .map(|bit| match varbit.bytes()[bit / 8] & (0x80 >> (bit % 8)) {
+          0 => '0',
+          _ => '1',
+        })
+        .collect();
+      Value::Text(bits)
     }
-    "FLOAT4" | "float4" => {
-      let value: f32 = row.get(index);
-      format!("{}", value)
+    Type::POINT => {
+      let point = binary::point_from_sql(raw)?;
+      Value::Text(format_point(point.x(), point.y()))
     }
-    "FLOAT8" | "float8" => {
-      let value: f64 = row.get(index);
-      format!("{}", value)
+    Type::BOX => {
+      let rectangle = binary::box_from_sql(raw)?;
+      Value::Text(format!(
+        "{},{}",
+        format_point(rectangle.upper_right().x(), rectangle.upper_right().y()),
+        format_point(rectangle.lower_left().x(), rectangle.lower_left().y())
+      ))
     }
-    "VARCHAR" | "TEXT" | "NAME" | "UNKNOWN" | "varchar" | "text" | "name" | "unknown" => {
-      let value: String = row.get(index);
-      format!("{}", value)
+    Type::LSEG => match read_floats(raw, 4)?.as_slice() {
+      [x1, y1, x2, y2] => Value::Text(format!(
+        "[{},{}]",
+        format_point(*x1, *y1),
+        format_point(*x2, *y2)
+      )),
+      _ => unreachable!(),
+    },
+    Type::LINE => match read_floats(raw, 3)?.as_slice() {
+      [a, b, c] => Value::Text(format!("{{{},{},{}}}", a, b, c)),
+      _ => unreachable!(),
+    },
+    Type::CIRCLE => match read_floats(raw, 3)?.as_slice() {
+      [x, y, radius] => Value::Text(format!("<{},{}>", format_point(*x, *y), radius)),
+      _ => unreachable!(),
+    },
+    Type::PATH => {
+      let path = binary::path_from_sql(raw)?;
+      let points = path
+        .points()
+        .map(|point| Ok(format_point(point.x(), point.y())))
+        .collect::<Vec<_>>()?
+        .join(",");
+      if path.closed() {
+        Value::Text(format!("({})", points))
+      } else {
+        Value::Text(format!("[{}]", points))
+      }
     }
-    "BYTEA" | "bytea" => {
-      let value: &[u8] = row.get(index);
-      format!("{:?}", value)
+    Type::POLYGON => {
+      let mut raw = raw;
+      let count = raw.read_i32::<BigEndian>()?;
+      let points = read_floats(raw, count.max(0) as usize * 2)?
+        .chunks(2)
+        .map(|point| format_point(point[0], point[1]))
+        .collect::<Vec<_>>()
+        .join(",");
+      Value::Text(format!("({})", points))
     }
-    "TIME" | "time" => {
-      let value: std::time::SystemTime = row.get(index);
-      format!("{:?}", value)
+    Type::PG_LSN => {
+      let lsn = { raw }.read_u64::<BigEndian>()?;
+      Value::Text(format!("{:X}/{:X}", lsn >> 32, lsn & 0xFFFF_FFFF))
     }
-    "TIMESTAMP" | "timestamp" => {
-      let value: std::time::SystemTime = row.get(index);
-      format!("{:?}", value)
+    // Extension types have no fixed oid, so they are known by name
+    _ if column_type.name() == "citext" => Value::Text(binary::text_from_sql(raw)?.to_string()),
+    _ if column_type.name() == "hstore" => {
+      let entries = binary::hstore_from_sql(raw)?
+        .map(|(key, value)| {
+          Ok(match value {
+            Some(value) => format!("{:?}=>{:?}", key, value),
+            None => format!("{:?}=>NULL", key),
+          })
+        })
+        .collect::<Vec<_>>()?;
+      Value::Text(entries.join(", "))
     }
-    "INET" | "inet" => {
-      let value: std::net::IpAddr = row.get(index);
-      format!("{}", value)
+    _ => return Err("Type is not supported.".into()),
+  };
+  Ok(value)
+}
+
+// Decodes the elements of an array of any dimension, e.g. {{1,2},{3,4}}
+fn decode_array(member_type: &Type, raw: &[u8]) -> Result<Value, DecodeError> {
+  let array = binary::array_from_sql(raw)?;
+  let lengths = array
+    .dimensions()
+    .map(|dimension| Ok(dimension.len as usize))
+    .collect::<Vec<_>>()?;
+  let elements = array
+    .values()
+    .map(|element| match element {
+      Some(raw) => decode(member_type, raw),
+      None => Ok(Value::Null),
+    })
+    .collect::<Vec<_>>()?;
+  Ok(nest_array(elements, &lengths))
+}
+
+// Groups the elements of a flattened array by the lengths of its inner dimensions
+fn nest_array(elements: Vec<Value>, lengths: &[usize]) -> Value {
+  match lengths {
+    [] | [_] => Value::Array(elements),
+    [_, inner_lengths @ ..] => {
+      let chunk_size = inner_lengths.iter().product::<usize>().max(1);
+      let mut elements = elements.into_iter();
+      let mut arrays = Vec::new();
+      loop {
+        let chunk = elements.by_ref().take(chunk_size).collect::<Vec<_>>();
+        if chunk.is_empty() {
+          break;
+        }
+        arrays.push(nest_array(chunk, inner_lengths));
+      }
+      Value::Array(arrays)
+    }
+  }
+}
+
+// Numerics are sent as base 10000 digits, with the weight of the first digit, a sign and
+// the number of decimal digits to display
+fn decode_numeric(mut raw: &[u8]) -> Result<String, DecodeError> {
+  let count = raw.read_i16::<BigEndian>()?;
+  let weight = raw.read_i16::<BigEndian>()? as i32;
+  let sign = raw.read_u16::<BigEndian>()?;
+  let scale = raw.read_u16::<BigEndian>()? as usize;
+  let mut digits = Vec::new();
+  for _ in 0..count {
+    digits.push(raw.read_i16::<BigEndian>()?);
+  }
+  let digit = |index: i32| {
+    if index >= 0 {
+      digits.get(index as usize).copied().unwrap_or(0)
+    } else {
+      0
+    }
+  };
+
+  let mut numeric = match sign {
+    0x0000 => String::new(),
+    0x4000 => "-".to_string(),
+    0xC000 => return Ok("NaN".to_string()),
+    0xD000 => return Ok("Infinity".to_string()),
+    0xF000 => return Ok("-Infinity".to_string()),
+    _ => return Err("invalid numeric sign".into()),
+  };
+  if weight < 0 {
+    numeric.push('0');
+  } else {
+    numeric.push_str(&digit(0).to_string());
+    for index in 1..=weight {
+      numeric.push_str(&format!("{:04}", digit(index)));
+    }
+  }
+  if scale > 0 {
+    let fraction = (0..(scale as i32 + 3) / 4)
+      .map(|index| format!("{:04}", digit(weight + 1 + index)))
+      .collect::<String>();
+    numeric.push('.');
+    numeric.push_str(&fraction[..scale]);
+  }
+  Ok(numeric)
+}
+
+// Intervals are sent as microseconds, days and months, which are kept apart since their
+// lengths vary. Formatted like postgres does, e.g. 1 year 2 mons 3 days 04:05:06
+fn decode_interval(mut raw: &[u8]) -> Result<String, DecodeError> {
+  let microseconds = raw.read_i64::<BigEndian>()?;
+  let days = raw.read_i32::<BigEndian>()?;
+  let months = raw.read_i32::<BigEndian>()?;
+  // Parts are formatted as postgres outputs them: a positive part after a negative one is
+  // signed explicitly, and a unit is plural unless its count is exactly 1
+  let mut parts = Vec::new();
+  let mut is_after_negative = false;
+  for &(count, unit) in [(months / 12, "year"), (months % 12, "mon"), (days, "day")].iter() {
+    if count != 0 {
+      let sign = if is_after_negative && count > 0 {
+        "+"
+      } else {
+        ""
+      };
+      let plural = if count == 1 { "" } else { "s" };
+      parts.push(format!("{}{} {}{}", sign, count, unit, plural));
+      is_after_negative = count < 0;
+    }
+  }
+  if microseconds != 0 || parts.is_empty() {
+    let sign = if microseconds < 0 {
+      "-"
+    } else if is_after_negative {
+      "+"
+    } else {
+      ""
+    };
+    let microseconds = microseconds.unsigned_abs();
+    let hours = microseconds / 3_600_000_000;
+    parts.push(format!(
+      "{}{:02}:{}",
+      sign,
+      hours,
+      &format_time((microseconds % 3_600_000_000) as i64)[3..]
+    ));
+  }
+  Ok(parts.join(" "))
+}
+
+fn read_floats(mut raw: &[u8], count: usize) -> Result<Vec<f64>, DecodeError> {
+  let mut floats = Vec::new();
+  for _ in 0..count {
+    floats.push(raw.read_f64::<BigEndian>()?);
+  }
+  Ok(floats)
+}
+
+fn format_point(x: f64, y: f64) -> String {
+  format!("({},{})", x, y)
+}
+
+fn format_uuid(bytes: &[u8; 16]) -> String {
+  let hex = bytes
+    .iter()
+    .map(|byte| format!("{:02x}", byte))
+    .collect::<String>();
+  format!(
+    "{}-{}-{}-{}-{}",
+    &hex[..8],
+    &hex[8..12],
+    &hex[12..16],
+    &hex[16..20],
+    &hex[20..]
+  )
+}
+
+// Money is sent as cents, e.g. -123456 is -$1,234.56
+fn format_money(cents: i64) -> String {
+  let sign = if cents < 0 { "-" } else { "" };
+  let cents = cents.unsigned_abs();
+  let dollars = (cents / 100).to_string();
+  // Digits are grouped in threes from the right, so the first group takes the remainder
+  let first_group = dollars.len() % 3;
+  let mut grouped = String::new();
+  for (index, digit) in dollars.chars().enumerate() {
+    if index > 0 && index % 3 == first_group {
+      grouped.push(',');
+    }
+    grouped.push(digit);
+  }
+  format!("{}${}.{:02}", sign, grouped, cents % 100)
+}
+
+// Dates are sent as days since 2000-01-01, formatted as ISO 8601 dates
+fn format_date(days: i32) -> String {
+  match days {
+    i32::MAX => "infinity".to_string(),
+    i32::MIN => "-infinity".to_string(),
+    _ => {
+      let (year, month, day) = civil_from_days(days as i64);
+      // There is no year 0, the year before 1 AD is 1 BC
+      if year > 0 {
+        format!("{:04}-{:02}-{:02}", year, month, day)
+      } else {
+        format!("{:04}-{:02}-{:02} BC", 1 - year, month, day)
+      }
     }
-    // TODO: support HashMap<String, Option<String>> HSTORE type
-    // TODO: cover all postgresql types
-    // Types can be found here: https://docs.rs/tokio-postgres/0.7.0/tokio_postgres/types/struct.Type.html
-    // FromSQL common types here: https://docs.rs/tokio-postgres/0.7.0/tokio_postgres/types/trait.FromSql.html#tymethod.from_sql
+  }
+}
+
+// Times are sent as microseconds since midnight. Fractional seconds are only shown if any.
+fn format_time(microseconds: i64) -> String {
+  let seconds = microseconds / 1_000_000;
+  let time = format!(
+    "{:02}:{:02}:{:02}",
+    seconds / 3600,
+    seconds / 60 % 60,
+    seconds % 60
+  );
+  match microseconds % 1_000_000 {
+    0 => time,
+    fraction => format!(
+      "{}.{}",
+      time,
+      format!("{:06}", fraction).trim_end_matches('0')
+    ),
+  }
+}
+
+// Timestamps are sent as microseconds since 2000-01-01 00:00:00, formatted as ISO 8601
+fn format_timestamp(microseconds: i64) -> String {
+  match microseconds {
+    i64::MAX => "infinity".to_string(),
+    i64::MIN => "-infinity".to_string(),
     _ => {
-      // could panic!
-      println!("Cannot resolve type {}. Sorry!", column_type.name());
-      "".to_string()
+      let days = microseconds.div_euclid(86_400_000_000);
+      let time = format_time(microseconds.rem_euclid(86_400_000_000));
+      let date = format_date(days as i32);
+      match date.strip_suffix(" BC") {
+        Some(date) => format!("{} {} BC", date, time),
+        None => format!("{} {}", date, time),
+      }
     }
   }
 }
+
+// Formats an offset east of UTC in seconds, e.g. +05:30
+fn format_offset(seconds: i32) -> String {
+  let sign = if seconds < 0 { '-' } else { '+' };
+  let seconds = seconds.abs();
+  format!("{}{:02}:{:02}", sign, seconds / 3600, seconds / 60 % 60)
+}
+
+// Converts days since 2000-01-01 into a proleptic gregorian (year, month, day)
+// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+  let days = days + 10957 + 719_468; // Shifted to days since 0000-03-01
+  let era = days.div_euclid(146_097);
+  let day_of_era = days.rem_euclid(146_097);
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let month_index = (5 * day_of_year + 2) / 153;
+  let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+  let month = if month_index < 10 {
+    month_index + 3
+  } else {
+    month_index - 9
+  } as u32;
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+  (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn interval(microseconds: i64, days: i32, months: i32) -> String {
+    let mut raw = Vec::new();
+    raw.extend_from_slice(&microseconds.to_be_bytes());
+    raw.extend_from_slice(&days.to_be_bytes());
+    raw.extend_from_slice(&months.to_be_bytes());
+    decode_interval(&raw).unwrap()
+  }
+
+  #[test]
+  fn decodes_intervals() {
+    assert_eq!(interval(0, 0, 0), "00:00:00");
+    assert_eq!(interval(0, 1, 0), "1 day");
+    assert_eq!(interval(0, 0, -10), "-10 mons");
+    assert_eq!(interval(90_500_000, 0, 13), "1 year 1 mon 00:01:30.5");
+    assert_eq!(interval(-1_500_000, 0, 0), "-00:00:01.5");
+  }
+
+  #[test]
+  fn decodes_intervals_of_mixed_signs() {
+    assert_eq!(interval(0, -1, 0), "-1 days");
+    assert_eq!(interval(7_200_000_000, -1, 0), "-1 days +02:00:00");
+    assert_eq!(interval(-7_200_000_000, 1, 0), "1 day -02:00:00");
+    assert_eq!(interval(0, 5, -14), "-1 years -2 mons +5 days");
+    assert_eq!(
+      interval(-10_800_000_000, 1, -12),
+      "-1 years +1 day -03:00:00"
+    );
+    assert_eq!(interval(0, 0, 23), "1 year 11 mons");
+  }
+
+  #[test]
+  fn groups_the_digits_of_money() {
+    assert_eq!(format_money(0), "$0.00");
+    assert_eq!(format_money(99_999), "$999.99");
+    assert_eq!(format_money(100_000), "$1,000.00");
+    assert_eq!(format_money(-123_456_789), "-$1,234,567.89");
+    assert_eq!(format_money(1_234_567_800), "$12,345,678.00");
+  }
+}
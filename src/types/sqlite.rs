@@ -0,0 +1,121 @@
+use super::annotation;
+use super::value::Value;
+use rusqlite::types::{Value as SqlValue, ValueRef};
+
+// SQLite has no column types, only affinities that decide how a value is stored. Annotated
+// types are mapped to the affinity closest to them.
+// | Affinity | Types                                               |
+// |----------|-----------------------------------------------------|
+// | INTEGER  | SMALLINT, INTEGER, BIGINT, SERIALs, BOOLEAN, OID    |
+// | REAL     | REAL, DOUBLE PRECISION                              |
+// | NUMERIC  | NUMERIC, MONEY                                      |
+// | BLOB     | BYTEA                                               |
+// | TEXT     | Anything else, e.g. dates, json and arrays as text |
+pub fn to_affinity(column_type: &str) -> &'static str {
+  let column_type = annotation::parse_type(column_type).unwrap_or_else(|_| column_type.to_string());
+  if column_type.ends_with(']') {
+    return "TEXT";
+  }
+  match column_type.split('(').next().unwrap_or("") {
+    "SMALLINT" | "INTEGER" | "BIGINT" | "SMALLSERIAL" | "SERIAL" | "BIGSERIAL" | "BOOLEAN"
+    | "OID" => "INTEGER",
+    "REAL" | "DOUBLE PRECISION" => "REAL",
+    "NUMERIC" | "MONEY" => "NUMERIC",
+    "BYTEA" => "BLOB",
+    _ => "TEXT",
+  }
+}
+
+// Converts a csv value into the value stored in a column of the given type. Values that do
+// not fit the affinity of their column are stored as text, as SQLite would.
+pub fn to_sql_value(column_type: &str, value: &str) -> SqlValue {
+  let is_boolean =
+    annotation::parse_type(column_type).is_ok_and(|column_type| column_type == "BOOLEAN");
+  let text = || SqlValue::Text(value.to_string());
+  match to_affinity(column_type) {
+    "INTEGER" if is_boolean => match value.trim().to_lowercase().as_str() {
+      "true" | "t" | "yes" | "y" | "on" | "1" => SqlValue::Integer(1),
+      "false" | "f" | "no" | "n" | "off" | "0" => SqlValue::Integer(0),
+      _ => text(),
+    },
+    "INTEGER" => value
+      .trim()
+      .parse::<i64>()
+      .map_or_else(|_| text(), SqlValue::Integer),
+    "REAL" => value
+      .trim()
+      .parse::<f64>()
+      .map_or_else(|_| text(), SqlValue::Real),
+    "NUMERIC" => match (value.trim().parse::<i64>(), value.trim().parse::<f64>()) {
+      (Ok(integer), _) => SqlValue::Integer(integer),
+      (_, Ok(real)) => SqlValue::Real(real),
+      _ => text(),
+    },
+    // Bytes are written in the hex format postgres outputs bytea in, e.g. \x0aff
+    "BLOB" => match value.strip_prefix("\\x") {
+      Some(hex) if hex.len() % 2 == 0 => (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_or_else(|_| text(), SqlValue::Blob),
+      _ => text(),
+    },
+    _ => text(),
+  }
+}
+
+// Reads a value of a query result. Every value carries its own storage class.
+pub fn get_value(value: ValueRef) -> Value {
+  match value {
+    ValueRef::Null => Value::Null,
+    ValueRef::Integer(integer) => Value::Int(integer),
+    ValueRef::Real(real) => Value::Float(real),
+    ValueRef::Text(text) => Value::Text(String::from_utf8_lossy(text).into_owned()),
+    ValueRef::Blob(bytes) => Value::Bytes(bytes.to_vec()),
+  }
+}
+
+// Type of a result column, named like the postgres type closest to it, so that exported
+// annotations can be imported into either database. Columns without a declared type, e.g.
+// expressions, are named after the storage class of their first value.
+pub fn result_type(declared_type: Option<&str>, value: Option<&Value>) -> String {
+  let affinity = match declared_type {
+    Some(declared_type) => declared_affinity(declared_type),
+    None => match value {
+      Some(Value::Int(_)) => "INTEGER",
+      Some(Value::Float(_)) => "REAL",
+      Some(Value::Bytes(_)) => "BLOB",
+      _ => "TEXT",
+    },
+  };
+  match affinity {
+    "INTEGER" => "BIGINT",
+    "REAL" => "DOUBLE PRECISION",
+    "NUMERIC" => "NUMERIC",
+    "BLOB" => "BYTEA",
+    _ => "TEXT",
+  }
+  .to_string()
+}
+
+// Affinity SQLite gives a declared column type, following its rules in order
+fn declared_affinity(declared_type: &str) -> &'static str {
+  let declared_type = declared_type.to_uppercase();
+  if declared_type.contains("INT") {
+    "INTEGER"
+  } else if ["CHAR", "CLOB", "TEXT"]
+    .iter()
+    .any(|name| declared_type.contains(name))
+  {
+    "TEXT"
+  } else if declared_type.contains("BLOB") || declared_type.is_empty() {
+    "BLOB"
+  } else if ["REAL", "FLOA", "DOUB"]
+    .iter()
+    .any(|name| declared_type.contains(name))
+  {
+    "REAL"
+  } else {
+    "NUMERIC"
+  }
+}
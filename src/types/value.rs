@@ -0,0 +1,139 @@
+use std::fmt;
+
+// A single typed cell of a table. Values keep the type they were read as, so that
+// displaying and exporting them does not have to guess it back from text.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Null,
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  Numeric(String), // Arbitrary precision decimal, kept as its exact digits
+  Text(String),
+  Bytes(Vec<u8>),
+  Date(String),      // ISO 8601 date, e.g. 2020-01-31
+  Timestamp(String), // ISO 8601 timestamp, e.g. 2020-01-31 12:30:00
+  Json(serde_json::Value),
+  Array(Vec<Value>),
+}
+
+impl Value {
+  pub fn is_null(&self) -> bool {
+    *self == Value::Null
+  }
+
+  // Number of characters the value takes up when displayed
+  pub fn width(&self) -> usize {
+    self.to_string().chars().count()
+  }
+
+  // Converts the value into json. Numbers and booleans stay unquoted, json is nested and
+  // everything else becomes a string of its displayed form.
+  pub fn to_json(&self) -> serde_json::Value {
+    use serde_json::Value as Json;
+    match self {
+      Value::Null => Json::Null,
+      Value::Bool(value) => Json::Bool(*value),
+      Value::Int(value) => Json::from(*value),
+      Value::Float(value) => match serde_json::Number::from_f64(*value) {
+        Some(number) => Json::Number(number),
+        None => Json::String(self.to_string()), // NaN and Infinity have no json number
+      },
+      // serde_json keeps the digits of numbers as they are, see its arbitrary_precision feature
+      Value::Numeric(digits) => match serde_json::from_str::<serde_json::Number>(digits) {
+        Ok(number) => Json::Number(number),
+        Err(_) => Json::String(digits.clone()),
+      },
+      Value::Json(value) => value.clone(),
+      Value::Array(values) => Json::Array(values.iter().map(Value::to_json).collect()),
+      _ => Json::String(self.to_string()),
+    }
+  }
+}
+
+impl fmt::Display for Value {
+  fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Value::Null => Ok(()),
+      Value::Bool(value) => write!(formatter, "{}", value),
+      Value::Int(value) => write!(formatter, "{}", value),
+      Value::Float(value) if value.is_infinite() && *value > 0.0 => write!(formatter, "Infinity"),
+      Value::Float(value) if value.is_infinite() => write!(formatter, "-Infinity"),
+      Value::Float(value) => write!(formatter, "{}", value),
+      Value::Numeric(digits) => write!(formatter, "{}", digits),
+      Value::Text(text) | Value::Date(text) | Value::Timestamp(text) => {
+        write!(formatter, "{}", text)
+      }
+      // Same hex format postgres outputs bytea in
+      Value::Bytes(bytes) => {
+        write!(formatter, "\\x")?;
+        bytes
+          .iter()
+          .try_for_each(|byte| write!(formatter, "{:02x}", byte))
+      }
+      Value::Json(value) => write!(formatter, "{}", value),
+      Value::Array(values) => {
+        let elements = values
+          .iter()
+          .map(to_array_element)
+          .collect::<Vec<_>>()
+          .join(",");
+        write!(formatter, "{{{}}}", elements)
+      }
+    }
+  }
+}
+
+// Formats an element of an array literal, e.g. {1,NULL,"a b"}. Elements that are empty,
+// spell NULL or hold special characters are quoted, with quotes and backslashes escaped.
+fn to_array_element(value: &Value) -> String {
+  if value.is_null() {
+    return "NULL".to_string();
+  }
+  let element = value.to_string();
+  let needs_quotes = match value {
+    Value::Array(_) => false,
+    _ => {
+      element.is_empty()
+        || element.eq_ignore_ascii_case("null")
+        || element
+          .chars()
+          .any(|c| c.is_whitespace() || "{},\"\\".contains(c))
+    }
+  };
+  if needs_quotes {
+    format!("\"{}\"", element.replace('\\', "\\\\").replace('"', "\\\""))
+  } else {
+    element
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn numeric_json_keeps_exact_digits() {
+    for digits in ["12345678901234567890.123456789", "123.4500", "-0.000001"].iter() {
+      let json = Value::Numeric(digits.to_string()).to_json();
+      assert_eq!(json.to_string(), *digits);
+    }
+  }
+
+  #[test]
+  fn numeric_json_falls_back_to_string() {
+    assert_eq!(
+      Value::Numeric("NaN".to_string()).to_json(),
+      serde_json::Value::String("NaN".to_string())
+    );
+  }
+
+  #[test]
+  fn float_json() {
+    assert_eq!(Value::Float(1.5).to_json().to_string(), "1.5");
+    assert_eq!(
+      Value::Float(f64::INFINITY).to_json().to_string(),
+      "\"Infinity\""
+    );
+  }
+}
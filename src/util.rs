@@ -1,4 +1,5 @@
 pub mod evict;
 pub mod format;
 pub mod less;
+pub mod sql;
 pub mod validate;
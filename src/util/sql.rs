@@ -0,0 +1,202 @@
+use std::ops::Range;
+
+// Splits a sql script into its individual statements.
+// Semicolons inside of quoted strings, quoted identifiers, dollar quoted bodies
+// and comments do not terminate a statement. Statements made up of only
+// whitespace and comments are dropped.
+pub fn split_statements(script: &str) -> Vec<String> {
+  let chars = script.chars().collect::<Vec<char>>();
+  let mut statements = Vec::new();
+  let mut statement = String::new();
+  let mut has_content = false;
+  let mut i = 0;
+  while i < chars.len() {
+    let c = chars[i];
+    match c {
+      ';' => {
+        if has_content {
+          statements.push(statement.trim().to_string());
+        }
+        statement.clear();
+        has_content = false;
+        i += 1;
+      }
+      '-' if chars.get(i + 1) == Some(&'-') => {
+        // Line comment, skip until the end of the line
+        while i < chars.len() && chars[i] != '\n' {
+          i += 1;
+        }
+      }
+      '/' if chars.get(i + 1) == Some(&'*') => {
+        // Block comment, skip until the closing */
+        i += 2;
+        while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+          i += 1;
+        }
+        i += 2;
+        statement.push(' ');
+      }
+      '\'' | '"' => {
+        // Quoted string or identifier, doubled quotes are escapes
+        let end = find_closing_quote(&chars, i + 1, c);
+        statement.extend(&chars[i..end]);
+        has_content = true;
+        i = end;
+      }
+      '$' => match dollar_tag(&chars, i) {
+        Some(tag) => {
+          let body_start = i + tag.len();
+          let end = find_dollar_tag(&chars, body_start, &tag)
+            .map_or(chars.len(), |tag_start| tag_start + tag.len());
+          statement.extend(&chars[i..end]);
+          has_content = true;
+          i = end;
+        }
+        None => {
+          statement.push(c);
+          has_content = true;
+          i += 1;
+        }
+      },
+      _ => {
+        statement.push(c);
+        has_content = has_content || !c.is_whitespace();
+        i += 1;
+      }
+    }
+  }
+  if has_content {
+    statements.push(statement.trim().to_string());
+  }
+  statements
+}
+
+// Byte ranges of the quoted strings, quoted identifiers, dollar quoted bodies and comments of
+// a sql script, inside of which nothing is sql of its own
+pub fn literal_ranges(script: &str) -> Vec<Range<usize>> {
+  let (offsets, chars): (Vec<usize>, Vec<char>) = script.char_indices().unzip();
+  let offset = |i: usize| offsets.get(i).copied().unwrap_or(script.len());
+  let mut ranges = Vec::new();
+  let mut i = 0;
+  while i < chars.len() {
+    let end = match chars[i] {
+      '-' if chars.get(i + 1) == Some(&'-') => (i..chars.len())
+        .find(|&j| chars[j] == '\n')
+        .unwrap_or(chars.len()),
+      '/' if chars.get(i + 1) == Some(&'*') => (i + 2..chars.len())
+        .find(|&j| chars[j] == '*' && chars.get(j + 1) == Some(&'/'))
+        .map_or(chars.len(), |j| j + 2),
+      '\'' | '"' => find_closing_quote(&chars, i + 1, chars[i]),
+      '$' if dollar_tag(&chars, i).is_some() => {
+        let tag = dollar_tag(&chars, i).unwrap();
+        let tag_length = tag.chars().count();
+        find_dollar_tag(&chars, i + tag_length, &tag)
+          .map_or(chars.len(), |tag_start| tag_start + tag_length)
+      }
+      _ => {
+        i += 1;
+        continue;
+      }
+    };
+    ranges.push(offset(i)..offset(end));
+    i = end;
+  }
+  ranges
+}
+
+// Returns the index just past the closing quote, or the end of input if unterminated
+fn find_closing_quote(chars: &[char], start: usize, quote: char) -> usize {
+  let mut i = start;
+  while i < chars.len() {
+    if chars[i] == quote {
+      if chars.get(i + 1) == Some(&quote) {
+        i += 2;
+        continue;
+      }
+      return i + 1;
+    }
+    i += 1;
+  }
+  chars.len()
+}
+
+// Matches a dollar quote tag such as $$ or $body$ starting at index start.
+// Tags cannot start with a digit, so positional parameters like $1 are not tags.
+fn dollar_tag(chars: &[char], start: usize) -> Option<String> {
+  let mut i = start + 1;
+  while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+    i += 1;
+  }
+  if i < chars.len() && chars[i] == '$' && !chars[start + 1].is_numeric() {
+    Some(chars[start..=i].iter().collect())
+  } else {
+    None
+  }
+}
+
+fn find_dollar_tag(chars: &[char], start: usize, tag: &str) -> Option<usize> {
+  let tag = tag.chars().collect::<Vec<char>>();
+  (start..chars.len()).find(|&i| chars[i..].starts_with(&tag))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn splits_on_semicolons() {
+    assert_eq!(
+      split_statements("SELECT 1; SELECT 2;\nSELECT 3"),
+      vec!["SELECT 1", "SELECT 2", "SELECT 3"]
+    );
+  }
+
+  #[test]
+  fn keeps_semicolons_in_quotes() {
+    assert_eq!(
+      split_statements(r#"SELECT 'a;b', "c;d" FROM t; SELECT 'it''s;'"#),
+      vec![r#"SELECT 'a;b', "c;d" FROM t"#, "SELECT 'it''s;'"]
+    );
+  }
+
+  #[test]
+  fn keeps_semicolons_in_dollar_quotes() {
+    let script =
+      "CREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql; SELECT $1";
+    assert_eq!(
+      split_statements(script),
+      vec![
+        "CREATE FUNCTION f() RETURNS int AS $body$ SELECT 1; $body$ LANGUAGE sql",
+        "SELECT $1"
+      ]
+    );
+  }
+
+  #[test]
+  fn drops_comments_and_empty_statements() {
+    assert_eq!(
+      split_statements("-- a; comment\nSELECT /* b; */ 1;;\n-- only a comment;\n"),
+      vec!["SELECT   1"]
+    );
+  }
+
+  #[test]
+  fn finds_literals_and_comments() {
+    let script = "SELECT 'a', \"b\" -- c\nFROM t /* d */ WHERE x = $$e$$";
+    let literals = literal_ranges(script)
+      .into_iter()
+      .map(|range| &script[range])
+      .collect::<Vec<_>>();
+    assert_eq!(literals, vec!["'a'", "\"b\"", "-- c", "/* d */", "$$e$$"]);
+  }
+
+  #[test]
+  fn finds_unterminated_literals() {
+    let script = "SELECT 'é' /* open";
+    let literals = literal_ranges(script)
+      .into_iter()
+      .map(|range| &script[range])
+      .collect::<Vec<_>>();
+    assert_eq!(literals, vec!["'é'", "/* open"]);
+  }
+}